@@ -31,7 +31,9 @@ trap '{
 # create streams and KV
 nats stream add --config nats/streams/PublicStashStream.json
 nats stream add --config nats/streams/PublicStashChangeIds.json
+nats stream add --config nats/streams/FailedStashes.json
 nats kv add ratelimiter
+nats kv add stash_state
 
 # create consumers
 nats consumer add --config nats/consumers/RiverCrawler.json PublicStashChangeIds
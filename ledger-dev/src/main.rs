@@ -0,0 +1,137 @@
+mod memory;
+mod mock;
+
+use std::{env, path::PathBuf, sync::Arc, time::Duration};
+
+use memory::MemoryLedger;
+use mock::{dev_change_id, MockApi, UnlimitedRateLimiter};
+use poe_types::stash::PublicStashChange;
+use price_history_api::{db::PriceHistoryStore, router, AppState};
+use river_crawler::{crawler::Crawler, filter::StashFilter, telemetry::Metrics};
+use stash_processor::{
+    lifecycle::MemoryStashStateStore, processor::StashProcessor, sink::ListingSink,
+};
+use time::OffsetDateTime;
+use tokio::{net::TcpListener, sync::mpsc};
+use tracing_subscriber::{layer::SubscriberExt, EnvFilter, Registry};
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    setup_logger();
+
+    let fixtures_dir = env::var("FIXTURES_DIR").unwrap_or("poe-api-client/test".to_owned());
+    let mock_port = env::var("MOCK_API_PORT")
+        .unwrap_or("3001".to_owned())
+        .parse::<u16>()
+        .expect("MOCK_API_PORT must be a valid 16bit port number");
+    let page_interval = env::var("PAGE_INTERVAL_MS")
+        .unwrap_or("1000".to_owned())
+        .parse::<u64>()
+        .map(Duration::from_millis)
+        .expect("PAGE_INTERVAL_MS must be a number of milliseconds");
+
+    let mock_api = MockApi::load(&PathBuf::from(fixtures_dir))?;
+    tokio::spawn(async move {
+        if let Err(e) = mock_api.serve(mock_port).await {
+            tracing::error!("mock API stopped with error: {e}");
+        }
+    });
+
+    let (listing_store, history_store) = storage().await?;
+
+    let mock_url = format!("http://127.0.0.1:{mock_port}");
+    let mut poe_client = poe_api_client::Client::new("ledger-dev", UnlimitedRateLimiter)?
+        .with_base_urls(&mock_url, &mock_url);
+    poe_client.authorize("ledger-dev", "ledger-dev").await?;
+
+    let (stash_tx, mut stash_rx) = mpsc::channel::<Vec<PublicStashChange>>(16);
+
+    let metrics = Arc::new(Metrics::default());
+    let mut crawler = Crawler::new(poe_client, StashFilter::from_env(), metrics);
+    tokio::spawn(async move {
+        let mut change_id = dev_change_id(0);
+        let mut interval = tokio::time::interval(page_interval);
+
+        loop {
+            interval.tick().await;
+
+            match crawler.crawl(&change_id).await {
+                Ok(page) => {
+                    if stash_tx.send(page.stashes).await.is_err() {
+                        tracing::error!("processor stopped, stopping the crawler");
+                        break;
+                    }
+
+                    change_id = page.next_change_id;
+                }
+                Err(e) => {
+                    tracing::error!(
+                        "failed getting public stashes for change_id: {change_id} with error: {e}"
+                    );
+                }
+            }
+        }
+    });
+
+    let mut processor = StashProcessor::from_env(MemoryStashStateStore::default());
+    tokio::spawn(async move {
+        while let Some(stashes) = stash_rx.recv().await {
+            let page = match processor.process(stashes, OffsetDateTime::now_utc()).await {
+                Ok(page) => page,
+                Err(e) => {
+                    tracing::error!("{e:#}");
+                    continue;
+                }
+            };
+
+            let listings = &page.extracted.listings;
+            match processor
+                .write(listing_store.as_ref(), listings, &page.events)
+                .await
+            {
+                Ok(_) => tracing::info!("stored {} listings", listings.len()),
+                Err(e) => tracing::error!("{e:#}"),
+            }
+        }
+    });
+
+    let port = env::var("PORT")
+        .unwrap_or("3000".to_owned())
+        .parse::<u16>()
+        .expect("PORT must be a valid 16bit port number");
+    let listener = TcpListener::bind(format!("127.0.0.1:{port}")).await?;
+
+    tracing::info!("ledger-dev is up, price history is served on port {port}");
+    axum::serve(listener, router(AppState::new(history_store))).await?;
+
+    Ok(())
+}
+
+/// Picks the storage backend from `LEDGER_DEV_STORAGE`, either `memory` (default) or `clickhouse`
+async fn storage() -> anyhow::Result<(Arc<dyn ListingSink>, Arc<dyn PriceHistoryStore>)> {
+    let backend = env::var("LEDGER_DEV_STORAGE").unwrap_or("memory".to_owned());
+
+    match backend.as_str() {
+        "memory" => {
+            let ledger = Arc::new(MemoryLedger::default());
+            Ok((ledger.clone(), ledger))
+        }
+        "clickhouse" => Ok((
+            Arc::new(stash_processor::db::ClickhouseDatabase::new().await),
+            Arc::new(price_history_api::db::ClickhouseDatabase::new().await),
+        )),
+        other => {
+            anyhow::bail!("unknown LEDGER_DEV_STORAGE: {other}, expected memory or clickhouse")
+        }
+    }
+}
+
+fn setup_logger() {
+    let logger = tracing_subscriber::fmt::layer().json();
+    let env_filter = EnvFilter::try_from_default_env()
+        .or_else(|_| EnvFilter::try_new("info"))
+        .expect("failed to create logger");
+    let exporter = Registry::default().with(logger).with(env_filter);
+
+    tracing::subscriber::set_global_default(exporter).expect("failed to set log exporter");
+}
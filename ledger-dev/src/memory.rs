@@ -0,0 +1,567 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    sync::RwLock,
+};
+
+use async_trait::async_trait;
+use poe_types::league::LeagueIdentity;
+use price_history_api::db::{
+    AccountSummaryRow, ChInterval, LeagueRow, LedgerQuery, PriceHistoryBucketRow, PriceHistoryStore,
+};
+use stash_processor::{
+    lifecycle::{ListingEvent, ListingEventKind},
+    listing::Listing,
+    sink::ListingSink,
+};
+use time::{Date, Month, OffsetDateTime, Time};
+
+/// The fields of a listing needed to answer price history queries
+struct StoredListing {
+    name: String,
+    category: String,
+    variant: String,
+    league: String,
+    normalized_price: f64,
+    listed_price: f64,
+    listed_currency: String,
+    stack_size: u32,
+    created_at: OffsetDateTime,
+    is_outlier: bool,
+}
+
+/// MemoryLedger keeps listings in memory, acting as both the processor's listing store and the
+/// API's price history store so the pipeline can run without Clickhouse
+#[derive(Default)]
+pub struct MemoryLedger {
+    listings: RwLock<Vec<StoredListing>>,
+    events: RwLock<Vec<ListingEvent>>,
+}
+
+#[async_trait]
+impl ListingSink for MemoryLedger {
+    fn name(&self) -> &'static str {
+        "memory"
+    }
+
+    async fn write_listings(&self, listings: &[Listing]) -> anyhow::Result<()> {
+        let mut stored = self.listings.write().unwrap();
+
+        stored.extend(listings.iter().map(|l| StoredListing {
+            name: l.name.clone(),
+            category: l.category.to_string(),
+            variant: l.variant.clone(),
+            league: l.league.clone(),
+            normalized_price: l.price.normalized_price,
+            listed_price: l.price.listed_price,
+            listed_currency: l.price.listed_currency.to_string(),
+            stack_size: l.stack_size,
+            created_at: l.created_at,
+            is_outlier: l.is_outlier,
+        }));
+
+        Ok(())
+    }
+
+    async fn write_events(&self, events: &[ListingEvent]) -> anyhow::Result<()> {
+        self.events.write().unwrap().extend_from_slice(events);
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl PriceHistoryStore for MemoryLedger {
+    async fn query_ledger_by_name(
+        &self,
+        query: LedgerQuery,
+    ) -> anyhow::Result<Vec<PriceHistoryBucketRow>> {
+        let stored = self.listings.read().unwrap();
+        let timeframe = query.timeframe.start..=query.timeframe.end;
+
+        let mut buckets: BTreeMap<(OffsetDateTime, String, String), Vec<(f64, u32)>> =
+            BTreeMap::new();
+        for l in stored.iter() {
+            let created_at = l.created_at.unix_timestamp();
+
+            if l.league != query.league
+                || !l.name.eq_ignore_ascii_case(&query.name)
+                || !timeframe.contains(&created_at)
+                || query.category.as_ref().is_some_and(|c| *c != l.category)
+                || query.variant.as_ref().is_some_and(|v| *v != l.variant)
+                || (query.exclude_outliers && l.is_outlier)
+            {
+                continue;
+            }
+
+            let (price, currency) = match query.normalized {
+                true if l.normalized_price > 0.0 => (l.normalized_price, "chaos".to_owned()),
+                true => continue,
+                false => (l.listed_price, l.listed_currency.clone()),
+            };
+
+            let key = (
+                start_of_interval(l.created_at, &query.interval),
+                l.name.clone(),
+                currency,
+            );
+            buckets
+                .entry(key)
+                .or_default()
+                .push((price, l.stack_size.max(1)));
+        }
+
+        let rows = buckets
+            .into_iter()
+            .map(
+                |((interval_bucket, item_name, listed_currency), mut prices)| {
+                    prices.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+                    PriceHistoryBucketRow {
+                        item_name,
+                        interval_bucket,
+                        price_by_quantile: query
+                            .quantiles
+                            .iter()
+                            .map(|q| (*q, weighted_quantile(&prices, *q)))
+                            .collect(),
+                        listed_currency,
+                    }
+                },
+            )
+            .collect();
+
+        Ok(rows)
+    }
+
+    /// Leagues are read from the stored listings, as the processor's league registry is kept in
+    /// Clickhouse
+    async fn leagues(&self) -> anyhow::Result<Vec<LeagueRow>> {
+        let stored = self.listings.read().unwrap();
+
+        let mut seen: BTreeMap<&str, (OffsetDateTime, OffsetDateTime)> = BTreeMap::new();
+        for l in stored.iter() {
+            let (first, last) = seen
+                .entry(&l.league)
+                .or_insert((l.created_at, l.created_at));
+            *first = (*first).min(l.created_at);
+            *last = (*last).max(l.created_at);
+        }
+
+        let mut leagues = seen
+            .into_iter()
+            .map(|(name, (first_seen, last_seen))| {
+                let identity = LeagueIdentity::from_name(name);
+
+                LeagueRow {
+                    name: identity.name,
+                    base: identity.base,
+                    kind: identity.kind.to_string(),
+                    hardcore: identity.hardcore,
+                    solo_self_found: identity.solo_self_found,
+                    ruthless: identity.ruthless,
+                    private: identity.private,
+                    first_seen,
+                    last_seen,
+                }
+            })
+            .collect::<Vec<_>>();
+        leagues.sort_by_key(|l| l.first_seen);
+
+        Ok(leagues)
+    }
+
+    /// Rolls up the stored listing events the way Clickhouse's account aggregates do
+    async fn account_summary(
+        &self,
+        account: &str,
+        league: &str,
+        since: OffsetDateTime,
+    ) -> anyhow::Result<Option<AccountSummaryRow>> {
+        let events = self.events.read().unwrap();
+        let since = since.date();
+
+        let mut summary: Option<AccountSummaryRow> = None;
+        let mut items = HashSet::new();
+        let mut removed_total = 0;
+        for e in events.iter() {
+            if e.item.account_name != account || e.item.league != league {
+                continue;
+            }
+
+            let row = summary.get_or_insert_with(|| AccountSummaryRow {
+                account_name: account.to_owned(),
+                league: league.to_owned(),
+                ..Default::default()
+            });
+            let recent = e.created_at.date() >= since;
+
+            match e.kind {
+                ListingEventKind::Listed => {
+                    row.active_listings += 1;
+                    row.listed_value += e.item.normalized_price;
+                    row.recent_listed += recent as u64;
+                }
+                ListingEventKind::Removed => {
+                    removed_total += 1;
+                    row.listed_value -= e.item.normalized_price;
+                    row.recent_removed += recent as u64;
+                }
+                ListingEventKind::Unpriced => {
+                    removed_total += 1;
+                    row.listed_value -= e.item.normalized_price;
+                    row.recent_unpriced += recent as u64;
+                }
+                ListingEventKind::Repriced => {
+                    let previous = e.previous.as_ref().map_or(0.0, |p| p.normalized_price);
+                    row.listed_value += e.item.normalized_price - previous;
+                    row.recent_repriced += recent as u64;
+                }
+            }
+
+            let up = !matches!(
+                e.kind,
+                ListingEventKind::Removed | ListingEventKind::Unpriced
+            );
+            if recent && up {
+                items.insert(e.item.name.as_str());
+            }
+        }
+
+        Ok(summary.map(|mut row| {
+            row.active_listings = (row.active_listings - removed_total).max(0);
+            // as the account_activity query computes it
+            let up = row.active_listings as u64 + row.recent_removed;
+            row.churn_rate = match up > 0 {
+                true => row.recent_removed as f64 / up as f64,
+                false => 0.0,
+            };
+            row.listed_value = row.listed_value.max(0.0);
+            row.recent_items = items.len() as u64;
+
+            row
+        }))
+    }
+}
+
+/// Mirrors Clickhouse's `quantileExactWeighted` over values sorted by price, each weighted by
+/// the number of units listed
+fn weighted_quantile(sorted: &[(f64, u32)], q: f64) -> f64 {
+    let total = sorted.iter().map(|(_, w)| *w as u64).sum::<u64>();
+    let threshold = (total as f64 * q).ceil() as u64;
+
+    let mut accumulated = 0;
+    for (price, weight) in sorted {
+        accumulated += *weight as u64;
+        if accumulated >= threshold {
+            return *price;
+        }
+    }
+
+    0.0
+}
+
+/// Mirrors Clickhouse's `toStartOfInterval` for the supported interval units
+fn start_of_interval(at: OffsetDateTime, interval: &ChInterval) -> OffsetDateTime {
+    let floor = |seconds: i64, offset: i64| {
+        let ts = at.unix_timestamp() - offset;
+        OffsetDateTime::from_unix_timestamp(ts - ts.rem_euclid(seconds) + offset)
+            .expect("bucket start must be a valid timestamp")
+    };
+
+    match *interval {
+        ChInterval::Minute(x) => floor(60 * x.max(1) as i64, 0),
+        ChInterval::Hour(x) => floor(60 * 60 * x.max(1) as i64, 0),
+        ChInterval::Day(x) => floor(24 * 60 * 60 * x.max(1) as i64, 0),
+        // weeks start on a monday, the unix epoch was a thursday
+        ChInterval::Week(x) => floor(7 * 24 * 60 * 60 * x.max(1) as i64, 4 * 24 * 60 * 60),
+        ChInterval::Month(x) => {
+            let months = at.year() as i64 * 12 + (at.month() as i64 - 1);
+            let start = months - months.rem_euclid(x.max(1) as i64);
+            let month = Month::try_from((start.rem_euclid(12) + 1) as u8).unwrap();
+            let date = Date::from_calendar_date(start.div_euclid(12) as i32, month, 1).unwrap();
+
+            date.with_time(Time::MIDNIGHT).assume_utc()
+        }
+        ChInterval::Year(x) => {
+            let year = at.year() - at.year().rem_euclid(x.max(1));
+            let date = Date::from_calendar_date(year, Month::January, 1).unwrap();
+
+            date.with_time(Time::MIDNIGHT).assume_utc()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use price_history_api::db::{ChInterval, ChTimeframe, LedgerQuery, PriceHistoryStore};
+    use stash_processor::{
+        lifecycle::{ItemState, ListingEvent, ListingEventKind},
+        listing::{ComplexPrice, Listing, ListingCurrency},
+        sink::ListingSink,
+    };
+    use time::macros::datetime;
+
+    use super::{start_of_interval, weighted_quantile, MemoryLedger};
+
+    fn query(name: &str, at: time::OffsetDateTime) -> LedgerQuery {
+        LedgerQuery {
+            name: name.to_owned(),
+            league: "Standard".to_owned(),
+            interval: ChInterval::Day(1),
+            quantiles: vec![0.5],
+            timeframe: ChTimeframe::new(at.unix_timestamp() - 60, at.unix_timestamp() + 60),
+            normalized: false,
+            category: None,
+            variant: None,
+            exclude_outliers: false,
+        }
+    }
+
+    fn listing(name: &str, price: f64, created_at: time::OffsetDateTime) -> Listing {
+        Listing {
+            name: name.to_owned(),
+            league: "Standard".to_owned(),
+            price: ComplexPrice {
+                normalized_price: 0.0,
+                listed_price: price,
+                listed_currency: ListingCurrency::CHAOS,
+                ..Default::default()
+            },
+            created_at,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn quantiles_weighted_by_units() {
+        let prices = [(1.0, 1), (2.0, 1), (3.0, 1), (4.0, 1), (5.0, 1)];
+
+        assert_eq!(weighted_quantile(&prices, 0.0), 1.0);
+        assert_eq!(weighted_quantile(&prices, 0.5), 3.0);
+        assert_eq!(weighted_quantile(&prices, 0.9), 5.0);
+        assert_eq!(weighted_quantile(&[], 0.5), 0.0);
+
+        let bulk = [(0.1, 5000), (0.5, 1)];
+        assert_eq!(weighted_quantile(&bulk, 0.9), 0.1);
+    }
+
+    #[test]
+    fn interval_buckets() {
+        let at = datetime!(2024-04-10 13:45:12 UTC);
+
+        assert_eq!(
+            start_of_interval(at, &ChInterval::Hour(6)),
+            datetime!(2024-04-10 12:00 UTC)
+        );
+        assert_eq!(
+            start_of_interval(at, &ChInterval::Week(1)),
+            datetime!(2024-04-08 00:00 UTC)
+        );
+        assert_eq!(
+            start_of_interval(at, &ChInterval::Month(3)),
+            datetime!(2024-04-01 00:00 UTC)
+        );
+        assert_eq!(
+            start_of_interval(at, &ChInterval::Year(1)),
+            datetime!(2024-01-01 00:00 UTC)
+        );
+    }
+
+    #[tokio::test]
+    async fn history_from_stored_listings() {
+        let ledger = MemoryLedger::default();
+        let at = datetime!(2024-04-10 13:45 UTC);
+
+        ledger
+            .write_listings(&[
+                listing("Mageblood", 100.0, at),
+                listing("Mageblood", 200.0, at),
+                listing("Headhunter", 50.0, at),
+            ])
+            .await
+            .unwrap();
+
+        let rows = ledger
+            .query_ledger_by_name(query("mageblood", at))
+            .await
+            .unwrap();
+
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].price_by_quantile, vec![(0.5, 100.0)]);
+        assert_eq!(rows[0].listed_currency, "chaos");
+    }
+
+    #[tokio::test]
+    async fn normalized_history_merges_currencies() {
+        let ledger = MemoryLedger::default();
+        let at = datetime!(2024-04-10 13:45 UTC);
+
+        let mut divine = listing("Mageblood", 1.0, at);
+        divine.price.listed_currency = ListingCurrency::DIVINE;
+        divine.price.normalized_price = 200.0;
+        let mut chaos = listing("Mageblood", 100.0, at);
+        chaos.price.normalized_price = 100.0;
+
+        ledger.write_listings(&[divine, chaos]).await.unwrap();
+
+        let rows = ledger
+            .query_ledger_by_name(LedgerQuery {
+                normalized: true,
+                ..query("Mageblood", at)
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].price_by_quantile, vec![(0.5, 100.0)]);
+    }
+
+    #[tokio::test]
+    async fn outliers_can_be_excluded() {
+        let ledger = MemoryLedger::default();
+        let at = datetime!(2024-04-10 13:45 UTC);
+
+        let mut fixed = listing("Mageblood", 1.0, at);
+        fixed.is_outlier = true;
+
+        ledger
+            .write_listings(&[fixed, listing("Mageblood", 200.0, at)])
+            .await
+            .unwrap();
+
+        let rows = ledger
+            .query_ledger_by_name(LedgerQuery {
+                quantiles: vec![0.1],
+                exclude_outliers: true,
+                ..query("Mageblood", at)
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(rows[0].price_by_quantile, vec![(0.1, 200.0)]);
+    }
+
+    #[tokio::test]
+    async fn variants_can_be_selected() {
+        let ledger = MemoryLedger::default();
+        let at = datetime!(2024-04-10 13:45 UTC);
+
+        let mut six_link = listing("Shavronne's Wrappings", 300.0, at);
+        six_link.variant = "6 links".to_owned();
+
+        ledger
+            .write_listings(&[six_link, listing("Shavronne's Wrappings", 5.0, at)])
+            .await
+            .unwrap();
+
+        let rows = ledger
+            .query_ledger_by_name(LedgerQuery {
+                variant: Some("6 links".to_owned()),
+                ..query("Shavronne's Wrappings", at)
+            })
+            .await
+            .unwrap();
+        assert_eq!(rows[0].price_by_quantile, vec![(0.5, 300.0)]);
+
+        let rows = ledger
+            .query_ledger_by_name(LedgerQuery {
+                variant: Some(String::new()),
+                ..query("Shavronne's Wrappings", at)
+            })
+            .await
+            .unwrap();
+        assert_eq!(rows[0].price_by_quantile, vec![(0.5, 5.0)]);
+    }
+
+    #[tokio::test]
+    async fn account_summary_from_events() {
+        let ledger = MemoryLedger::default();
+        let at = datetime!(2024-04-10 13:45 UTC);
+
+        let item = |name: &str, price: f64| ItemState {
+            name: name.to_owned(),
+            league: "Standard".to_owned(),
+            account_name: "someone".to_owned(),
+            category: "unique".to_owned(),
+            listed_price: price,
+            listed_currency: "chaos".to_owned(),
+            normalized_price: price,
+            first_listed_at: at,
+        };
+        let event = |kind, item_id: &str, item, previous| ListingEvent {
+            kind,
+            stash_id: "stash".to_owned(),
+            item_id: item_id.to_owned(),
+            item,
+            previous,
+            created_at: at,
+        };
+
+        ledger
+            .write_events(&[
+                event(
+                    ListingEventKind::Listed,
+                    "a",
+                    item("Mageblood", 200.0),
+                    None,
+                ),
+                event(
+                    ListingEventKind::Listed,
+                    "b",
+                    item("Headhunter", 50.0),
+                    None,
+                ),
+                event(
+                    ListingEventKind::Repriced,
+                    "a",
+                    item("Mageblood", 180.0),
+                    Some(item("Mageblood", 200.0)),
+                ),
+                event(
+                    ListingEventKind::Removed,
+                    "b",
+                    item("Headhunter", 50.0),
+                    None,
+                ),
+                event(
+                    ListingEventKind::Listed,
+                    "c",
+                    item("Headhunter", 40.0),
+                    None,
+                ),
+                event(
+                    ListingEventKind::Unpriced,
+                    "c",
+                    item("Headhunter", 40.0),
+                    None,
+                ),
+            ])
+            .await
+            .unwrap();
+
+        let summary = ledger
+            .account_summary("someone", "Standard", at)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(summary.active_listings, 1);
+        assert_eq!(summary.listed_value, 180.0);
+        assert_eq!(
+            (
+                summary.recent_listed,
+                summary.recent_removed,
+                summary.recent_unpriced,
+                summary.recent_repriced
+            ),
+            (3, 1, 1, 1)
+        );
+        assert_eq!(summary.recent_items, 2);
+        assert_eq!(summary.churn_rate, 0.5);
+
+        assert!(ledger
+            .account_summary("someone else", "Standard", at)
+            .await
+            .unwrap()
+            .is_none());
+    }
+}
@@ -0,0 +1,152 @@
+use std::{collections::BTreeSet, fs, path::Path, sync::Arc};
+
+use anyhow::Context;
+use async_trait::async_trait;
+use axum::{
+    extract::{Query, State},
+    routing::{get, post},
+    Json, Router,
+};
+use poe_api_client::ratelimit::limiter::{LimiterOutcome, Policy, RateLimiter, RateLimiterError};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::net::TcpListener;
+
+/// MockApi serves the public stash fixtures in a loop, rewriting each page's `next_change_id`
+/// so the crawler walks through them in order as if it were following the river
+pub struct MockApi {
+    pages: Vec<Value>,
+    leagues: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct StashQuery {
+    id: Option<String>,
+}
+
+impl MockApi {
+    /// Loads every `.json` page of public stashes in `dir`, sorted by file name
+    pub fn load(dir: &Path) -> anyhow::Result<Self> {
+        let mut paths = fs::read_dir(dir)
+            .context(format!("failed reading fixtures dir: {}", dir.display()))?
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| p.extension().is_some_and(|e| e == "json"))
+            .collect::<Vec<_>>();
+        paths.sort();
+
+        let mut pages = Vec::new();
+        let mut leagues = BTreeSet::new();
+        for (i, path) in paths.iter().enumerate() {
+            let raw = fs::read(path).context(format!("failed reading {}", path.display()))?;
+            let mut page = serde_json::from_slice::<Value>(&raw)
+                .context(format!("failed parsing {}", path.display()))?;
+
+            page["next_change_id"] = json!(dev_change_id((i + 1) % paths.len()));
+
+            if let Some(stashes) = page["stashes"].as_array() {
+                leagues.extend(
+                    stashes
+                        .iter()
+                        .filter_map(|s| s["league"].as_str().map(|l| l.to_owned())),
+                );
+            }
+
+            pages.push(page);
+        }
+
+        if pages.is_empty() {
+            anyhow::bail!("no fixtures found in {}", dir.display());
+        }
+
+        tracing::info!(
+            "loaded {} fixture pages from {}",
+            pages.len(),
+            dir.display()
+        );
+
+        Ok(Self {
+            pages,
+            leagues: leagues.into_iter().collect(),
+        })
+    }
+
+    pub async fn serve(self, port: u16) -> anyhow::Result<()> {
+        let app = Router::new()
+            .route("/oauth/token", post(token))
+            .route("/public-stash-tabs", get(public_stashes))
+            .route("/league", get(leagues))
+            .with_state(Arc::new(self));
+
+        let listener = TcpListener::bind(format!("127.0.0.1:{port}")).await?;
+        axum::serve(listener, app).await?;
+
+        Ok(())
+    }
+}
+
+pub fn dev_change_id(page: usize) -> String {
+    format!("0-0-0-0-{page}")
+}
+
+fn page_index(change_id: Option<&str>, pages: usize) -> usize {
+    change_id
+        .and_then(|id| id.rsplit('-').next())
+        .and_then(|p| p.parse::<usize>().ok())
+        .map(|p| p % pages)
+        .unwrap_or_default()
+}
+
+async fn token() -> Json<Value> {
+    Json(json!({
+        "access_token": "ledger-dev",
+        "expires_in": null,
+        "token_type": "bearer",
+        "scope": "service:psapi service:leagues",
+    }))
+}
+
+async fn public_stashes(
+    Query(query): Query<StashQuery>,
+    State(api): State<Arc<MockApi>>,
+) -> Json<Value> {
+    let index = page_index(query.id.as_deref(), api.pages.len());
+
+    Json(api.pages[index].clone())
+}
+
+async fn leagues(State(api): State<Arc<MockApi>>) -> Json<Value> {
+    let leagues = api
+        .leagues
+        .iter()
+        .map(|id| json!({ "id": id, "realm": "pc" }))
+        .collect::<Vec<_>>();
+
+    Json(json!({ "leagues": leagues }))
+}
+
+/// The mock API has no rate limits, so every request is allowed to proceed
+pub struct UnlimitedRateLimiter;
+
+#[async_trait]
+impl RateLimiter for UnlimitedRateLimiter {
+    async fn check(&self, _endpoint: &str) -> Result<LimiterOutcome, RateLimiterError> {
+        Ok(LimiterOutcome::Proceed)
+    }
+
+    async fn update(&mut self, _endpoint: &str, _policy: Policy) -> Result<(), RateLimiterError> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{dev_change_id, page_index};
+
+    #[test]
+    fn change_ids_cycle_through_pages() {
+        assert_eq!(page_index(Some(&dev_change_id(1)), 2), 1);
+        assert_eq!(page_index(Some(&dev_change_id(2)), 2), 0);
+        assert_eq!(page_index(None, 2), 0);
+        assert_eq!(page_index(Some("not-a-dev-id"), 2), 0);
+    }
+}
@@ -0,0 +1,30 @@
+/// Version of the ledger schema in Clickhouse, the latest migration applied to it or 0 when none
+/// has been. The client must not be bound to the ledger database, as it may not exist yet
+pub async fn current_version(client: &clickhouse::Client) -> anyhow::Result<u32> {
+    let exists = client
+        .query("EXISTS TABLE ledger.schema_migrations")
+        .fetch_one::<u8>()
+        .await?;
+    if exists == 0 {
+        return Ok(0);
+    }
+
+    let version = client
+        .query("SELECT max(version) FROM ledger.schema_migrations")
+        .fetch_one::<u32>()
+        .await?;
+
+    Ok(version)
+}
+
+/// Fails when the schema is older than `required`
+pub async fn check(client: &clickhouse::Client, required: u32) -> anyhow::Result<()> {
+    let version = current_version(client).await?;
+    if version < required {
+        anyhow::bail!(
+            "Clickhouse schema is at version {version} but version {required} is required, run `stash-processor migrate`"
+        );
+    }
+
+    Ok(())
+}
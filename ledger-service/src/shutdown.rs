@@ -0,0 +1,104 @@
+use std::{
+    env,
+    future::Future,
+    sync::OnceLock,
+    time::{Duration, Instant},
+};
+
+use tokio::signal;
+
+const DEFAULT_SHUTDOWN_DEADLINE: Duration = Duration::from_secs(25);
+
+/// Shutdown waits for the process to be asked to stop, then gives it until a deadline to finish
+/// what is in flight
+pub struct Shutdown {
+    deadline: Duration,
+    signalled_at: OnceLock<Instant>,
+}
+
+impl Shutdown {
+    pub fn new(deadline: Duration) -> Self {
+        Self {
+            deadline,
+            signalled_at: OnceLock::new(),
+        }
+    }
+
+    /// Reads the deadline from `SHUTDOWN_DEADLINE_SECS`, keep it below the pod's termination grace
+    /// period
+    pub fn from_env() -> Self {
+        let deadline = env::var("SHUTDOWN_DEADLINE_SECS")
+            .map(|s| {
+                Duration::from_secs(
+                    s.parse::<u64>()
+                        .expect("SHUTDOWN_DEADLINE_SECS must be a number"),
+                )
+            })
+            .unwrap_or(DEFAULT_SHUTDOWN_DEADLINE);
+
+        Self::new(deadline)
+    }
+
+    /// Resolves on SIGTERM or Ctrl+C, which starts the deadline
+    pub async fn signalled(&self) {
+        let ctrl_c = async {
+            signal::ctrl_c()
+                .await
+                .expect("failed to install Ctrl+C handler");
+        };
+
+        #[cfg(unix)]
+        let terminate = async {
+            signal::unix::signal(signal::unix::SignalKind::terminate())
+                .expect("failed to install signal handler")
+                .recv()
+                .await;
+        };
+
+        #[cfg(not(unix))]
+        let terminate = std::future::pending::<()>();
+
+        tokio::select! {
+            _ = ctrl_c => {},
+            _ = terminate => {},
+        }
+
+        self.signalled_at.get_or_init(Instant::now);
+        tracing::info!(
+            "shutting down, finishing in-flight work within {:?}",
+            self.deadline
+        );
+    }
+
+    /// Runs what is left to do before stopping, failing once the deadline passes. The deadline
+    /// counts from the signal, or from now when the process is stopping on its own
+    pub async fn drain<F: Future>(&self, drain: F) -> anyhow::Result<F::Output> {
+        let remaining = self
+            .signalled_at
+            .get()
+            .map(|at| self.deadline.saturating_sub(at.elapsed()))
+            .unwrap_or(self.deadline);
+
+        tokio::time::timeout(remaining, drain)
+            .await
+            .map_err(|_| anyhow::anyhow!("in-flight work didn't finish within {:?}", self.deadline))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::Shutdown;
+
+    #[tokio::test]
+    async fn drain_fails_past_the_deadline() {
+        let shutdown = Shutdown::new(Duration::from_millis(20));
+
+        assert_eq!(shutdown.drain(async { 3 }).await.unwrap(), 3);
+        assert!(shutdown
+            .drain(tokio::time::sleep(Duration::from_secs(5)))
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,169 @@
+use std::{
+    collections::VecDeque,
+    env,
+    fmt::{Display, Write},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use async_nats::{connection::State, jetstream::consumer::PullConsumer};
+use async_trait::async_trait;
+use axum::{extract::State as AxumState, http::StatusCode, routing::get, Router};
+use tokio::net::TcpListener;
+
+const THROUGHPUT_WINDOW: Duration = Duration::from_secs(60);
+const MONITOR_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Throughput tracks events over a sliding window to report a per second rate
+#[derive(Default)]
+pub struct Throughput {
+    events: Mutex<VecDeque<(Instant, u64)>>,
+}
+
+impl Throughput {
+    pub fn record(&self, count: u64) {
+        let mut events = self.events.lock().unwrap();
+        let now = Instant::now();
+
+        events.push_back((now, count));
+        while events
+            .front()
+            .is_some_and(|(at, _)| now.duration_since(*at) > THROUGHPUT_WINDOW)
+        {
+            events.pop_front();
+        }
+    }
+
+    pub fn per_second(&self) -> f64 {
+        let events = self.events.lock().unwrap();
+        let total = events
+            .iter()
+            .filter(|(at, _)| at.elapsed() <= THROUGHPUT_WINDOW)
+            .map(|(_, c)| c)
+            .sum::<u64>();
+
+        total as f64 / THROUGHPUT_WINDOW.as_secs_f64()
+    }
+}
+
+/// ConsumerStats are the pending counts of a JetStream consumer, kept up to date by
+/// [`spawn_monitor`]
+#[derive(Default)]
+pub struct ConsumerStats {
+    pub pending: AtomicU64,
+    pub ack_pending: AtomicU64,
+}
+
+/// Telemetry is what a service exposes on its telemetry server
+#[async_trait]
+pub trait Telemetry: Send + Sync + 'static {
+    /// Metrics in the prometheus text format
+    fn render_metrics(&self) -> String;
+
+    /// Why the service can't take work, empty when it is ready
+    async fn unready(&self) -> Vec<String>;
+}
+
+pub fn write_counter(out: &mut String, name: &str, value: impl Display) {
+    let _ = writeln!(out, "# TYPE {name} counter");
+    let _ = writeln!(out, "{name} {value}");
+}
+
+pub fn write_gauge(out: &mut String, name: &str, value: impl Display) {
+    let _ = writeln!(out, "# TYPE {name} gauge");
+    let _ = writeln!(out, "{name} {value}");
+}
+
+/// Why a NATS connection isn't ready, if it isn't
+pub fn nats_unready(nats: &async_nats::Client) -> Option<String> {
+    match nats.connection_state() {
+        State::Connected => None,
+        s => Some(format!("nats: {s}")),
+    }
+}
+
+/// Serves `/healthz`, `/readyz` and `/metrics` on `TELEMETRY_PORT`
+pub async fn serve(telemetry: Arc<dyn Telemetry>) -> anyhow::Result<()> {
+    let port = env::var("TELEMETRY_PORT")
+        .unwrap_or("8080".to_owned())
+        .parse::<u16>()
+        .expect("TELEMETRY_PORT must be a valid 16bit port number");
+
+    let app = Router::new()
+        .route("/healthz", get(|| async { StatusCode::OK }))
+        .route("/readyz", get(readyz))
+        .route("/metrics", get(render_metrics))
+        .with_state(telemetry);
+
+    let listener = TcpListener::bind(format!("0.0.0.0:{port}")).await?;
+    axum::serve(listener, app).await?;
+
+    Ok(())
+}
+
+async fn readyz(AxumState(telemetry): AxumState<Arc<dyn Telemetry>>) -> (StatusCode, String) {
+    let failures = telemetry.unready().await;
+
+    match failures.is_empty() {
+        true => (StatusCode::OK, "ok".to_owned()),
+        false => (StatusCode::SERVICE_UNAVAILABLE, failures.join("\n")),
+    }
+}
+
+async fn render_metrics(AxumState(telemetry): AxumState<Arc<dyn Telemetry>>) -> String {
+    telemetry.render_metrics()
+}
+
+/// Periodically records the consumer's pending counts
+pub fn spawn_monitor<M>(mut consumer: PullConsumer, metrics: Arc<M>)
+where
+    M: AsRef<ConsumerStats> + Send + Sync + 'static,
+{
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(MONITOR_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            match consumer.info().await {
+                Ok(info) => {
+                    let stats = (*metrics).as_ref();
+                    stats.pending.store(info.num_pending, Ordering::Relaxed);
+                    stats
+                        .ack_pending
+                        .store(info.num_ack_pending as u64, Ordering::Relaxed);
+                }
+                Err(e) => tracing::warn!("failed fetching consumer info: {e}"),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{write_counter, write_gauge, Throughput};
+
+    #[test]
+    fn throughput_is_averaged_over_the_window() {
+        let throughput = Throughput::default();
+        throughput.record(30);
+        throughput.record(90);
+
+        assert_eq!(throughput.per_second(), 2.0);
+    }
+
+    #[test]
+    fn metrics_are_rendered_with_their_type() {
+        let mut out = String::new();
+        write_counter(&mut out, "pages_total", 3);
+        write_gauge(&mut out, "pages_per_second", 0.5);
+
+        assert_eq!(
+            out,
+            "# TYPE pages_total counter\npages_total 3\n# TYPE pages_per_second gauge\npages_per_second 0.5\n"
+        );
+    }
+}
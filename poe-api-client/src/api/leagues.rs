@@ -0,0 +1,8 @@
+use serde::Deserialize;
+
+use poe_types::league::League;
+
+#[derive(Deserialize)]
+pub struct LeaguesResponse {
+    pub leagues: Vec<League>,
+}
@@ -1 +1,2 @@
+pub mod leagues;
 pub mod stashes;
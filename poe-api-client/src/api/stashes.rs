@@ -19,7 +19,7 @@ mod tests {
         let path = Path::new("test/stash-1.json");
         let display = path.display();
 
-        let file1 = match File::open(&path) {
+        let file1 = match File::open(path) {
             Err(why) => panic!("couldn't read {}: {}", display, why),
             Ok(f) => f,
         };
@@ -29,7 +29,7 @@ mod tests {
         let path = Path::new("test/stash-2.json");
         let display = path.display();
 
-        let file2 = match File::open(&path) {
+        let file2 = match File::open(path) {
             Err(why) => panic!("couldn't read {}: {}", display, why),
             Ok(f) => f,
         };
@@ -39,7 +39,7 @@ mod tests {
         let path = Path::new("test/stash-3.json");
         let display = path.display();
 
-        let file3 = match File::open(&path) {
+        let file3 = match File::open(path) {
             Err(why) => panic!("couldn't read {}: {}", display, why),
             Ok(f) => f,
         };
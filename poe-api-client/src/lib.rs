@@ -2,7 +2,8 @@ pub mod api;
 pub mod fetch;
 pub mod ratelimit;
 
-use api::stashes::PublicStashesResponse;
+use api::{leagues::LeaguesResponse, stashes::PublicStashesResponse};
+use bytes::Bytes;
 use ratelimit::limiter::{RateLimiter, RateLimiterError};
 use reqwest::{
     header::{HeaderMap, HeaderValue, ACCEPT},
@@ -20,6 +21,8 @@ pub enum ClientError {
     SendFailed(reqwest::Error),
     #[error("reqwest couldn't deserialize body: {0}")]
     DeserializeError(reqwest::Error),
+    #[error("couldn't parse response body: {0}")]
+    ParseError(serde_json::Error),
     #[error("encountered rate limit")]
     RateLimited,
     #[error("failed processing rate limiter rules: {0}")]
@@ -32,8 +35,13 @@ pub enum ClientError {
     UnknownError,
 }
 
+const DEFAULT_WEB_URL: &str = "https://www.pathofexile.com";
+const DEFAULT_API_URL: &str = "https://api.pathofexile.com";
+
 pub struct Client<L: RateLimiter> {
     access_token: Option<String>,
+    web_url: String,
+    api_url: String,
     http_client: reqwest::Client,
     limiter: L,
 }
@@ -51,11 +59,21 @@ impl<L: RateLimiter> Client<L> {
 
         Ok(Self {
             access_token: None,
+            web_url: DEFAULT_WEB_URL.to_owned(),
+            api_url: DEFAULT_API_URL.to_owned(),
             http_client,
             limiter: rate_limiter,
         })
     }
 
+    /// Overrides the urls used for authorization and API requests, e.g. to point at a mock API
+    pub fn with_base_urls(mut self, web_url: &str, api_url: &str) -> Self {
+        self.web_url = web_url.trim_end_matches('/').to_owned();
+        self.api_url = api_url.trim_end_matches('/').to_owned();
+
+        self
+    }
+
     pub async fn authorize(
         &mut self,
         client_id: &str,
@@ -64,7 +82,7 @@ impl<L: RateLimiter> Client<L> {
         let endpoint = "oauth/token";
         let request = self
             .http_client
-            .post(format!("https://www.pathofexile.com/{endpoint}"))
+            .post(format!("{}/{endpoint}", self.web_url))
             .form(&[
                 ("client_id", client_id),
                 ("client_secret", client_secret),
@@ -87,10 +105,57 @@ impl<L: RateLimiter> Client<L> {
         }
     }
 
+    /// Fetches the leagues of a realm, `type=main` only returns the permanent and current challenge leagues
+    pub async fn get_leagues(
+        &mut self,
+        realm: &str,
+        league_type: &str,
+    ) -> Result<(LeaguesResponse, StatusCode), ClientError> {
+        let endpoint = "league";
+
+        let token = match &self.access_token {
+            Some(t) => t,
+            None => return Err(ClientError::AuthError),
+        };
+
+        let request = self
+            .http_client
+            .get(format!("{}/{endpoint}", self.api_url))
+            .query(&[("realm", realm), ("type", league_type)])
+            .bearer_auth(token);
+
+        let response = self.fetch_api_response(endpoint, request).await?;
+        let status = response.status();
+        match status {
+            StatusCode::OK => {
+                let body = response
+                    .json::<LeaguesResponse>()
+                    .await
+                    .map_err(ClientError::DeserializeError)?;
+
+                Ok((body, status))
+            }
+            StatusCode::UNAUTHORIZED => Err(ClientError::AuthError),
+            _ => Err(ClientError::HttpError(response.status())),
+        }
+    }
+
     pub async fn get_public_stashes(
         &mut self,
         next_change_id: Option<&str>,
     ) -> Result<(PublicStashesResponse, StatusCode), ClientError> {
+        let (body, status) = self.get_public_stashes_raw(next_change_id).await?;
+        let stashes = serde_json::from_slice::<PublicStashesResponse>(&body)
+            .map_err(ClientError::ParseError)?;
+
+        Ok((stashes, status))
+    }
+
+    /// Fetches a page of public stashes without deserializing it, returning the raw response body
+    pub async fn get_public_stashes_raw(
+        &mut self,
+        next_change_id: Option<&str>,
+    ) -> Result<(Bytes, StatusCode), ClientError> {
         let endpoint = "public-stash-tabs";
 
         let token = match &self.access_token {
@@ -99,8 +164,8 @@ impl<L: RateLimiter> Client<L> {
         };
 
         let stash_url = match next_change_id {
-            Some(id) => format!("https://api.pathofexile.com/{endpoint}?id={id}"),
-            None => format!("https://api.pathofexile.com/{endpoint}"),
+            Some(id) => format!("{}/{endpoint}?id={id}", self.api_url),
+            None => format!("{}/{endpoint}", self.api_url),
         };
 
         let request = self.http_client.get(stash_url).bearer_auth(token);
@@ -110,7 +175,7 @@ impl<L: RateLimiter> Client<L> {
         match status {
             StatusCode::OK => {
                 let body = response
-                    .json::<PublicStashesResponse>()
+                    .bytes()
                     .await
                     .map_err(ClientError::DeserializeError)?;
 
@@ -0,0 +1,140 @@
+use std::{collections::HashMap, fmt, sync::OnceLock};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// The catalogue embedded from `data/currencies.json`
+const EMBEDDED_CATALOGUE: &str = include_str!("../data/currencies.json");
+
+#[derive(Debug, Error)]
+pub enum CatalogueError {
+    #[error("failed parsing currency catalogue: {0}")]
+    Parse(#[from] serde_json::Error),
+    #[error("currency tag or alias `{0}` is in the catalogue more than once")]
+    DuplicateTag(String),
+}
+
+/// Currency is something items can be priced in on the trade site
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Currency {
+    /// tag used for the currency in price notes and trade searches, e.g. `chaos`
+    pub tag: String,
+    /// name of the currency item, e.g. `Chaos Orb`
+    pub name: String,
+    pub category: CurrencyCategory,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub icon: Option<String>,
+    /// other tags notes may use for the currency, e.g. `exa` for `exalted`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub aliases: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CurrencyCategory {
+    Currency,
+    Shard,
+    Fragment,
+    Splinter,
+    Scarab,
+}
+
+impl fmt::Display for CurrencyCategory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CurrencyCategory::Currency => write!(f, "currency"),
+            CurrencyCategory::Shard => write!(f, "shard"),
+            CurrencyCategory::Fragment => write!(f, "fragment"),
+            CurrencyCategory::Splinter => write!(f, "splinter"),
+            CurrencyCategory::Scarab => write!(f, "scarab"),
+        }
+    }
+}
+
+/// CurrencyCatalogue looks up currencies by their tag, aliases or item name
+#[derive(Debug)]
+pub struct CurrencyCatalogue {
+    currencies: Vec<Currency>,
+    by_tag: HashMap<String, usize>,
+    by_name: HashMap<String, usize>,
+}
+
+impl CurrencyCatalogue {
+    pub fn from_json(json: &str) -> Result<Self, CatalogueError> {
+        let currencies: Vec<Currency> = serde_json::from_str(json)?;
+
+        let mut by_tag = HashMap::new();
+        let mut by_name = HashMap::new();
+        for (i, currency) in currencies.iter().enumerate() {
+            for tag in std::iter::once(&currency.tag).chain(&currency.aliases) {
+                if by_tag.insert(tag.clone(), i).is_some() {
+                    return Err(CatalogueError::DuplicateTag(tag.clone()));
+                }
+            }
+            by_name.insert(currency.name.clone(), i);
+        }
+
+        Ok(Self {
+            currencies,
+            by_tag,
+            by_name,
+        })
+    }
+
+    /// The catalogue built into this crate
+    pub fn embedded() -> &'static Self {
+        static CATALOGUE: OnceLock<CurrencyCatalogue> = OnceLock::new();
+
+        CATALOGUE.get_or_init(|| {
+            Self::from_json(EMBEDDED_CATALOGUE).expect("embedded currency catalogue must be valid")
+        })
+    }
+
+    /// Looks up a currency by its tag or one of its aliases
+    pub fn get(&self, tag: &str) -> Option<&Currency> {
+        self.by_tag.get(tag).map(|&i| &self.currencies[i])
+    }
+
+    /// Looks up a currency by the name of its item, which is the base type of currency items
+    pub fn by_name(&self, name: &str) -> Option<&Currency> {
+        self.by_name.get(name).map(|&i| &self.currencies[i])
+    }
+
+    pub fn currencies(&self) -> &[Currency] {
+        &self.currencies
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CatalogueError, CurrencyCatalogue, CurrencyCategory};
+
+    #[test]
+    fn embedded_catalogue_resolves_aliases() {
+        let catalogue = CurrencyCatalogue::embedded();
+
+        let exalted = catalogue.get("exa").expect("exa is an alias");
+        assert_eq!(exalted.tag, "exalted");
+        assert_eq!(catalogue.by_name("Exalted Orb"), Some(exalted));
+
+        let shard = catalogue
+            .get("mirror-shard")
+            .expect("mirror shards are listed");
+        assert_eq!(shard.category, CurrencyCategory::Shard);
+
+        assert!(catalogue.get("offer-gift").is_none());
+    }
+
+    #[test]
+    fn duplicate_tags_are_rejected() {
+        let json = r#"[
+            {"tag": "exalted", "name": "Exalted Orb", "category": "currency", "aliases": ["exa"]},
+            {"tag": "exa", "name": "Exa", "category": "currency"}
+        ]"#;
+
+        assert!(matches!(
+            CurrencyCatalogue::from_json(json),
+            Err(CatalogueError::DuplicateTag(tag)) if tag == "exa"
+        ));
+    }
+}
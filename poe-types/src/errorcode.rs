@@ -87,7 +87,7 @@ mod tests {
             ApiErrorCode::try_from(10).unwrap()
         );
 
-        assert_eq!(true, ApiErrorCode::try_from(100).is_err());
+        assert!(ApiErrorCode::try_from(100).is_err());
         assert_eq!(
             ApiError::UnknownApiErrorCode(100),
             ApiErrorCode::try_from(100).unwrap_err()
@@ -1,3 +1,5 @@
+use core::fmt;
+
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Default, Clone, Deserialize, Serialize)]
@@ -35,3 +37,242 @@ pub struct LeagueAccount {
 pub struct AtlasPassives {
     pub hashes: Vec<usize>,
 }
+
+/// Rule ids of the leagues endpoint
+const HARDCORE_RULE: &str = "Hardcore";
+const SOLO_SELF_FOUND_RULE: &str = "NoParties";
+const RUTHLESS_RULE: &str = "HardMode";
+
+/// LeagueKind is how long a league runs for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LeagueKind {
+    /// Standard and its hardcore, SSF and ruthless versions, which always run
+    Permanent,
+    /// a league running for a few months, along with its hardcore, SSF and ruthless versions
+    Challenge,
+    /// a short event league
+    Event,
+}
+
+impl fmt::Display for LeagueKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LeagueKind::Permanent => write!(f, "permanent"),
+            LeagueKind::Challenge => write!(f, "challenge"),
+            LeagueKind::Event => write!(f, "event"),
+        }
+    }
+}
+
+/// LeagueIdentity is a league split into the league it is a version of and its modifiers, so
+/// `HC SSF Necropolis` is the hardcore SSF version of `Necropolis`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LeagueIdentity {
+    pub name: String,
+    /// name without the modifiers, `Standard` for every permanent league
+    pub base: String,
+    pub kind: LeagueKind,
+    pub hardcore: bool,
+    pub solo_self_found: bool,
+    pub ruthless: bool,
+    pub private: bool,
+}
+
+impl LeagueIdentity {
+    /// Reads a league's identity from its name, as stashes only name their league. Private
+    /// leagues are suffixed with their id, e.g. `My League (PL12345)`
+    pub fn from_name(name: &str) -> Self {
+        let mut identity = Self {
+            name: name.to_owned(),
+            base: String::new(),
+            kind: LeagueKind::Challenge,
+            hardcore: false,
+            solo_self_found: false,
+            ruthless: false,
+            private: false,
+        };
+
+        let mut rest = name.trim();
+        if let Some(i) = rest.rfind("(PL").filter(|_| rest.ends_with(')')) {
+            identity.private = true;
+            rest = rest[..i].trim_end();
+        }
+
+        let mut base = Vec::new();
+        for word in rest.split_whitespace() {
+            match word.to_ascii_lowercase().as_str() {
+                "hardcore" | "hc" => identity.hardcore = true,
+                "ssf" => identity.solo_self_found = true,
+                "ruthless" => identity.ruthless = true,
+                _ => base.push(word),
+            }
+        }
+
+        identity.base = match base.is_empty() {
+            true => "Standard".to_owned(),
+            false => base.join(" "),
+        };
+        if identity.base == "Standard" && !identity.private {
+            identity.kind = LeagueKind::Permanent;
+        }
+
+        identity
+    }
+
+    /// Reads a league's identity from the leagues endpoint, where its rules are known
+    pub fn from_league(league: &League) -> Self {
+        let mut identity = Self::from_name(&league.id);
+
+        if let Some(rules) = &league.rules {
+            let has_rule = |id: &str| rules.iter().any(|r| r.id == id);
+
+            identity.hardcore = has_rule(HARDCORE_RULE);
+            identity.solo_self_found = has_rule(SOLO_SELF_FOUND_RULE);
+            identity.ruthless = has_rule(RUTHLESS_RULE);
+        }
+        if league.event.unwrap_or(false) && identity.kind != LeagueKind::Permanent {
+            identity.kind = LeagueKind::Event;
+        }
+
+        identity
+    }
+
+    /// Whether this is the trade league of a challenge league, without any modifiers
+    pub fn is_main_challenge(&self) -> bool {
+        self.kind == LeagueKind::Challenge
+            && !(self.hardcore || self.solo_self_found || self.ruthless || self.private)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{League, LeagueIdentity, LeagueKind, LeagueRule};
+
+    #[test]
+    fn identity_from_name() {
+        let cases = [
+            // name, base, kind, hardcore, ssf, ruthless, private
+            (
+                "Standard",
+                "Standard",
+                LeagueKind::Permanent,
+                false,
+                false,
+                false,
+                false,
+            ),
+            (
+                "Hardcore",
+                "Standard",
+                LeagueKind::Permanent,
+                true,
+                false,
+                false,
+                false,
+            ),
+            (
+                "HC SSF Ruthless",
+                "Standard",
+                LeagueKind::Permanent,
+                true,
+                true,
+                true,
+                false,
+            ),
+            (
+                "Necropolis",
+                "Necropolis",
+                LeagueKind::Challenge,
+                false,
+                false,
+                false,
+                false,
+            ),
+            (
+                "Hardcore Necropolis",
+                "Necropolis",
+                LeagueKind::Challenge,
+                true,
+                false,
+                false,
+                false,
+            ),
+            (
+                "SSF Necropolis",
+                "Necropolis",
+                LeagueKind::Challenge,
+                false,
+                true,
+                false,
+                false,
+            ),
+            (
+                "Ruthless Necropolis",
+                "Necropolis",
+                LeagueKind::Challenge,
+                false,
+                false,
+                true,
+                false,
+            ),
+            (
+                "Settlers of Kalguur",
+                "Settlers of Kalguur",
+                LeagueKind::Challenge,
+                false,
+                false,
+                false,
+                false,
+            ),
+            (
+                "Mapping Fun (PL41234)",
+                "Mapping Fun",
+                LeagueKind::Challenge,
+                false,
+                false,
+                false,
+                true,
+            ),
+        ];
+
+        for (name, base, kind, hardcore, solo_self_found, ruthless, private) in cases {
+            assert_eq!(
+                LeagueIdentity::from_name(name),
+                LeagueIdentity {
+                    name: name.to_owned(),
+                    base: base.to_owned(),
+                    kind,
+                    hardcore,
+                    solo_self_found,
+                    ruthless,
+                    private,
+                },
+                "identifying {name}"
+            );
+        }
+
+        assert!(LeagueIdentity::from_name("Necropolis").is_main_challenge());
+        assert!(!LeagueIdentity::from_name("SSF Necropolis").is_main_challenge());
+        assert!(!LeagueIdentity::from_name("Standard").is_main_challenge());
+    }
+
+    #[test]
+    fn identity_from_rules() {
+        let rule = |id: &str| LeagueRule {
+            id: id.to_owned(),
+            name: id.to_owned(),
+            description: None,
+        };
+        let league = League {
+            id: "Phrecia".to_owned(),
+            rules: Some(vec![rule("Hardcore"), rule("NoParties")]),
+            event: Some(true),
+            ..Default::default()
+        };
+
+        let identity = LeagueIdentity::from_league(&league);
+        assert_eq!(identity.kind, LeagueKind::Event);
+        assert!(identity.hardcore && identity.solo_self_found && !identity.ruthless);
+    }
+}
@@ -1,5 +1,6 @@
 pub mod account;
 pub mod character;
+pub mod currency;
 pub mod errorcode;
 pub mod filter;
 pub mod guild;
@@ -1,4 +1,4 @@
-use std::str::FromStr;
+use std::{fmt, str::FromStr};
 
 use chrono::NaiveDate;
 use serde::{Deserialize, Serialize};
@@ -51,19 +51,19 @@ impl FromStr for League {
     }
 }
 
-impl ToString for League {
-    fn to_string(&self) -> String {
+impl fmt::Display for League {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            League::Crucible => "Crucible".to_owned(),
-            League::Sanctum => "Sanctum".to_owned(),
-            League::Kalandra => "Kalandra".to_owned(),
-            League::Sentinel => "Sentinel".to_owned(),
-            League::Archnemesis => "Archnemesis".to_owned(),
-            League::Scourge => "Scourge".to_owned(),
-            League::Expedition => "Expedition".to_owned(),
-            League::Ultimatum => "Ultimatum".to_owned(),
-            League::Ritual => "Ritual".to_owned(),
-            League::Heist => "Heist".to_owned(),
+            League::Crucible => write!(f, "Crucible"),
+            League::Sanctum => write!(f, "Sanctum"),
+            League::Kalandra => write!(f, "Kalandra"),
+            League::Sentinel => write!(f, "Sentinel"),
+            League::Archnemesis => write!(f, "Archnemesis"),
+            League::Scourge => write!(f, "Scourge"),
+            League::Expedition => write!(f, "Expedition"),
+            League::Ultimatum => write!(f, "Ultimatum"),
+            League::Ritual => write!(f, "Ritual"),
+            League::Heist => write!(f, "Heist"),
         }
     }
 }
@@ -90,12 +90,12 @@ impl FromStr for Confidence {
     }
 }
 
-impl ToString for Confidence {
-    fn to_string(&self) -> String {
+impl fmt::Display for Confidence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Confidence::High => "High".to_owned(),
-            Confidence::Medium => "Medium".to_owned(),
-            Confidence::Low => "Low".to_owned(),
+            Confidence::High => write!(f, "High"),
+            Confidence::Medium => write!(f, "Medium"),
+            Confidence::Low => write!(f, "Low"),
         }
     }
 }
@@ -125,12 +125,12 @@ impl FromStr for ItemLinks {
     }
 }
 
-impl ToString for ItemLinks {
-    fn to_string(&self) -> String {
+impl fmt::Display for ItemLinks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            ItemLinks::OneToFour => "1-4 links".to_owned(),
-            ItemLinks::Five => "5 links".to_owned(),
-            ItemLinks::Six => "6 links".to_owned(),
+            ItemLinks::OneToFour => write!(f, "1-4 links"),
+            ItemLinks::Five => write!(f, "5 links"),
+            ItemLinks::Six => write!(f, "6 links"),
         }
     }
 }
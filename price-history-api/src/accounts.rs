@@ -0,0 +1,115 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use axum_extra::extract::Query;
+use serde::{Deserialize, Serialize};
+use time::{Duration, OffsetDateTime};
+
+use crate::{db::AccountSummaryRow, AppState};
+
+const DEFAULT_SUMMARY_DAYS: u32 = 7;
+const MAX_SUMMARY_DAYS: u32 = 90;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountSummaryQuery {
+    league: Option<String>,
+    /// days listing events are counted over, counting today
+    days: Option<u32>,
+}
+
+/// AccountSummary is the trading activity of an account in a league. Activity is taken from its
+/// public stashes, so items sold from private tabs or traded directly aren't seen
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountSummary {
+    account: String,
+    league: String,
+    /// listings up now, see [`AccountSummaryRow::active_listings`] for how far back it goes
+    active_listings: i64,
+    listed_value: f64,
+    /// start of the window the counts below are over
+    since: i64,
+    listed: u64,
+    removed: u64,
+    unpriced: u64,
+    repriced: u64,
+    distinct_items: u64,
+    /// share of the listings up during the window which were removed by now
+    churn_rate: f64,
+}
+
+impl AccountSummary {
+    pub fn from_row(row: AccountSummaryRow, since: OffsetDateTime) -> Self {
+        Self {
+            account: row.account_name,
+            league: row.league,
+            active_listings: row.active_listings,
+            listed_value: row.listed_value,
+            since: since.unix_timestamp(),
+            listed: row.recent_listed,
+            removed: row.recent_removed,
+            unpriced: row.recent_unpriced,
+            repriced: row.recent_repriced,
+            distinct_items: row.recent_items,
+            churn_rate: row.churn_rate,
+        }
+    }
+}
+
+/// Start of the day `days` days ago, counting today
+fn window_start(now: OffsetDateTime, days: u32) -> OffsetDateTime {
+    let day = now.date() - Duration::days(days.saturating_sub(1) as i64);
+
+    day.midnight().assume_utc()
+}
+
+pub async fn account_summary(
+    Path(account): Path<String>,
+    Query(params): Query<AccountSummaryQuery>,
+    State(state): State<AppState>,
+) -> anyhow::Result<impl IntoResponse, StatusCode> {
+    let days = params.days.unwrap_or(DEFAULT_SUMMARY_DAYS);
+    if !(1..=MAX_SUMMARY_DAYS).contains(&days) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let league = match params.league {
+        Some(league) => league,
+        None => state.current_league.get(state.db.as_ref()).await,
+    };
+    let since = window_start(OffsetDateTime::now_utc(), days);
+
+    match state.db.account_summary(&account, &league, since).await {
+        Ok(Some(row)) => Ok(Json(AccountSummary::from_row(row, since))),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            tracing::error!("failed querying account summary: {e}");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use time_macros::datetime;
+
+    use super::{window_start, AccountSummary};
+    use crate::db::AccountSummaryRow;
+
+    #[test]
+    fn summary_window_counts_today() {
+        let since = window_start(datetime!(2024-04-10 13:45 UTC), 7);
+        assert_eq!(since, datetime!(2024-04-04 00:00 UTC));
+        assert_eq!(
+            window_start(datetime!(2024-04-10 13:45 UTC), 1),
+            datetime!(2024-04-10 00:00 UTC)
+        );
+
+        let summary = AccountSummary::from_row(AccountSummaryRow::default(), since);
+        assert_eq!(summary.since, 1712188800);
+    }
+}
@@ -0,0 +1,52 @@
+use axum::{http::StatusCode, response::IntoResponse, Json};
+use axum_extra::extract::Query;
+use poe_types::currency::{Currency, CurrencyCatalogue, CurrencyCategory};
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+pub struct CurrencyQuery {
+    category: Option<CurrencyCategory>,
+}
+
+/// Lists the currencies listings can be priced in, `listed_currency` in price history is the tag
+/// of one of these
+pub async fn currencies(
+    Query(params): Query<CurrencyQuery>,
+) -> anyhow::Result<impl IntoResponse, StatusCode> {
+    Ok(Json(filter_currencies(
+        CurrencyCatalogue::embedded(),
+        params.category,
+    )))
+}
+
+fn filter_currencies(
+    catalogue: &CurrencyCatalogue,
+    category: Option<CurrencyCategory>,
+) -> Vec<&Currency> {
+    catalogue
+        .currencies()
+        .iter()
+        .filter(|c| category.is_none_or(|category| c.category == category))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use poe_types::currency::{CurrencyCatalogue, CurrencyCategory};
+
+    use super::filter_currencies;
+
+    #[test]
+    fn currencies_filter_by_category() {
+        let catalogue = CurrencyCatalogue::embedded();
+
+        let all = filter_currencies(catalogue, None);
+        assert_eq!(all.len(), catalogue.currencies().len());
+
+        let scarabs = filter_currencies(catalogue, Some(CurrencyCategory::Scarab));
+        assert!(!scarabs.is_empty());
+        assert!(scarabs
+            .iter()
+            .all(|c| c.category == CurrencyCategory::Scarab));
+    }
+}
@@ -1,7 +1,10 @@
-use std::env;
+use std::{env, fmt, sync::OnceLock};
 
 use anyhow::anyhow;
+use async_trait::async_trait;
 use clickhouse::Row;
+use ledger_service::schema;
+use poe_types::{currency::CurrencyCatalogue, league::LeagueKind};
 use serde::{Deserialize, Serialize};
 use time::OffsetDateTime;
 
@@ -55,15 +58,15 @@ impl TryFrom<String> for ChInterval {
     }
 }
 
-impl ToString for ChInterval {
-    fn to_string(&self) -> String {
+impl fmt::Display for ChInterval {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            ChInterval::Minute(x) => format!("{x} minute"),
-            ChInterval::Hour(x) => format!("{x} hour"),
-            ChInterval::Day(x) => format!("{x} day"),
-            ChInterval::Week(x) => format!("{x} week"),
-            ChInterval::Month(x) => format!("{x} month"),
-            ChInterval::Year(x) => format!("{x} year"),
+            ChInterval::Minute(x) => write!(f, "{x} minute"),
+            ChInterval::Hour(x) => write!(f, "{x} hour"),
+            ChInterval::Day(x) => write!(f, "{x} day"),
+            ChInterval::Week(x) => write!(f, "{x} week"),
+            ChInterval::Month(x) => write!(f, "{x} month"),
+            ChInterval::Year(x) => write!(f, "{x} year"),
         }
     }
 }
@@ -80,6 +83,10 @@ impl ChTimeframe {
     }
 }
 
+/// Oldest schema version, as recorded by `stash-processor migrate`, with every table and column
+/// queried here. Bump it along with queries relying on a newer migration
+pub const SCHEMA_VERSION: u32 = 18;
+
 #[derive(Clone)]
 pub struct ClickhouseDatabase {
     client: clickhouse::Client,
@@ -94,6 +101,56 @@ pub struct PriceHistoryBucketRow {
     pub listed_currency: String,
 }
 
+/// LeagueRow is a league in the registry the stash processor keeps of every league it has seen
+#[derive(Debug, Clone, Serialize, Deserialize, Row)]
+pub struct LeagueRow {
+    pub name: String,
+    pub base: String,
+    pub kind: String,
+    pub hardcore: bool,
+    pub solo_self_found: bool,
+    pub ruthless: bool,
+    pub private: bool,
+    #[serde(with = "clickhouse::serde::time::datetime")]
+    pub first_seen: OffsetDateTime,
+    #[serde(with = "clickhouse::serde::time::datetime")]
+    pub last_seen: OffsetDateTime,
+}
+
+impl LeagueRow {
+    /// Whether this is the trade league of a challenge league, without any modifiers
+    pub fn is_main_challenge(&self) -> bool {
+        self.kind == LeagueKind::Challenge.to_string()
+            && !(self.hardcore || self.solo_self_found || self.ruthless || self.private)
+    }
+}
+
+/// AccountSummaryRow is the listing activity of an account in a league, from the aggregates the
+/// stash processor's listing events are rolled up into
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Row)]
+pub struct AccountSummaryRow {
+    pub account_name: String,
+    pub league: String,
+    /// listings up now, counting every listing event since accounts were tracked. Removals of
+    /// listings from before then can outnumber the listings seen, so it is clamped at 0 and
+    /// undercounts accounts which were already trading when tracking started
+    pub active_listings: i64,
+    /// chaos value of the listings up now, at the rates they were listed or repriced at
+    pub listed_value: f64,
+    /// listing events since the start of the window
+    pub recent_listed: u64,
+    /// listings taken down by selling, or moving the item out of the public stash
+    pub recent_removed: u64,
+    /// listings whose item is still in the stash but no longer priced
+    pub recent_unpriced: u64,
+    pub recent_repriced: u64,
+    /// distinct items listed or repriced since the start of the window
+    pub recent_items: u64,
+    /// share of the listings up during the window which were removed by now,
+    /// `recent_removed / (active_listings + recent_removed)`
+    pub churn_rate: f64,
+}
+
 impl ClickhouseDatabase {
     pub async fn new() -> Self {
         let url = env::var("CLICKHOUSE_URL").unwrap_or("http://localhost:8123".to_string());
@@ -119,48 +176,201 @@ impl ClickhouseDatabase {
             std::process::exit(1);
         }
 
+        // refuse to serve from a schema older than the queries expect
+        if let Err(e) = schema::check(&client, SCHEMA_VERSION).await {
+            tracing::error!("{e}");
+            tracing::error!("exiting!");
+
+            std::process::exit(1);
+        }
+
         tracing::info!("connected to DB!");
 
         Self { client }
     }
+}
+
+/// Maps the currency of listings stored under an alias to its canonical tag, so listings
+/// stored before the currency catalogue, like `exa` for `exalted`, are in the same series
+fn canonical_currency() -> &'static str {
+    static EXPR: OnceLock<String> = OnceLock::new();
 
-    pub async fn query_ledger_by_name(
+    EXPR.get_or_init(|| {
+        let (aliases, tags): (Vec<_>, Vec<_>) = CurrencyCatalogue::embedded()
+            .currencies()
+            .iter()
+            .flat_map(|c| {
+                c.aliases
+                    .iter()
+                    .map(|a| (format!("'{a}'"), format!("'{}'", c.tag)))
+            })
+            .unzip();
+
+        match aliases.is_empty() {
+            true => "listed_currency".to_owned(),
+            false => format!(
+                "transform(listed_currency, [{}], [{}], listed_currency)",
+                aliases.join(","),
+                tags.join(",")
+            ),
+        }
+    })
+}
+
+/// LedgerQuery selects the listings price history is built from.
+///
+/// Prices are per unit and quantiles are weighted by the number of units listed, so a stack of
+/// 5000 bulk listed currency counts for more than a single unit listed at an outlying price.
+///
+/// When `normalized` is set, prices are the chaos equivalent of each listing at the time it was
+/// ingested, so listings in every currency are grouped into the same bucket.
+///
+/// When `variant` is set, only listings of that variant are included, e.g. `6 links` or `21/23c`.
+/// An empty variant selects listings without one.
+///
+/// When `exclude_outliers` is set, listings the stash processor scored as likely price fixing
+/// are left out.
+pub struct LedgerQuery {
+    pub name: String,
+    pub league: String,
+    pub interval: ChInterval,
+    pub quantiles: Vec<f64>,
+    pub timeframe: ChTimeframe,
+    pub normalized: bool,
+    pub category: Option<String>,
+    pub variant: Option<String>,
+    pub exclude_outliers: bool,
+}
+
+/// PriceHistoryStore is the storage backend price history is queried from
+#[async_trait]
+pub trait PriceHistoryStore: Send + Sync {
+    async fn query_ledger_by_name(
         &self,
-        name: &str,
+        query: LedgerQuery,
+    ) -> anyhow::Result<Vec<PriceHistoryBucketRow>>;
+
+    /// Every league listings have been seen in
+    async fn leagues(&self) -> anyhow::Result<Vec<LeagueRow>>;
+
+    /// Listing activity of an account in a league, with listing events counted from the day of
+    /// `since`. None when the account has no listing events in the league
+    async fn account_summary(
+        &self,
+        account: &str,
         league: &str,
-        interval: ChInterval,
-        quantiles: Vec<f64>,
-        timeframe: ChTimeframe,
+        since: OffsetDateTime,
+    ) -> anyhow::Result<Option<AccountSummaryRow>>;
+}
+
+#[async_trait]
+impl PriceHistoryStore for ClickhouseDatabase {
+    async fn query_ledger_by_name(
+        &self,
+        query: LedgerQuery,
     ) -> anyhow::Result<Vec<PriceHistoryBucketRow>> {
-        let quants = quantiles
+        let quants = query
+            .quantiles
             .iter()
             .map(|x| x.to_string())
             .collect::<Vec<String>>()
             .join(",");
 
-        let ChTimeframe { start, end } = timeframe;
+        let ChTimeframe { start, end } = query.timeframe;
+
+        let (price, currency, price_filter) = match query.normalized {
+            true => ("normalized_price", "'chaos'", "AND normalized_price > 0"),
+            false => ("listed_price", canonical_currency(), ""),
+        };
+        let category_filter = match query.category {
+            Some(_) => "AND category = ?",
+            None => "",
+        };
+        let variant_filter = match query.variant {
+            Some(_) => "AND variant = ?",
+            None => "",
+        };
+
+        let outlier_filter = match query.exclude_outliers {
+            true => "AND NOT is_outlier",
+            false => "",
+        };
 
         let raw_query = format!(
             "SELECT
                 name as item_name,
                 toStartOfInterval(created_at, INTERVAL {}) AS interval_bucket,
-                arrayZip([{quants}], quantiles({quants})(listed_price)) AS price_by_quantile,
-                listed_currency
-            FROM ledger.listings
-            WHERE name ilike ? AND league = ? AND created_at BETWEEN {start} AND {end}
+                arrayZip([{quants}], quantilesExactWeighted({quants})({price}, greatest(stack_size, 1))) AS price_by_quantile,
+                {currency} AS listed_currency
+            FROM ledger.listings FINAL
+            WHERE name ilike ? AND league = ? AND created_at BETWEEN {start} AND {end} {price_filter} {category_filter} {variant_filter} {outlier_filter}
             GROUP BY interval_bucket, name, listed_currency
             ORDER BY interval_bucket",
-            interval.to_string()
+            query.interval
         );
 
-        let rows = self
+        let mut ch_query = self
             .client
             .query(&raw_query)
-            .bind(name)
-            .bind(league)
-            .fetch_all::<PriceHistoryBucketRow>()
+            .bind(&query.name)
+            .bind(&query.league);
+        if let Some(category) = &query.category {
+            ch_query = ch_query.bind(category);
+        }
+        if let Some(variant) = &query.variant {
+            ch_query = ch_query.bind(variant);
+        }
+
+        let rows = ch_query.fetch_all::<PriceHistoryBucketRow>().await?;
+
+        Ok(rows)
+    }
+
+    async fn leagues(&self) -> anyhow::Result<Vec<LeagueRow>> {
+        let rows = self
+            .client
+            .query("SELECT ?fields FROM ledger.leagues FINAL ORDER BY first_seen")
+            .fetch_all::<LeagueRow>()
             .await?;
 
         Ok(rows)
     }
+
+    async fn account_summary(
+        &self,
+        account: &str,
+        league: &str,
+        since: OffsetDateTime,
+    ) -> anyhow::Result<Option<AccountSummaryRow>> {
+        let row = self
+            .client
+            .query(
+                "SELECT
+                    account_name,
+                    league,
+                    greatest(toInt64(sum(listed)) - toInt64(sum(removed)) - toInt64(sum(unpriced)), 0) AS active_listings,
+                    greatest(sum(value_change), 0) AS listed_value,
+                    sumIf(listed, day >= toDate(fromUnixTimestamp(?))) AS recent_listed,
+                    sumIf(removed, day >= toDate(fromUnixTimestamp(?))) AS recent_removed,
+                    sumIf(unpriced, day >= toDate(fromUnixTimestamp(?))) AS recent_unpriced,
+                    sumIf(repriced, day >= toDate(fromUnixTimestamp(?))) AS recent_repriced,
+                    uniqMergeIf(items, day >= toDate(fromUnixTimestamp(?))) AS recent_items,
+                    if(active_listings + recent_removed > 0,
+                        recent_removed / (active_listings + recent_removed), 0) AS churn_rate
+                FROM ledger.account_activity
+                WHERE account_name = ? AND league = ?
+                GROUP BY account_name, league",
+            )
+            .bind(since.unix_timestamp())
+            .bind(since.unix_timestamp())
+            .bind(since.unix_timestamp())
+            .bind(since.unix_timestamp())
+            .bind(since.unix_timestamp())
+            .bind(account)
+            .bind(league)
+            .fetch_optional::<AccountSummaryRow>()
+            .await?;
+
+        Ok(row)
+    }
 }
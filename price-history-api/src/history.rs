@@ -4,13 +4,12 @@ use serde::Deserialize;
 use time::{Duration, OffsetDateTime};
 
 use crate::{
-    db::{are_valid_quantiles, ChInterval, ChTimeframe},
+    db::{are_valid_quantiles, ChInterval, ChTimeframe, LedgerQuery},
     AppState,
 };
 
 const DEFAULT_INTERVAL: ChInterval = ChInterval::Hour(6);
 const DEFAULT_HISTORY_DURATION: Duration = Duration::days(7);
-const DEFAULT_LEAGUE: &str = "Necropolis";
 
 #[derive(Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -22,13 +21,20 @@ pub struct PriceHistoryQuery {
     quantiles: Option<Vec<String>>,
     start_time: Option<i64>,
     end_time: Option<i64>,
+    normalized: Option<bool>,
+    category: Option<String>,
+    variant: Option<String>,
+    exclude_outliers: Option<bool>,
 }
 
 pub async fn history_by_name(
     Query(params): Query<PriceHistoryQuery>,
     State(state): State<AppState>,
 ) -> anyhow::Result<impl IntoResponse, StatusCode> {
-    let league = params.league.unwrap_or(DEFAULT_LEAGUE.to_owned());
+    let league = match params.league {
+        Some(league) => league,
+        None => state.current_league.get(state.db.as_ref()).await,
+    };
 
     let interval = match (params.interval_amount, params.interval_unit) {
         (None, None) => Ok(DEFAULT_INTERVAL),
@@ -72,7 +78,17 @@ pub async fn history_by_name(
 
     match state
         .db
-        .query_ledger_by_name(&params.item, &league, interval, quantiles, timeframe)
+        .query_ledger_by_name(LedgerQuery {
+            name: params.item,
+            league,
+            interval,
+            quantiles,
+            timeframe,
+            normalized: params.normalized.unwrap_or(false),
+            category: params.category,
+            variant: params.variant,
+            exclude_outliers: params.exclude_outliers.unwrap_or(false),
+        })
         .await
     {
         Ok(results) => Ok(Json(results)),
@@ -1,27 +1,54 @@
-use axum::{http::StatusCode, response::IntoResponse, Json};
-use serde::Serialize;
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use axum_extra::extract::Query;
+use serde::{Deserialize, Serialize};
 use time::OffsetDateTime;
-use time_macros::datetime;
+
+use crate::{
+    db::{LeagueRow, PriceHistoryStore},
+    AppState,
+};
+
+/// Leagues without listings for longer than this have ended
+const LEAGUE_ENDED_AFTER: time::Duration = time::Duration::days(1);
+/// How long the current challenge league is kept before reading the registry again
+const CURRENT_LEAGUE_TTL: Duration = Duration::from_secs(300);
+/// League used until a challenge league has been seen
+const FALLBACK_LEAGUE: &str = "Standard";
 
 #[derive(Default, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct LeagueInfo {
     name: String,
-    start_time: Option<i64>,
+    base: String,
+    kind: String,
+    hardcore: bool,
+    solo_self_found: bool,
+    ruthless: bool,
+    private: bool,
+    /// When listings in the league were first seen, which can be later than the league started
+    first_seen: Option<i64>,
     end_time: Option<i64>,
 }
 
 impl LeagueInfo {
-    pub fn new(name: &str, start: Option<OffsetDateTime>, end: Option<OffsetDateTime>) -> Self {
+    pub fn new(
+        name: &str,
+        first_seen: Option<OffsetDateTime>,
+        end: Option<OffsetDateTime>,
+    ) -> Self {
         let mut info = Self {
             name: name.to_owned(),
-            start_time: Default::default(),
-            end_time: Default::default(),
+            ..Default::default()
         };
 
-        if let Some(s) = start {
+        if let Some(s) = first_seen {
             let st = s.unix_timestamp();
-            info.start_time = Some(st);
+            info.first_seen = Some(st);
         }
 
         if let Some(e) = end {
@@ -31,33 +58,120 @@ impl LeagueInfo {
 
         info
     }
+
+    /// Describes a league in the registry, leagues which aren't permanent end when they were last
+    /// seen once they haven't been seen for a day
+    pub fn from_row(row: &LeagueRow, now: OffsetDateTime) -> Self {
+        let ended = row.kind != "permanent" && now - row.last_seen > LEAGUE_ENDED_AFTER;
+
+        Self {
+            base: row.base.clone(),
+            kind: row.kind.clone(),
+            hardcore: row.hardcore,
+            solo_self_found: row.solo_self_found,
+            ruthless: row.ruthless,
+            private: row.private,
+            ..Self::new(
+                &row.name,
+                Some(row.first_seen),
+                ended.then_some(row.last_seen),
+            )
+        }
+    }
 }
 
-pub async fn league_info() -> anyhow::Result<impl IntoResponse, StatusCode> {
-    let leagues: Vec<LeagueInfo> = vec![
-        LeagueInfo::new("Standard", None, None),
-        LeagueInfo::new(
-            "Affliction",
-            Some(datetime!(2023-12-08 19:00 UTC)),
-            Some(datetime!(2023-03-26 21:00 UTC)),
-        ),
-        LeagueInfo::new("Necropolis", Some(datetime!(2024-03-29 18:00 UTC)), None),
-    ];
+/// The newest challenge league without modifiers
+pub fn current_challenge_league(leagues: &[LeagueRow]) -> Option<&LeagueRow> {
+    leagues
+        .iter()
+        .filter(|l| l.is_main_challenge())
+        .max_by_key(|l| l.first_seen)
+}
+
+/// CurrentLeague is the league price history defaults to, the current challenge league
+#[derive(Clone, Default)]
+pub struct CurrentLeague {
+    cached: Arc<Mutex<Option<(Instant, String)>>>,
+}
+
+impl CurrentLeague {
+    pub async fn get(&self, db: &dyn PriceHistoryStore) -> String {
+        if let Some((at, league)) = &*self.cached.lock().unwrap() {
+            if at.elapsed() < CURRENT_LEAGUE_TTL {
+                return league.clone();
+            }
+        }
 
-    Ok(Json(leagues))
+        let league = match db.leagues().await {
+            Ok(leagues) => current_challenge_league(&leagues)
+                .map_or(FALLBACK_LEAGUE, |l| l.name.as_str())
+                .to_owned(),
+            Err(e) => {
+                tracing::error!("failed querying leagues: {e}");
+                return FALLBACK_LEAGUE.to_owned();
+            }
+        };
+        *self.cached.lock().unwrap() = Some((Instant::now(), league.clone()));
+
+        league
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LeagueQuery {
+    include_private: Option<bool>,
+    include_ended: Option<bool>,
+}
+
+/// Lists the leagues listings have been seen in, private and ended leagues are only included when
+/// asked for
+pub async fn league_info(
+    Query(params): Query<LeagueQuery>,
+    State(state): State<AppState>,
+) -> anyhow::Result<impl IntoResponse, StatusCode> {
+    match state.db.leagues().await {
+        Ok(leagues) => Ok(Json(filter_leagues(
+            &leagues,
+            OffsetDateTime::now_utc(),
+            params.include_private.unwrap_or(false),
+            params.include_ended.unwrap_or(false),
+        ))),
+        Err(e) => {
+            tracing::error!("failed querying leagues: {e}");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+fn filter_leagues(
+    leagues: &[LeagueRow],
+    now: OffsetDateTime,
+    include_private: bool,
+    include_ended: bool,
+) -> Vec<LeagueInfo> {
+    leagues
+        .iter()
+        .filter(|l| include_private || !l.private)
+        .map(|l| LeagueInfo::from_row(l, now))
+        .filter(|l| include_ended || l.end_time.is_none())
+        .collect()
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::league::LeagueInfo;
+    use crate::{
+        db::LeagueRow,
+        league::{current_challenge_league, filter_leagues, LeagueInfo},
+    };
     use time_macros::datetime;
 
     #[test]
-    fn league_start_timestamp() {
+    fn league_first_seen_timestamp() {
         let league = LeagueInfo::new("Affliction", Some(datetime!(2023-12-08 19:00 UTC)), None);
 
         assert_eq!(league.name, "Affliction");
-        assert_eq!(league.start_time, Some(1702062000));
+        assert_eq!(league.first_seen, Some(1702062000));
         assert_eq!(league.end_time, None);
     }
 
@@ -70,7 +184,103 @@ mod tests {
         );
 
         assert_eq!(league.name, "Affliction");
-        assert_eq!(league.start_time, Some(1702062000));
+        assert_eq!(league.first_seen, Some(1702062000));
         assert_eq!(league.end_time, Some(1711324800));
     }
+
+    #[test]
+    fn newest_challenge_league_is_current() {
+        let league = |name: &str, kind: &str, hardcore, first_seen, last_seen| LeagueRow {
+            name: name.to_owned(),
+            base: name.to_owned(),
+            kind: kind.to_owned(),
+            hardcore,
+            solo_self_found: false,
+            ruthless: false,
+            private: false,
+            first_seen,
+            last_seen,
+        };
+        let leagues = [
+            league(
+                "Standard",
+                "permanent",
+                false,
+                datetime!(2023-01-01 00:00 UTC),
+                datetime!(2024-04-10 00:00 UTC),
+            ),
+            league(
+                "Affliction",
+                "challenge",
+                false,
+                datetime!(2023-12-08 19:00 UTC),
+                datetime!(2024-03-25 00:00 UTC),
+            ),
+            league(
+                "Necropolis",
+                "challenge",
+                false,
+                datetime!(2024-03-29 18:00 UTC),
+                datetime!(2024-04-10 00:00 UTC),
+            ),
+            league(
+                "Hardcore Settlers",
+                "challenge",
+                true,
+                datetime!(2024-07-26 20:00 UTC),
+                datetime!(2024-07-27 00:00 UTC),
+            ),
+        ];
+
+        let current = current_challenge_league(&leagues).unwrap();
+        assert_eq!(current.name, "Necropolis");
+
+        let now = datetime!(2024-04-10 12:00 UTC);
+        assert_eq!(LeagueInfo::from_row(&leagues[0], now).end_time, None);
+        assert_eq!(
+            LeagueInfo::from_row(&leagues[1], now).end_time,
+            Some(datetime!(2024-03-25 00:00 UTC).unix_timestamp())
+        );
+        assert_eq!(LeagueInfo::from_row(&leagues[2], now).end_time, None);
+    }
+
+    #[test]
+    fn private_and_ended_leagues_are_filtered() {
+        let league = |name: &str, private, last_seen| LeagueRow {
+            name: name.to_owned(),
+            base: name.to_owned(),
+            kind: "challenge".to_owned(),
+            hardcore: false,
+            solo_self_found: false,
+            ruthless: false,
+            private,
+            first_seen: datetime!(2023-12-08 19:00 UTC),
+            last_seen,
+        };
+        let leagues = [
+            league("Affliction", false, datetime!(2024-03-25 00:00 UTC)),
+            league("Necropolis", false, datetime!(2024-04-10 00:00 UTC)),
+            league(
+                "Necropolis (PL12345)",
+                true,
+                datetime!(2024-04-10 00:00 UTC),
+            ),
+        ];
+        let now = datetime!(2024-04-10 12:00 UTC);
+        let names =
+            |leagues: Vec<LeagueInfo>| leagues.into_iter().map(|l| l.name).collect::<Vec<_>>();
+
+        assert_eq!(
+            names(filter_leagues(&leagues, now, false, false)),
+            ["Necropolis"]
+        );
+        assert_eq!(
+            names(filter_leagues(&leagues, now, true, false)),
+            ["Necropolis", "Necropolis (PL12345)"]
+        );
+        assert_eq!(
+            names(filter_leagues(&leagues, now, false, true)),
+            ["Affliction", "Necropolis"]
+        );
+    }
 }
@@ -0,0 +1,47 @@
+pub mod accounts;
+pub mod currency;
+pub mod db;
+pub mod history;
+pub mod league;
+
+use std::{sync::Arc, time::Duration};
+
+use axum::{routing::get, Router};
+use db::PriceHistoryStore;
+use league::CurrentLeague;
+use tower_http::{
+    cors::{self, CorsLayer},
+    timeout::TimeoutLayer,
+    trace::TraceLayer,
+};
+
+#[derive(Clone)]
+pub struct AppState {
+    db: Arc<dyn PriceHistoryStore>,
+    current_league: CurrentLeague,
+}
+
+impl AppState {
+    pub fn new(db: Arc<dyn PriceHistoryStore>) -> Self {
+        Self {
+            db,
+            current_league: CurrentLeague::default(),
+        }
+    }
+}
+
+pub fn router(state: AppState) -> Router {
+    Router::new()
+        .route("/history", get(history::history_by_name))
+        .route("/leagues", get(league::league_info))
+        .route("/currencies", get(currency::currencies))
+        .route("/accounts/:name/summary", get(accounts::account_summary))
+        .layer((
+            TraceLayer::new_for_http(),
+            TimeoutLayer::new(Duration::from_secs(10)),
+            CorsLayer::new()
+                .allow_origin(cors::Any)
+                .allow_methods(cors::Any),
+        ))
+        .with_state(state)
+}
@@ -1,42 +1,15 @@
-mod db;
-mod history;
-mod league;
+use std::{env, sync::Arc};
 
-use std::{env, time::Duration};
-
-use axum::{routing::get, Router};
-use db::ClickhouseDatabase;
+use price_history_api::{db::ClickhouseDatabase, router, AppState};
 use tokio::{net::TcpListener, signal};
-use tower_http::{
-    cors::{self, CorsLayer},
-    timeout::TimeoutLayer,
-    trace::TraceLayer,
-};
 use tracing_subscriber::{layer::SubscriberExt, EnvFilter, Registry};
 
-#[derive(Clone)]
-pub struct AppState {
-    db: ClickhouseDatabase,
-}
-
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     setup_logger();
 
-    let db = db::ClickhouseDatabase::new().await;
-    let state = AppState { db };
-
-    let app = Router::new()
-        .route("/history", get(history::history_by_name))
-        .route("/leagues", get(league::league_info))
-        .layer((
-            TraceLayer::new_for_http(),
-            TimeoutLayer::new(Duration::from_secs(10)),
-            CorsLayer::new()
-                .allow_origin(cors::Any)
-                .allow_methods(cors::Any),
-        ))
-        .with_state(state);
+    let db = ClickhouseDatabase::new().await;
+    let app = router(AppState::new(Arc::new(db)));
 
     let port = env::var("PORT")
         .unwrap_or("3000".to_owned())
@@ -0,0 +1,306 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    env,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use bytes::Bytes;
+use time::OffsetDateTime;
+use tokio::{sync::mpsc, task::JoinHandle};
+
+const DEFAULT_SEGMENT_MAX_BYTES: u64 = 256 * 1024 * 1024;
+const DEFAULT_COMPRESSION_LEVEL: i32 = 3;
+const ARCHIVE_QUEUE_SIZE: usize = 64;
+/// Number of recently archived change ids remembered, which covers any change id redelivered
+/// after a failed publish or a restart
+const RECENT_CHANGE_IDS: usize = 1024;
+
+/// A raw page of the river as returned by the public stash API
+pub struct RawPage {
+    pub change_id: String,
+    pub body: Bytes,
+}
+
+/// RiverArchive writes raw river pages to rotating segment files on local disk.
+///
+/// Every page is written as an independent zstd frame, so a whole segment can be decompressed
+/// as a stream of concatenated pages, or a single page can be read from the offset and length
+/// recorded for its change id in the segment's `.idx` file.
+///
+/// Pages of change ids archived recently, including by a previous run into the newest index, are
+/// skipped so replaying the archive doesn't process a change id twice.
+pub struct RiverArchive {
+    dir: PathBuf,
+    segment_max_bytes: u64,
+    compression_level: i32,
+    segment: Option<Segment>,
+    recent: RecentChangeIds,
+}
+
+/// The last `RECENT_CHANGE_IDS` change ids archived
+#[derive(Default)]
+struct RecentChangeIds {
+    ids: HashSet<String>,
+    order: VecDeque<String>,
+}
+
+struct Segment {
+    data: File,
+    index: File,
+    size: u64,
+}
+
+impl RiverArchive {
+    pub fn new(dir: impl Into<PathBuf>, segment_max_bytes: u64, compression_level: i32) -> Self {
+        let dir = dir.into();
+        let recent = match RecentChangeIds::from_newest_index(&dir) {
+            Ok(recent) => recent,
+            Err(e) => {
+                tracing::error!("failed reading the newest archive index: {e}");
+                RecentChangeIds::default()
+            }
+        };
+
+        Self {
+            dir,
+            segment_max_bytes,
+            compression_level,
+            segment: None,
+            recent,
+        }
+    }
+
+    /// Creates an archive if `ARCHIVE_DIR` is set, archiving is disabled otherwise
+    pub fn from_env() -> Option<Self> {
+        let dir = env::var("ARCHIVE_DIR").ok()?;
+
+        let segment_max_bytes = env::var("ARCHIVE_SEGMENT_MAX_BYTES")
+            .map(|b| {
+                b.parse::<u64>()
+                    .expect("ARCHIVE_SEGMENT_MAX_BYTES must be a positive integer")
+            })
+            .unwrap_or(DEFAULT_SEGMENT_MAX_BYTES);
+        let compression_level = env::var("ARCHIVE_COMPRESSION_LEVEL")
+            .map(|l| {
+                l.parse::<i32>()
+                    .expect("ARCHIVE_COMPRESSION_LEVEL must be an integer")
+            })
+            .unwrap_or(DEFAULT_COMPRESSION_LEVEL);
+
+        Some(Self::new(dir, segment_max_bytes, compression_level))
+    }
+
+    /// Appends a page to the current segment, rotating to a new segment once it is full. Pages of
+    /// recently archived change ids are skipped
+    pub fn write_page(&mut self, change_id: &str, body: &[u8]) -> io::Result<()> {
+        if self.recent.contains(change_id) {
+            tracing::debug!("change_id: {change_id} is already archived, skipping");
+            return Ok(());
+        }
+
+        let compressed = zstd::encode_all(body, self.compression_level)?;
+
+        let segment = match self.segment.as_mut() {
+            Some(s) if s.size < self.segment_max_bytes => s,
+            _ => {
+                let segment = Segment::create(&self.dir, change_id)?;
+                self.segment.insert(segment)
+            }
+        };
+
+        let offset = segment.size;
+        segment.data.write_all(&compressed)?;
+        segment.data.flush()?;
+        segment.size += compressed.len() as u64;
+
+        writeln!(segment.index, "{change_id}\t{offset}\t{}", compressed.len())?;
+        segment.index.flush()?;
+        self.recent.insert(change_id);
+
+        Ok(())
+    }
+
+    /// Moves the archive onto a blocking thread, returning a sender for pages to archive and the
+    /// writer's task, which finishes once every sender is dropped and the queued pages are written
+    pub fn spawn(mut self) -> (mpsc::Sender<RawPage>, JoinHandle<()>) {
+        let (tx, mut rx) = mpsc::channel::<RawPage>(ARCHIVE_QUEUE_SIZE);
+
+        let writer = tokio::task::spawn_blocking(move || {
+            while let Some(page) = rx.blocking_recv() {
+                if let Err(e) = self.write_page(&page.change_id, &page.body) {
+                    tracing::error!(
+                        "failed archiving page for change_id: {} with error: {e}",
+                        page.change_id
+                    );
+                }
+            }
+        });
+
+        (tx, writer)
+    }
+}
+
+impl RecentChangeIds {
+    /// Reads the change ids in the most recently written index in `dir`, if any
+    fn from_newest_index(dir: &Path) -> io::Result<Self> {
+        let mut recent = Self::default();
+        if !dir.exists() {
+            return Ok(recent);
+        }
+
+        let mut newest = None;
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|e| e == "idx") {
+                let modified = fs::metadata(&path)?.modified()?;
+                if newest.as_ref().is_none_or(|(at, _)| modified > *at) {
+                    newest = Some((modified, path));
+                }
+            }
+        }
+
+        if let Some((_, path)) = newest {
+            for line in fs::read_to_string(path)?.lines() {
+                if let Some(change_id) = line.split('\t').next() {
+                    recent.insert(change_id);
+                }
+            }
+        }
+
+        Ok(recent)
+    }
+
+    fn contains(&self, change_id: &str) -> bool {
+        self.ids.contains(change_id)
+    }
+
+    fn insert(&mut self, change_id: &str) {
+        if !self.ids.insert(change_id.to_owned()) {
+            return;
+        }
+
+        self.order.push_back(change_id.to_owned());
+        if self.order.len() > RECENT_CHANGE_IDS {
+            if let Some(oldest) = self.order.pop_front() {
+                self.ids.remove(&oldest);
+            }
+        }
+    }
+}
+
+impl Segment {
+    fn create(dir: &Path, first_change_id: &str) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+
+        let name = format!(
+            "river-{}-{first_change_id}",
+            OffsetDateTime::now_utc().unix_timestamp()
+        );
+        let data_path = dir.join(format!("{name}.zst"));
+        let index_path = dir.join(format!("{name}.idx"));
+
+        let data = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&data_path)?;
+        let index = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(index_path)?;
+        let size = data.metadata()?.len();
+
+        tracing::info!("opened new archive segment: {}", data_path.display());
+
+        Ok(Self { data, index, size })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::{self, File},
+        io::{Read, Seek, SeekFrom},
+    };
+
+    use super::RiverArchive;
+
+    #[test]
+    fn pages_are_indexed_by_change_id() {
+        let dir = std::env::temp_dir().join(format!("river-archive-{}", std::process::id()));
+        let mut archive = RiverArchive::new(&dir, 1024 * 1024, 3);
+
+        archive.write_page("1-1-1-1-1", b"{\"page\": 1}").unwrap();
+        archive.write_page("2-2-2-2-2", b"{\"page\": 2}").unwrap();
+
+        let mut segments = fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .collect::<Vec<_>>();
+        segments.sort();
+        assert_eq!(segments.len(), 2);
+
+        let index = fs::read_to_string(&segments[0]).unwrap();
+        let entries = index
+            .lines()
+            .map(|l| l.split('\t').collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1][0], "2-2-2-2-2");
+
+        let offset = entries[1][1].parse::<u64>().unwrap();
+        let len = entries[1][2].parse::<usize>().unwrap();
+        let mut data = File::open(&segments[1]).unwrap();
+        data.seek(SeekFrom::Start(offset)).unwrap();
+        let mut frame = vec![0; len];
+        data.read_exact(&mut frame).unwrap();
+        assert_eq!(
+            zstd::decode_all(frame.as_slice()).unwrap(),
+            b"{\"page\": 2}"
+        );
+
+        let whole = zstd::decode_all(File::open(&segments[1]).unwrap()).unwrap();
+        assert_eq!(whole, b"{\"page\": 1}{\"page\": 2}");
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn segments_rotate_when_full() {
+        let dir = std::env::temp_dir().join(format!("river-rotate-{}", std::process::id()));
+        let mut archive = RiverArchive::new(&dir, 1, 3);
+
+        archive.write_page("1-1-1-1-1", b"{}").unwrap();
+        archive.write_page("2-2-2-2-2", b"{}").unwrap();
+
+        let segments = fs::read_dir(&dir).unwrap().count();
+        assert_eq!(segments, 4);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn archived_change_ids_are_skipped() {
+        let dir = std::env::temp_dir().join(format!("river-skip-{}", std::process::id()));
+
+        let mut archive = RiverArchive::new(&dir, 1024 * 1024, 3);
+        archive.write_page("1-1-1-1-1", b"{}").unwrap();
+        archive.write_page("1-1-1-1-1", b"{}").unwrap();
+        drop(archive);
+
+        // a restarted crawler reads the change ids archived before it stopped
+        let mut archive = RiverArchive::new(&dir, 1024 * 1024, 3);
+        archive.write_page("1-1-1-1-1", b"{}").unwrap();
+
+        let indexed = fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .filter(|p| p.extension().is_some_and(|e| e == "idx"))
+            .map(|p| fs::read_to_string(p).unwrap().lines().count())
+            .sum::<usize>();
+        assert_eq!(indexed, 1);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
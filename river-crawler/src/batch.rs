@@ -0,0 +1,93 @@
+use std::env;
+
+use async_nats::jetstream::context::Publish;
+use poe_types::stash::PublicStashChange;
+use serde::Serialize;
+
+/// Header used to tell consumers how a stash batch payload is encoded
+pub const CONTENT_ENCODING_HEADER: &str = "Content-Encoding";
+pub const ZSTD_ENCODING: &str = "zstd";
+
+const DEFAULT_COMPRESSION_LEVEL: i32 = 3;
+
+/// StashBatch is the envelope published to `river.stashes`, containing a chunk of the
+/// public stashes returned for a single change id
+#[derive(Serialize)]
+pub struct StashBatch<'a> {
+    pub change_id: &'a str,
+    pub chunk: usize,
+    pub stashes: &'a [PublicStashChange],
+}
+
+/// StashBatcher splits a page of stash changes into compressed, de-duplicatable messages
+pub struct StashBatcher {
+    /// maximum number of stashes per message, 0 publishes the whole page as one message
+    chunk_size: usize,
+    compression_level: i32,
+}
+
+impl StashBatcher {
+    pub fn new(chunk_size: usize, compression_level: i32) -> Self {
+        Self {
+            chunk_size,
+            compression_level,
+        }
+    }
+
+    pub fn from_env() -> Self {
+        let chunk_size = env::var("STASH_BATCH_SIZE")
+            .unwrap_or("0".to_owned())
+            .parse::<usize>()
+            .expect("STASH_BATCH_SIZE must be a positive integer");
+        let compression_level = env::var("STASH_BATCH_COMPRESSION_LEVEL")
+            .map(|l| {
+                l.parse::<i32>()
+                    .expect("STASH_BATCH_COMPRESSION_LEVEL must be an integer")
+            })
+            .unwrap_or(DEFAULT_COMPRESSION_LEVEL);
+
+        Self::new(chunk_size, compression_level)
+    }
+
+    /// Encodes the stashes of a page into publishable messages. Message ids are derived from the
+    /// change id and chunk index so redelivering a change id is dropped by the stream's duplicate window
+    pub fn encode(
+        &self,
+        change_id: &str,
+        stashes: &[PublicStashChange],
+    ) -> anyhow::Result<Vec<Publish>> {
+        if stashes.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let chunk_size = match self.chunk_size {
+            0 => stashes.len(),
+            n => n,
+        };
+
+        let mut messages = Vec::new();
+        for (chunk, chunk_stashes) in stashes.chunks(chunk_size).enumerate() {
+            let batch = StashBatch {
+                change_id,
+                chunk,
+                stashes: chunk_stashes,
+            };
+
+            let json = serde_json::to_vec(&batch)?;
+            let compressed = zstd::encode_all(json.as_slice(), self.compression_level)?;
+
+            let publish = Publish::build()
+                .payload(compressed.into())
+                .message_id(batch_message_id(change_id, chunk))
+                .header(CONTENT_ENCODING_HEADER, ZSTD_ENCODING);
+
+            messages.push(publish);
+        }
+
+        Ok(messages)
+    }
+}
+
+pub fn batch_message_id(change_id: &str, chunk: usize) -> String {
+    format!("{change_id}:{chunk}")
+}
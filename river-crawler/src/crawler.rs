@@ -0,0 +1,72 @@
+use std::sync::{atomic::Ordering, Arc};
+
+use poe_api_client::{
+    api::stashes::PublicStashesResponse, ratelimit::limiter::RateLimiter, Client, ClientError,
+};
+use poe_types::stash::PublicStashChange;
+
+use crate::{archive::RawPage, filter::StashFilter, telemetry::Metrics};
+
+/// A page of the river after filtering, ready to be published for processing
+pub struct CrawledPage {
+    pub next_change_id: String,
+    pub stashes: Vec<PublicStashChange>,
+    /// the page as returned by the API, to archive once it is published
+    pub raw: RawPage,
+}
+
+/// Crawler fetches pages of the river, filtering them before they are published
+pub struct Crawler<L: RateLimiter> {
+    client: Client<L>,
+    filter: StashFilter,
+    metrics: Arc<Metrics>,
+}
+
+impl<L: RateLimiter> Crawler<L> {
+    pub fn new(client: Client<L>, filter: StashFilter, metrics: Arc<Metrics>) -> Self {
+        Self {
+            client,
+            filter,
+            metrics,
+        }
+    }
+
+    pub async fn crawl(&mut self, change_id: &str) -> Result<CrawledPage, ClientError> {
+        self.filter.refresh_current_leagues(&mut self.client).await;
+
+        let body = match self.client.get_public_stashes_raw(Some(change_id)).await {
+            Ok((body, _)) => body,
+            Err(e) => {
+                self.metrics
+                    .fetch_errors_total
+                    .fetch_add(1, Ordering::Relaxed);
+                return Err(e);
+            }
+        };
+
+        let page_bytes = body.len();
+        let changes = match serde_json::from_slice::<PublicStashesResponse>(&body) {
+            Ok(c) => c,
+            Err(e) => {
+                self.metrics
+                    .fetch_errors_total
+                    .fetch_add(1, Ordering::Relaxed);
+                return Err(ClientError::ParseError(e));
+            }
+        };
+
+        let stashes = self.filter.apply(changes.stashes);
+        self.metrics
+            .record_page(change_id, page_bytes, stashes.len());
+        self.metrics.update_filter_stats(self.filter.stats());
+
+        Ok(CrawledPage {
+            next_change_id: changes.next_change_id,
+            stashes,
+            raw: RawPage {
+                change_id: change_id.to_owned(),
+                body,
+            },
+        })
+    }
+}
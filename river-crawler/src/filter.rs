@@ -0,0 +1,373 @@
+use std::{
+    collections::{HashMap, HashSet},
+    env, fmt,
+    time::{Duration, Instant},
+};
+
+use poe_api_client::{ratelimit::limiter::RateLimiter, Client};
+use poe_types::{
+    league::{League, LeagueIdentity},
+    stash::PublicStashChange,
+};
+
+const CURRENT_LEAGUES_REFRESH: Duration = Duration::from_secs(60 * 60);
+/// How long to wait before trying the leagues endpoint again after it failed
+const CURRENT_LEAGUES_RETRY: Duration = Duration::from_secs(5 * 60);
+const STATS_LOG_INTERVAL: u64 = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DropReason {
+    Private,
+    NoLeague,
+    LeagueNotAllowed,
+    LeagueDenied,
+    NotCurrentLeague,
+    StashType,
+}
+
+impl fmt::Display for DropReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DropReason::Private => write!(f, "private"),
+            DropReason::NoLeague => write!(f, "no_league"),
+            DropReason::LeagueNotAllowed => write!(f, "league_not_allowed"),
+            DropReason::LeagueDenied => write!(f, "league_denied"),
+            DropReason::NotCurrentLeague => write!(f, "not_current_league"),
+            DropReason::StashType => write!(f, "stash_type"),
+        }
+    }
+}
+
+/// FilterStats counts the stashes forwarded and dropped by the filter
+#[derive(Debug, Default, Clone)]
+pub struct FilterStats {
+    pub pages: u64,
+    pub forwarded: u64,
+    pub dropped: HashMap<DropReason, u64>,
+}
+
+/// StashFilter decides which stash changes are published for processing.
+///
+/// League allow and deny lists accept glob patterns using `*` and `?`, matched case-insensitively.
+/// When `current_leagues_only` is set, only the main challenge league returned by the leagues
+/// endpoint is forwarded, which drops permanent, event and private leagues along with the
+/// hardcore, SSF and ruthless versions of the challenge league.
+#[derive(Default)]
+pub struct StashFilter {
+    league_allow: Vec<String>,
+    league_deny: Vec<String>,
+    stash_types: Vec<String>,
+    current_leagues_only: bool,
+    current_leagues: HashSet<String>,
+    /// when the current leagues are next resolved, backing off after a failure
+    next_refresh: Option<Instant>,
+    stats: FilterStats,
+}
+
+impl StashFilter {
+    pub fn from_env() -> Self {
+        let current_leagues_only = env::var("CURRENT_LEAGUES_ONLY")
+            .map(|v| {
+                v.parse::<bool>()
+                    .expect("CURRENT_LEAGUES_ONLY must be a bool")
+            })
+            .unwrap_or(false);
+
+        Self {
+            league_allow: env_list("LEAGUE_ALLOW"),
+            league_deny: env_list("LEAGUE_DENY"),
+            stash_types: env_list("STASH_TYPES"),
+            current_leagues_only,
+            ..Default::default()
+        }
+    }
+
+    pub fn stats(&self) -> &FilterStats {
+        &self.stats
+    }
+
+    /// Refreshes the current challenge leagues from the leagues endpoint if they are outdated.
+    /// The previous leagues are kept if the refresh fails, and it isn't tried again for a while
+    pub async fn refresh_current_leagues<L: RateLimiter>(&mut self, client: &mut Client<L>) {
+        if !self.current_leagues_only || self.next_refresh.is_some_and(|at| Instant::now() < at) {
+            return;
+        }
+
+        match client.get_leagues("pc", "main").await {
+            Ok((response, _)) => {
+                self.current_leagues = current_leagues(&response.leagues);
+                self.next_refresh = Some(Instant::now() + CURRENT_LEAGUES_REFRESH);
+
+                tracing::info!("resolved current leagues: {:?}", self.current_leagues);
+            }
+            Err(e) => {
+                self.next_refresh = Some(Instant::now() + CURRENT_LEAGUES_RETRY);
+
+                tracing::error!(
+                    "failed resolving current leagues, retrying in {CURRENT_LEAGUES_RETRY:?}: {e}"
+                );
+            }
+        }
+    }
+
+    pub fn check(&self, stash: &PublicStashChange) -> Result<(), DropReason> {
+        if !stash.public {
+            return Err(DropReason::Private);
+        }
+
+        let league = stash.league.as_deref().ok_or(DropReason::NoLeague)?;
+
+        if !self.league_allow.is_empty() && !self.league_allow.iter().any(|p| glob_match(p, league))
+        {
+            return Err(DropReason::LeagueNotAllowed);
+        }
+
+        if self.league_deny.iter().any(|p| glob_match(p, league)) {
+            return Err(DropReason::LeagueDenied);
+        }
+
+        // until the current leagues are resolved, every league is treated as current
+        if self.current_leagues_only
+            && !self.current_leagues.is_empty()
+            && !self.current_leagues.contains(league)
+        {
+            return Err(DropReason::NotCurrentLeague);
+        }
+
+        if !self.stash_types.is_empty()
+            && !self
+                .stash_types
+                .iter()
+                .any(|t| t.eq_ignore_ascii_case(&stash.stash_type))
+        {
+            return Err(DropReason::StashType);
+        }
+
+        Ok(())
+    }
+
+    /// Filters a page of stash changes, counting what was forwarded and dropped
+    pub fn apply(&mut self, stashes: Vec<PublicStashChange>) -> Vec<PublicStashChange> {
+        let mut forwarded = Vec::new();
+
+        for stash in stashes {
+            match self.check(&stash) {
+                Ok(_) => forwarded.push(stash),
+                Err(reason) => *self.stats.dropped.entry(reason).or_default() += 1,
+            }
+        }
+
+        self.stats.pages += 1;
+        self.stats.forwarded += forwarded.len() as u64;
+
+        if self.stats.pages.is_multiple_of(STATS_LOG_INTERVAL) {
+            tracing::info!(
+                "filter stats after {} pages: forwarded {} stashes, dropped {:?}",
+                self.stats.pages,
+                self.stats.forwarded,
+                self.stats.dropped
+            );
+        }
+
+        forwarded
+    }
+}
+
+/// Names of the main challenge leagues, without hardcore, SSF or ruthless modifiers
+fn current_leagues(leagues: &[League]) -> HashSet<String> {
+    leagues
+        .iter()
+        .map(LeagueIdentity::from_league)
+        .filter(|l| l.is_main_challenge())
+        .map(|l| l.name)
+        .collect()
+}
+
+fn env_list(key: &str) -> Vec<String> {
+    env::var(key)
+        .map(|v| {
+            v.split(',')
+                .map(|s| s.trim().to_owned())
+                .filter(|s| !s.is_empty())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Matches text against a glob pattern supporting `*` and `?`, ignoring case
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.to_lowercase().chars().collect::<Vec<_>>();
+    let text = text.to_lowercase().chars().collect::<Vec<_>>();
+
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((bp, bt)) => {
+                    p = bp + 1;
+                    t = bt + 1;
+                    backtrack = Some((bp, bt + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use poe_types::{
+        league::{League, LeagueRule},
+        stash::PublicStashChange,
+    };
+
+    use super::{current_leagues, glob_match, DropReason, StashFilter};
+
+    fn stash(league: Option<&str>, stash_type: &str) -> PublicStashChange {
+        PublicStashChange {
+            public: true,
+            league: league.map(|l| l.to_owned()),
+            stash_type: stash_type.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn glob_patterns() {
+        assert!(glob_match("Necropolis", "necropolis"));
+        assert!(glob_match("*Necropolis", "Hardcore Necropolis"));
+        assert!(glob_match("SSF *", "SSF Necropolis"));
+        assert!(glob_match("*(PL*)", "My League (PL12345)"));
+        assert!(glob_match("Necro?olis", "Necropolis"));
+        assert!(!glob_match("SSF *", "Necropolis"));
+        assert!(!glob_match("Necropolis", "Hardcore Necropolis"));
+    }
+
+    #[test]
+    fn private_and_leagueless_stashes_are_dropped() {
+        let filter = StashFilter::default();
+
+        let mut private = stash(Some("Necropolis"), "PremiumStash");
+        private.public = false;
+
+        assert_eq!(filter.check(&private), Err(DropReason::Private));
+        assert_eq!(
+            filter.check(&stash(None, "PremiumStash")),
+            Err(DropReason::NoLeague)
+        );
+        assert_eq!(
+            filter.check(&stash(Some("Necropolis"), "PremiumStash")),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn league_allow_and_deny_lists() {
+        let filter = StashFilter {
+            league_allow: vec!["*Necropolis*".to_owned()],
+            league_deny: vec!["*SSF*".to_owned(), "*Hardcore*".to_owned()],
+            ..Default::default()
+        };
+
+        assert_eq!(
+            filter.check(&stash(Some("Necropolis"), "PremiumStash")),
+            Ok(())
+        );
+        assert_eq!(
+            filter.check(&stash(Some("Standard"), "PremiumStash")),
+            Err(DropReason::LeagueNotAllowed)
+        );
+        assert_eq!(
+            filter.check(&stash(Some("SSF Necropolis"), "PremiumStash")),
+            Err(DropReason::LeagueDenied)
+        );
+    }
+
+    #[test]
+    fn current_leagues_and_stash_types() {
+        let filter = StashFilter {
+            stash_types: vec!["CurrencyStash".to_owned(), "PremiumStash".to_owned()],
+            current_leagues_only: true,
+            current_leagues: HashSet::from(["Necropolis".to_owned()]),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            filter.check(&stash(Some("Necropolis"), "PremiumStash")),
+            Ok(())
+        );
+        assert_eq!(
+            filter.check(&stash(Some("Standard"), "PremiumStash")),
+            Err(DropReason::NotCurrentLeague)
+        );
+        assert_eq!(
+            filter.check(&stash(Some("Necropolis"), "MapStash")),
+            Err(DropReason::StashType)
+        );
+    }
+
+    #[test]
+    fn only_the_main_challenge_league_is_current() {
+        let league = |id: &str, rules: &[&str]| League {
+            id: id.to_owned(),
+            rules: Some(
+                rules
+                    .iter()
+                    .map(|r| LeagueRule {
+                        id: r.to_string(),
+                        name: r.to_string(),
+                        description: None,
+                    })
+                    .collect(),
+            ),
+            ..Default::default()
+        };
+
+        let leagues = [
+            league("Standard", &[]),
+            league("Hardcore", &["Hardcore"]),
+            league("Necropolis", &[]),
+            league("Hardcore Necropolis", &["Hardcore"]),
+            league("SSF Necropolis", &["NoParties"]),
+            league("Ruthless Necropolis", &["HardMode"]),
+        ];
+
+        assert_eq!(
+            current_leagues(&leagues),
+            HashSet::from(["Necropolis".to_owned()])
+        );
+    }
+
+    #[test]
+    fn apply_counts_dropped_stashes() {
+        let mut filter = StashFilter {
+            league_deny: vec!["Standard".to_owned()],
+            ..Default::default()
+        };
+
+        let forwarded = filter.apply(vec![
+            stash(Some("Necropolis"), "PremiumStash"),
+            stash(Some("Standard"), "PremiumStash"),
+            stash(None, "PremiumStash"),
+        ]);
+
+        assert_eq!(forwarded.len(), 1);
+        assert_eq!(filter.stats().forwarded, 1);
+        assert_eq!(filter.stats().dropped[&DropReason::LeagueDenied], 1);
+        assert_eq!(filter.stats().dropped[&DropReason::NoLeague], 1);
+    }
+}
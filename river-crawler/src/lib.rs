@@ -0,0 +1,6 @@
+pub mod archive;
+pub mod batch;
+pub mod crawler;
+pub mod filter;
+pub mod limiter;
+pub mod telemetry;
@@ -1,4 +1,4 @@
-use std::time::Duration;
+use std::{sync::Arc, time::Duration};
 
 use anyhow::bail;
 use async_nats::jetstream;
@@ -7,13 +7,16 @@ use poe_api_client::ratelimit::limiter::{
     LimiterOutcome, Policy, RateLimiter, RateLimiterError, Rule, RuleType,
 };
 
+use crate::telemetry::Metrics;
+
 pub struct NatsRateLimiter {
     bucket: jetstream::kv::Store,
     ip: String,
+    metrics: Arc<Metrics>,
 }
 
 impl NatsRateLimiter {
-    pub async fn new(nats: async_nats::Client) -> anyhow::Result<Self> {
+    pub async fn new(nats: async_nats::Client, metrics: Arc<Metrics>) -> anyhow::Result<Self> {
         let jetstream = jetstream::new(nats);
         let bucket = jetstream.get_key_value("ratelimiter").await?;
 
@@ -22,7 +25,11 @@ impl NatsRateLimiter {
             .text()
             .await?;
 
-        Ok(Self { bucket, ip })
+        Ok(Self {
+            bucket,
+            ip,
+            metrics,
+        })
     }
 
     async fn kv_insert_rule(&self, key: String, rule: Rule) -> anyhow::Result<()> {
@@ -114,9 +121,10 @@ impl RateLimiter for NatsRateLimiter {
             Ok(rtypes) => rtypes,
             Err(e) => {
                 tracing::error!("{e}");
-                return Ok(LimiterOutcome::Retry {
-                    after: Duration::from_secs(5),
-                });
+                let after = Duration::from_secs(5);
+                self.metrics.record_limiter_wait(after);
+
+                return Ok(LimiterOutcome::Retry { after });
             }
         };
 
@@ -148,6 +156,10 @@ impl RateLimiter for NatsRateLimiter {
             None => tracing::info!("no rtypes found for endpoint: {endpoint}"),
         }
 
+        if let LimiterOutcome::Retry { after } = &outcome {
+            self.metrics.record_limiter_wait(*after);
+        }
+
         Ok(outcome)
     }
 
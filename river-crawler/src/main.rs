@@ -1,13 +1,21 @@
-mod limiter;
-
-use std::{env, str::from_utf8};
+use std::{env, str::from_utf8, sync::Arc, time::Duration};
 
 use anyhow::Context;
 use async_nats::jetstream::{self, consumer::PullConsumer, AckKind};
 use futures::StreamExt;
+use ledger_service::{shutdown::Shutdown, telemetry as service_telemetry};
+use river_crawler::{
+    archive::RiverArchive,
+    batch::StashBatcher,
+    crawler::{CrawledPage, Crawler},
+    filter::StashFilter,
+    limiter::NatsRateLimiter,
+    telemetry::{self, CrawlerTelemetry, Metrics},
+};
 use tracing_subscriber::{layer::SubscriberExt, EnvFilter, Registry};
 
-use crate::limiter::NatsRateLimiter;
+/// How long a change id whose page failed to publish waits before it is crawled again
+const PUBLISH_RETRY_DELAY: Duration = Duration::from_secs(5);
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
@@ -21,7 +29,19 @@ async fn main() -> anyhow::Result<()> {
     let nats_client = async_nats::connect(&nats_url)
         .await
         .context(format!("failed to connect to NATS_URL: {nats_url}"))?;
-    let limiter = NatsRateLimiter::new(nats_client.clone()).await?;
+
+    let metrics = Arc::new(Metrics::default());
+    let crawler_telemetry = Arc::new(CrawlerTelemetry {
+        nats: nats_client.clone(),
+        metrics: metrics.clone(),
+    });
+    tokio::spawn(async move {
+        if let Err(e) = service_telemetry::serve(crawler_telemetry).await {
+            tracing::error!("telemetry server stopped with error: {e}");
+        }
+    });
+
+    let limiter = NatsRateLimiter::new(nats_client.clone(), metrics.clone()).await?;
 
     let mut poe_client = poe_api_client::Client::new(&user_agent, limiter)?;
     poe_client.authorize(&client_id, &client_secret).await?;
@@ -36,11 +56,31 @@ async fn main() -> anyhow::Result<()> {
             panic!("failed to get consumer: {consumer_name} for stream: {stream_name}")
         });
 
+    service_telemetry::spawn_monitor(consumer.clone(), metrics.clone());
+    telemetry::spawn_river_head_monitor(metrics.clone());
+
+    let batcher = StashBatcher::from_env();
+    let filter = StashFilter::from_env();
+    let (archive, archive_writer) = RiverArchive::from_env().map(RiverArchive::spawn).unzip();
+    let mut crawler = Crawler::new(poe_client, filter, metrics);
     let messages = consumer.messages().await?;
+    let shutdown = Shutdown::from_env();
+    let signalled = shutdown.signalled();
 
     tokio::pin!(messages);
+    tokio::pin!(signalled);
+
+    // change ids are only pulled between pages, so a page being crawled when the process is asked
+    // to stop is still published and acked
+    loop {
+        let msg = tokio::select! {
+            _ = &mut signalled => break,
+            msg = messages.next() => match msg {
+                Some(msg) => msg,
+                None => break,
+            },
+        };
 
-    while let Some(msg) = messages.next().await {
         match msg {
             Ok(m) => {
                 let change_id = match from_utf8(&m.payload) {
@@ -56,34 +96,29 @@ async fn main() -> anyhow::Result<()> {
                     }
                 };
 
-                match poe_client.get_public_stashes(Some(change_id)).await {
-                    Ok((changes, _)) => {
-                        let next_change_id = &changes.next_change_id;
-                        if let Err(e) = jetstream
-                            .publish(
-                                "river.changeids".to_owned(),
-                                next_change_id.to_owned().into(),
-                            )
-                            .await
-                        {
+                match crawler.crawl(change_id).await {
+                    Ok(page) => {
+                        // a page which failed to publish is crawled again, any batches of it
+                        // the stream already stored are dropped as duplicates by their msg id
+                        if let Err(e) = publish_page(&jetstream, &batcher, change_id, &page).await {
                             tracing::error!(
-                                "failed publishing next_change_id: {next_change_id} with error: {e}"
+                                "failed publishing page for change_id: {change_id} with error: {e:#}"
                             );
+                            if let Err(e) =
+                                m.ack_with(AckKind::Nak(Some(PUBLISH_RETRY_DELAY))).await
+                            {
+                                tracing::error!("failed to nak message: {e}");
+                            }
+                            continue;
                         }
 
-                        if !changes.stashes.is_empty() {
-                            for stash_change in changes.stashes {
-                                if stash_change.public && stash_change.league.is_some() {
-                                    if let Ok(sc_json) = serde_json::to_string(&stash_change) {
-                                        if let Err(e) =
-                                            jetstream.publish("river.stashes", sc_json.into()).await
-                                        {
-                                            tracing::error!(
-                                                "failed publishing a json stash with error: {e}"
-                                            );
-                                        }
-                                    }
-                                }
+                        // pages are only archived once stored in the stream, so the archive
+                        // holds what was processed
+                        if let Some(archive) = &archive {
+                            if archive.send(page.raw).await.is_err() {
+                                tracing::error!(
+                                    "archive writer stopped, page for change_id: {change_id} was not archived"
+                                );
                             }
                         }
 
@@ -104,6 +139,48 @@ async fn main() -> anyhow::Result<()> {
         }
     }
 
+    // closing the archive queue stops the writer once every queued page is written
+    drop(archive);
+    if let Some(writer) = archive_writer {
+        if let Err(e) = shutdown.drain(writer).await? {
+            tracing::error!("archive writer stopped with error: {e}");
+        }
+    }
+    tracing::info!("stopped crawling");
+
+    Ok(())
+}
+
+/// Publishes a page's stash batches and then its next change id, waiting for the streams to
+/// store each one so one they reject, like when they are full, fails the page. The next change id
+/// goes last so a page which failed isn't crawled past before it is retried
+async fn publish_page(
+    jetstream: &jetstream::Context,
+    batcher: &StashBatcher,
+    change_id: &str,
+    page: &CrawledPage,
+) -> anyhow::Result<()> {
+    let batches = batcher
+        .encode(change_id, &page.stashes)
+        .context("failed encoding stash batches")?;
+
+    for batch in batches {
+        jetstream
+            .send_publish("river.stashes", batch)
+            .await?
+            .await
+            .context("stash batch wasn't stored")?;
+    }
+
+    jetstream
+        .publish(
+            "river.changeids".to_owned(),
+            page.next_change_id.to_owned().into(),
+        )
+        .await?
+        .await
+        .with_context(|| format!("next_change_id {} wasn't stored", page.next_change_id))?;
+
     Ok(())
 }
 
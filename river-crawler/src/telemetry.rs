@@ -0,0 +1,238 @@
+use std::{
+    env,
+    fmt::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use ledger_service::telemetry::{
+    nats_unready, write_counter, write_gauge, ConsumerStats, Telemetry, Throughput,
+};
+use serde::Deserialize;
+
+use crate::filter::FilterStats;
+
+const RIVER_HEAD_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Metrics collected by the crawler, rendered in the prometheus text format on `/metrics`
+#[derive(Default)]
+pub struct Metrics {
+    pub pages_total: AtomicU64,
+    pub stashes_total: AtomicU64,
+    pub bytes_total: AtomicU64,
+    pub fetch_errors_total: AtomicU64,
+    pub limiter_waits_total: AtomicU64,
+    /// rendered in seconds, most waits are shorter than one
+    pub limiter_wait_micros_total: AtomicU64,
+    pub consumer: ConsumerStats,
+    pub pages: Throughput,
+    pub stashes: Throughput,
+    current_change_id: Mutex<Option<String>>,
+    head_change_id: Mutex<Option<String>>,
+    filter: Mutex<FilterStats>,
+}
+
+impl AsRef<ConsumerStats> for Metrics {
+    fn as_ref(&self) -> &ConsumerStats {
+        &self.consumer
+    }
+}
+
+impl Metrics {
+    pub fn record_page(&self, change_id: &str, bytes: usize, stashes: usize) {
+        self.pages_total.fetch_add(1, Ordering::Relaxed);
+        self.stashes_total
+            .fetch_add(stashes as u64, Ordering::Relaxed);
+        self.bytes_total.fetch_add(bytes as u64, Ordering::Relaxed);
+        self.pages.record(1);
+        self.stashes.record(stashes as u64);
+
+        *self.current_change_id.lock().unwrap() = Some(change_id.to_owned());
+    }
+
+    pub fn record_limiter_wait(&self, wait: Duration) {
+        self.limiter_waits_total.fetch_add(1, Ordering::Relaxed);
+        self.limiter_wait_micros_total
+            .fetch_add(wait.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    pub fn update_filter_stats(&self, stats: &FilterStats) {
+        *self.filter.lock().unwrap() = stats.clone();
+    }
+
+    /// Estimated number of changes between the last crawled change id and the head of the river
+    pub fn change_id_lag(&self) -> Option<u64> {
+        let current = self.current_change_id.lock().unwrap();
+        let head = self.head_change_id.lock().unwrap();
+
+        change_id_lag(current.as_deref()?, head.as_deref()?)
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        let counters = [
+            ("river_crawler_pages_total", &self.pages_total),
+            ("river_crawler_stashes_total", &self.stashes_total),
+            ("river_crawler_bytes_total", &self.bytes_total),
+            ("river_crawler_fetch_errors_total", &self.fetch_errors_total),
+            (
+                "river_crawler_limiter_waits_total",
+                &self.limiter_waits_total,
+            ),
+        ];
+        for (name, counter) in counters {
+            write_counter(&mut out, name, counter.load(Ordering::Relaxed));
+        }
+        write_counter(
+            &mut out,
+            "river_crawler_limiter_wait_seconds_total",
+            self.limiter_wait_micros_total.load(Ordering::Relaxed) as f64 / 1_000_000.0,
+        );
+
+        let gauges = [
+            ("river_crawler_pages_per_second", self.pages.per_second()),
+            (
+                "river_crawler_stashes_per_second",
+                self.stashes.per_second(),
+            ),
+            (
+                "river_crawler_consumer_pending",
+                self.consumer.pending.load(Ordering::Relaxed) as f64,
+            ),
+            (
+                "river_crawler_consumer_ack_pending",
+                self.consumer.ack_pending.load(Ordering::Relaxed) as f64,
+            ),
+        ];
+        for (name, value) in gauges {
+            write_gauge(&mut out, name, value);
+        }
+
+        if let Some(lag) = self.change_id_lag() {
+            write_gauge(&mut out, "river_crawler_change_id_lag", lag);
+        }
+
+        let filter = self.filter.lock().unwrap();
+        let _ = writeln!(out, "# TYPE river_crawler_stashes_dropped_total counter");
+        for (reason, count) in &filter.dropped {
+            let _ = writeln!(
+                out,
+                "river_crawler_stashes_dropped_total{{reason=\"{reason}\"}} {count}"
+            );
+        }
+
+        out
+    }
+}
+
+/// Estimates how far behind `current` is from `head` by summing the difference of each shard
+pub fn change_id_lag(current: &str, head: &str) -> Option<u64> {
+    let parse = |id: &str| {
+        id.split('-')
+            .map(|s| s.parse::<u64>())
+            .collect::<Result<Vec<_>, _>>()
+            .ok()
+    };
+
+    let (current, head) = (parse(current)?, parse(head)?);
+    if current.len() != head.len() {
+        return None;
+    }
+
+    let lag = current
+        .iter()
+        .zip(head.iter())
+        .map(|(c, h)| h.saturating_sub(*c))
+        .sum();
+
+    Some(lag)
+}
+
+/// CrawlerTelemetry is served by the crawler's telemetry server
+pub struct CrawlerTelemetry {
+    pub nats: async_nats::Client,
+    pub metrics: Arc<Metrics>,
+}
+
+#[async_trait]
+impl Telemetry for CrawlerTelemetry {
+    fn render_metrics(&self) -> String {
+        self.metrics.render()
+    }
+
+    async fn unready(&self) -> Vec<String> {
+        nats_unready(&self.nats).into_iter().collect()
+    }
+}
+
+#[derive(Deserialize)]
+struct RiverStats {
+    next_change_id: String,
+}
+
+/// Periodically records the head of the river from `RIVER_HEAD_URL`, which reports the change
+/// id lag. Nothing is polled unless it is set, e.g. to `https://poe.ninja/api/data/getstats`
+pub fn spawn_river_head_monitor(metrics: Arc<Metrics>) {
+    let Ok(head_url) = env::var("RIVER_HEAD_URL") else {
+        return;
+    };
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(RIVER_HEAD_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            match fetch_river_head(&head_url).await {
+                Ok(head) => *metrics.head_change_id.lock().unwrap() = Some(head),
+                Err(e) => tracing::debug!("failed fetching river head: {e}"),
+            }
+        }
+    });
+}
+
+async fn fetch_river_head(url: &str) -> anyhow::Result<String> {
+    let stats = reqwest::get(url).await?.json::<RiverStats>().await?;
+
+    Ok(stats.next_change_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{change_id_lag, Metrics};
+
+    #[test]
+    fn lag_sums_shard_differences() {
+        assert_eq!(change_id_lag("10-20-30", "15-20-40"), Some(15));
+        assert_eq!(change_id_lag("10-20-30", "10-20-30"), Some(0));
+    }
+
+    #[test]
+    fn lag_ignores_shards_ahead_of_head() {
+        assert_eq!(change_id_lag("20-20", "10-25"), Some(5));
+    }
+
+    #[test]
+    fn lag_requires_matching_change_ids() {
+        assert_eq!(change_id_lag("10-20", "10-20-30"), None);
+        assert_eq!(change_id_lag("abc", "10"), None);
+    }
+
+    #[test]
+    fn sub_second_limiter_waits_are_counted() {
+        let metrics = Metrics::default();
+        metrics.record_limiter_wait(Duration::from_millis(250));
+        metrics.record_limiter_wait(Duration::from_millis(500));
+
+        assert!(metrics
+            .render()
+            .contains("river_crawler_limiter_wait_seconds_total 0.75\n"));
+    }
+}
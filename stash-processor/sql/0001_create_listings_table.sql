@@ -1,4 +1,4 @@
-CREATE TABLE ledger.listings (
+CREATE TABLE IF NOT EXISTS ledger.listings (
     item_id String,
     name String,
     league String,
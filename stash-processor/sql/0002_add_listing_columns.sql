@@ -0,0 +1,12 @@
+ALTER TABLE ledger.listings
+    ADD COLUMN IF NOT EXISTS stash_id String AFTER item_id,
+    ADD COLUMN IF NOT EXISTS account_name String AFTER stash_id,
+    ADD COLUMN IF NOT EXISTS base_type String AFTER name,
+    ADD COLUMN IF NOT EXISTS category LowCardinality(String) AFTER base_type,
+    ADD COLUMN IF NOT EXISTS stack_size UInt32 AFTER category,
+    ADD COLUMN IF NOT EXISTS listed_amount Float64 AFTER listed_currency,
+    ADD COLUMN IF NOT EXISTS listed_units Float64 DEFAULT 1 AFTER listed_amount,
+    ADD COLUMN IF NOT EXISTS rate_snapshot_id UInt64 AFTER created_at,
+    ADD COLUMN IF NOT EXISTS price_source LowCardinality(String) AFTER rate_snapshot_id,
+    ADD COLUMN IF NOT EXISTS outlier_score Float64 AFTER price_source,
+    ADD COLUMN IF NOT EXISTS is_outlier Bool AFTER outlier_score;
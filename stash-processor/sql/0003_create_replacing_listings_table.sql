@@ -0,0 +1,24 @@
+CREATE TABLE IF NOT EXISTS ledger.listings_replacing (
+    item_id String,
+    stash_id String,
+    account_name String,
+    name String,
+    base_type String,
+    category LowCardinality(String),
+    stack_size UInt32,
+    league String,
+    normalized_price Float64,
+    listed_price Float64,
+    listed_currency String,
+    listed_amount Float64,
+    listed_units Float64 DEFAULT 1,
+    implicit_mods Array(String),
+    explicit_mods Array(String),
+    created_at DateTime,
+    rate_snapshot_id UInt64,
+    price_source LowCardinality(String),
+    outlier_score Float64,
+    is_outlier Bool
+) ENGINE = ReplacingMergeTree
+PRIMARY KEY (name, league, toDate(created_at))
+ORDER BY (name, league, toDate(created_at), item_id, listed_price, listed_currency, stack_size);
@@ -0,0 +1 @@
+INSERT INTO ledger.listings_replacing SELECT * FROM ledger.listings;
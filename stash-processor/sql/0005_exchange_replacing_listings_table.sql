@@ -0,0 +1 @@
+EXCHANGE TABLES ledger.listings AND ledger.listings_replacing;
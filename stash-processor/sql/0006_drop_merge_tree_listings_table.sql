@@ -0,0 +1 @@
+DROP TABLE IF EXISTS ledger.listings_replacing;
@@ -0,0 +1,16 @@
+CREATE TABLE IF NOT EXISTS ledger.listing_events (
+    event LowCardinality(String),
+    stash_id String,
+    item_id String,
+    name String,
+    league String,
+    category LowCardinality(String),
+    listed_price Float64,
+    listed_currency String,
+    normalized_price Float64,
+    previous_price Float64,
+    previous_currency String,
+    first_listed_at DateTime,
+    time_on_market UInt64,
+    created_at DateTime
+) ENGINE = MergeTree PRIMARY KEY (name, created_at) ORDER BY (name, created_at);
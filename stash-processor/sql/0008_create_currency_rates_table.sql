@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS ledger.currency_rates (
+    snapshot_id UInt64,
+    league String,
+    currency String,
+    chaos_equivalent Float64,
+    observations UInt32,
+    updated_at DateTime,
+    created_at DateTime
+) ENGINE = MergeTree PRIMARY KEY (league, currency, snapshot_id) ORDER BY (league, currency, snapshot_id);
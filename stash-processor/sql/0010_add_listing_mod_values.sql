@@ -0,0 +1,4 @@
+ALTER TABLE ledger.listings
+    ADD COLUMN IF NOT EXISTS mod_kinds Array(LowCardinality(String)) AFTER explicit_mods,
+    ADD COLUMN IF NOT EXISTS mod_stats Array(LowCardinality(String)) AFTER mod_kinds,
+    ADD COLUMN IF NOT EXISTS mod_values Array(Array(Float64)) AFTER mod_stats;
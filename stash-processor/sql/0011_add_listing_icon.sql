@@ -0,0 +1 @@
+ALTER TABLE ledger.listings ADD COLUMN IF NOT EXISTS icon String AFTER variant;
@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS ledger.leagues (
+    name String,
+    base String,
+    kind LowCardinality(String),
+    hardcore Bool,
+    solo_self_found Bool,
+    ruthless Bool,
+    private Bool,
+    first_seen DateTime,
+    last_seen DateTime
+) ENGINE = ReplacingMergeTree(last_seen)
+ORDER BY name;
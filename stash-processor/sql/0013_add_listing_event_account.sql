@@ -0,0 +1,3 @@
+ALTER TABLE ledger.listing_events
+    ADD COLUMN IF NOT EXISTS account_name String AFTER stash_id,
+    ADD COLUMN IF NOT EXISTS previous_normalized_price Float64 AFTER previous_currency;
@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS ledger.account_activity (
+    account_name String,
+    league String,
+    day Date,
+    listed SimpleAggregateFunction(sum, UInt64),
+    removed SimpleAggregateFunction(sum, UInt64),
+    repriced SimpleAggregateFunction(sum, UInt64),
+    value_change SimpleAggregateFunction(sum, Float64),
+    items AggregateFunction(uniq, String)
+) ENGINE = AggregatingMergeTree
+ORDER BY (account_name, league, day);
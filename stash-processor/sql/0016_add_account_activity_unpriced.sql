@@ -0,0 +1 @@
+ALTER TABLE ledger.account_activity ADD COLUMN IF NOT EXISTS unpriced SimpleAggregateFunction(sum, UInt64) AFTER removed;
@@ -0,0 +1 @@
+DROP VIEW IF EXISTS ledger.account_activity_mv;
@@ -0,0 +1,18 @@
+CREATE MATERIALIZED VIEW IF NOT EXISTS ledger.account_activity_mv TO ledger.account_activity AS
+SELECT
+    account_name,
+    league,
+    toDate(created_at) AS day,
+    countIf(event = 'listed') AS listed,
+    countIf(event = 'removed') AS removed,
+    countIf(event = 'unpriced') AS unpriced,
+    countIf(event = 'repriced') AS repriced,
+    sum(multiIf(
+        event = 'listed', normalized_price,
+        event IN ('removed', 'unpriced'), -normalized_price,
+        normalized_price - previous_normalized_price
+    )) AS value_change,
+    uniqStateIf(name, event NOT IN ('removed', 'unpriced')) AS items
+FROM ledger.listing_events
+WHERE account_name != ''
+GROUP BY account_name, league, day;
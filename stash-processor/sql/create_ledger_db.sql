@@ -1 +0,0 @@
-CREATE DATABASE ledger;
@@ -0,0 +1,83 @@
+use std::{
+    env,
+    time::{Duration, Instant},
+};
+
+/// How often churning accounts are read from the account aggregates again
+const CHURN_REFRESH_INTERVAL: Duration = Duration::from_secs(300);
+
+/// ChurnPolicy is when an account counts as churning through its listings, going by the
+/// `ledger.account_activity` aggregates of its listing events
+pub struct ChurnPolicy {
+    /// minimum share of the listings up during the window which were removed by its end. Both
+    /// sellers whose items sell and price fixers pulling bait listings churn, so this only backs
+    /// up other signals
+    pub rate: f64,
+    /// minimum number of listings up during the window, so small sellers are left alone
+    pub min_listings: u64,
+    /// days the churn rate is taken over, counting today
+    pub window_days: u32,
+    refreshed_at: Option<Instant>,
+}
+
+impl ChurnPolicy {
+    pub fn new(rate: f64, min_listings: u64, window_days: u32) -> Self {
+        Self {
+            rate,
+            min_listings,
+            window_days,
+            refreshed_at: None,
+        }
+    }
+
+    pub fn from_env() -> Self {
+        let rate = env::var("OUTLIER_CHURN_RATE")
+            .map(|v| {
+                v.parse::<f64>()
+                    .expect("OUTLIER_CHURN_RATE must be a number")
+            })
+            .unwrap_or(0.9);
+        let number = |key: &str, default: u64| {
+            env::var(key)
+                .map(|v| {
+                    v.parse::<u64>()
+                        .unwrap_or_else(|_| panic!("{key} must be a number"))
+                })
+                .unwrap_or(default)
+        };
+
+        Self::new(
+            rate,
+            number("OUTLIER_CHURN_MIN_LISTINGS", 50),
+            number("OUTLIER_CHURN_WINDOW_DAYS", 1) as u32,
+        )
+    }
+
+    /// Whether churning accounts are due to be read again, counting them as read when they are
+    pub fn due(&mut self, now: Instant) -> bool {
+        let due = self
+            .refreshed_at
+            .is_none_or(|at| now.duration_since(at) >= CHURN_REFRESH_INTERVAL);
+        if due {
+            self.refreshed_at = Some(now);
+        }
+
+        due
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::ChurnPolicy;
+
+    #[test]
+    fn churn_is_refreshed_periodically() {
+        let mut policy = ChurnPolicy::new(0.9, 50, 1);
+        let now = Instant::now();
+        assert!(policy.due(now));
+        assert!(!policy.due(now + Duration::from_secs(60)));
+        assert!(policy.due(now + Duration::from_secs(301)));
+    }
+}
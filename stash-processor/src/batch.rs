@@ -0,0 +1,98 @@
+use anyhow::Context;
+use async_nats::HeaderMap;
+use poe_types::stash::PublicStashChange;
+use serde::Deserialize;
+
+pub const CONTENT_ENCODING_HEADER: &str = "Content-Encoding";
+pub const ZSTD_ENCODING: &str = "zstd";
+
+/// StashBatch is the envelope the river-crawler publishes to `river.stashes`, containing a
+/// chunk of the public stashes returned for a single change id
+#[derive(Deserialize)]
+pub struct StashBatch {
+    pub change_id: String,
+    pub chunk: usize,
+    pub stashes: Vec<PublicStashChange>,
+}
+
+/// Decodes a `river.stashes` message into its stash changes. Messages without a content encoding
+/// are treated as a single json stash change, as published by older crawlers
+pub fn decode_stash_message(
+    headers: Option<&HeaderMap>,
+    payload: &[u8],
+) -> anyhow::Result<Vec<PublicStashChange>> {
+    let encoding = headers
+        .and_then(|h| h.get(CONTENT_ENCODING_HEADER))
+        .map(|v| v.as_str());
+
+    match encoding {
+        Some(ZSTD_ENCODING) => {
+            let json = zstd::decode_all(payload).context("failed decompressing stash batch")?;
+            let batch = serde_json::from_slice::<StashBatch>(&json)
+                .context("failed parsing stash batch")?;
+
+            tracing::debug!(
+                "decoded chunk {} of change_id: {} with {} stashes",
+                batch.chunk,
+                batch.change_id,
+                batch.stashes.len()
+            );
+
+            Ok(batch.stashes)
+        }
+        Some(other) => anyhow::bail!("unsupported stash batch encoding: {other}"),
+        None => {
+            let stash = serde_json::from_slice::<PublicStashChange>(payload)
+                .context("failed parsing a stash change")?;
+
+            Ok(vec![stash])
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_nats::HeaderMap;
+
+    use super::{decode_stash_message, CONTENT_ENCODING_HEADER, ZSTD_ENCODING};
+
+    const STASH_JSON: &str = r#"{
+        "id": "abc",
+        "public": true,
+        "accountName": "someone",
+        "stash": "~price 1 chaos",
+        "stashType": "PremiumStash",
+        "league": "Necropolis",
+        "items": []
+    }"#;
+
+    #[test]
+    fn legacy_single_stash() {
+        let stashes = decode_stash_message(None, STASH_JSON.as_bytes()).expect("should decode");
+
+        assert_eq!(stashes.len(), 1);
+        assert_eq!(stashes[0].id, "abc");
+    }
+
+    #[test]
+    fn zstd_stash_batch() {
+        let batch = format!(
+            r#"{{"change_id": "1-2-3-4-5", "chunk": 0, "stashes": [{STASH_JSON}, {STASH_JSON}]}}"#
+        );
+        let payload = zstd::encode_all(batch.as_bytes(), 3).unwrap();
+
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_ENCODING_HEADER, ZSTD_ENCODING);
+
+        let stashes = decode_stash_message(Some(&headers), &payload).expect("should decode");
+        assert_eq!(stashes.len(), 2);
+    }
+
+    #[test]
+    fn unknown_encoding() {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_ENCODING_HEADER, "gzip");
+
+        assert!(decode_stash_message(Some(&headers), STASH_JSON.as_bytes()).is_err());
+    }
+}
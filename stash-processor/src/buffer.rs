@@ -0,0 +1,172 @@
+use std::{
+    env,
+    time::{Duration, Instant},
+};
+
+use crate::{lifecycle::ListingEvent, listing::Listing};
+
+/// BufferedMessage is a message whose listings are waiting in a [`ListingBuffer`] to be
+/// written, it can only be acked once they are
+pub struct BufferedMessage<M> {
+    pub message: M,
+    pub bytes: usize,
+    pub stashes: usize,
+    pub listings: usize,
+}
+
+/// Flush is everything taken out of a [`ListingBuffer`] to be written together
+pub struct Flush<M> {
+    pub listings: Vec<Listing>,
+    pub events: Vec<ListingEvent>,
+    pub messages: Vec<BufferedMessage<M>>,
+}
+
+/// ListingBuffer collects the listings of several messages so they are written in one insert
+/// instead of one per message.
+///
+/// The buffer is flushed once it holds `max_listings` listings or `max_messages` messages, or
+/// when the first message in it is `max_age` old. Messages are held unacked until their flush,
+/// so `max_messages` must stay below the consumer's max ack pending and `max_age` well below its
+/// ack wait. Nothing else is read while flushing, which keeps memory bounded to a full buffer.
+pub struct ListingBuffer<M> {
+    listings: Vec<Listing>,
+    events: Vec<ListingEvent>,
+    messages: Vec<BufferedMessage<M>>,
+    started_at: Option<Instant>,
+    max_listings: usize,
+    max_messages: usize,
+    max_age: Duration,
+}
+
+impl<M> ListingBuffer<M> {
+    pub fn new(max_listings: usize, max_messages: usize, max_age: Duration) -> Self {
+        Self {
+            listings: Vec::new(),
+            events: Vec::new(),
+            messages: Vec::new(),
+            started_at: None,
+            max_listings: max_listings.max(1),
+            max_messages: max_messages.max(1),
+            max_age: max_age.max(Duration::from_millis(1)),
+        }
+    }
+
+    pub fn from_env() -> Self {
+        let number = |key: &str, default: u64| {
+            env::var(key)
+                .map(|v| {
+                    v.parse::<u64>()
+                        .unwrap_or_else(|_| panic!("{key} must be a number"))
+                })
+                .unwrap_or(default)
+        };
+
+        Self::new(
+            number("LISTING_BUFFER_SIZE", 20_000) as usize,
+            number("LISTING_BUFFER_MESSAGES", 500) as usize,
+            Duration::from_millis(number("LISTING_BUFFER_MAX_AGE_MS", 5_000)),
+        )
+    }
+
+    pub fn max_age(&self) -> Duration {
+        self.max_age
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+
+    /// Adds the listings and events of a message, along with the message to ack once they're written
+    pub fn push(
+        &mut self,
+        message: M,
+        bytes: usize,
+        stashes: usize,
+        listings: Vec<Listing>,
+        events: Vec<ListingEvent>,
+    ) {
+        self.started_at.get_or_insert_with(Instant::now);
+        self.messages.push(BufferedMessage {
+            message,
+            bytes,
+            stashes,
+            listings: listings.len(),
+        });
+        self.listings.extend(listings);
+        self.events.extend(events);
+    }
+
+    pub fn should_flush(&self, now: Instant) -> bool {
+        let full =
+            self.listings.len() >= self.max_listings || self.messages.len() >= self.max_messages;
+        let expired = self
+            .started_at
+            .is_some_and(|at| now.duration_since(at) >= self.max_age);
+
+        !self.is_empty() && (full || expired)
+    }
+
+    /// Empties the buffer, returning everything in it
+    pub fn take(&mut self) -> Flush<M> {
+        self.started_at = None;
+
+        Flush {
+            listings: std::mem::take(&mut self.listings),
+            events: std::mem::take(&mut self.events),
+            messages: std::mem::take(&mut self.messages),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::ListingBuffer;
+    use crate::listing::Listing;
+
+    fn listings(count: usize) -> Vec<Listing> {
+        (0..count).map(|_| Listing::default()).collect()
+    }
+
+    #[test]
+    fn flushes_on_size() {
+        let mut buffer = ListingBuffer::new(10, 3, Duration::from_secs(60));
+        let now = Instant::now();
+
+        assert!(!buffer.should_flush(now));
+
+        buffer.push("a", 100, 1, listings(4), Vec::new());
+        buffer.push("b", 100, 1, listings(4), Vec::new());
+        assert!(!buffer.should_flush(now));
+
+        buffer.push("c", 100, 1, listings(4), Vec::new());
+        assert!(buffer.should_flush(now));
+
+        let flush = buffer.take();
+        assert_eq!(flush.listings.len(), 12);
+        assert_eq!(
+            flush.messages.iter().map(|m| m.message).collect::<Vec<_>>(),
+            vec!["a", "b", "c"]
+        );
+        assert!(buffer.is_empty());
+
+        // messages without listings still count towards a flush, they need acking too
+        for m in ["d", "e", "f"] {
+            buffer.push(m, 100, 1, Vec::new(), Vec::new());
+        }
+        assert!(buffer.should_flush(now));
+    }
+
+    #[test]
+    fn flushes_on_age() {
+        let mut buffer = ListingBuffer::new(10, 10, Duration::from_secs(5));
+
+        buffer.push((), 100, 1, listings(1), Vec::new());
+        assert!(!buffer.should_flush(Instant::now()));
+        assert!(buffer.should_flush(Instant::now() + Duration::from_secs(5)));
+
+        buffer.take();
+        assert!(!buffer.should_flush(Instant::now() + Duration::from_secs(5)));
+    }
+}
@@ -0,0 +1,237 @@
+use core::fmt;
+
+use poe_types::item::{FrameType, Item};
+use serde::{Deserialize, Serialize};
+
+/// ItemCategory is the class of tradable item a listing is for.
+///
+/// Uniques are traded by name, every other category is traded by base type.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ItemCategory {
+    #[default]
+    Unique,
+    Currency,
+    Fragment,
+    Scarab,
+    Breachstone,
+    Invitation,
+    DivinationCard,
+    Gem,
+    Map,
+    Essence,
+    Fossil,
+    Resonator,
+    Oil,
+    Incubator,
+    Beast,
+}
+
+impl ItemCategory {
+    /// Classifies an item by its frame type, trade category and base type. Items which are not
+    /// traded by name or base type, like rare equipment, have no category
+    pub fn classify(item: &Item) -> Option<Self> {
+        match item.frame_type.as_ref()? {
+            FrameType::Unique | FrameType::Foil | FrameType::SupporterFoil => {
+                return Some(ItemCategory::Unique)
+            }
+            FrameType::Gem => return Some(ItemCategory::Gem),
+            FrameType::DivinationCard => return Some(ItemCategory::DivinationCard),
+            FrameType::Quest | FrameType::Prophecy => return None,
+            _ => {}
+        }
+
+        let extended = item.extended.as_ref()?;
+        let subcategories = extended.subcategories.as_deref().unwrap_or_default();
+        let has_subcategory = |s: &str| subcategories.iter().any(|sub| sub == s);
+
+        let category = match extended.category.as_deref()? {
+            "currency" if has_subcategory("fossil") => ItemCategory::Fossil,
+            "currency" if has_subcategory("resonator") => ItemCategory::Resonator,
+            "currency" if has_subcategory("oil") => ItemCategory::Oil,
+            "currency" if has_subcategory("incubator") => ItemCategory::Incubator,
+            "currency" if is_essence(&item.base_type) => ItemCategory::Essence,
+            "currency" => ItemCategory::Currency,
+            "maps" if has_subcategory("scarab") => ItemCategory::Scarab,
+            "maps" if has_subcategory("breachstone") => ItemCategory::Breachstone,
+            "maps" if has_subcategory("invitation") => ItemCategory::Invitation,
+            "maps" if has_subcategory("fragment") => ItemCategory::Fragment,
+            "maps" => ItemCategory::Map,
+            "cards" => ItemCategory::DivinationCard,
+            "gems" => ItemCategory::Gem,
+            "monsters" => ItemCategory::Beast,
+            _ => return None,
+        };
+
+        Some(category)
+    }
+}
+
+fn is_essence(base_type: &str) -> bool {
+    base_type.contains("Essence of") || base_type == "Remnant of Corruption"
+}
+
+impl fmt::Display for ItemCategory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ItemCategory::Unique => write!(f, "unique"),
+            ItemCategory::Currency => write!(f, "currency"),
+            ItemCategory::Fragment => write!(f, "fragment"),
+            ItemCategory::Scarab => write!(f, "scarab"),
+            ItemCategory::Breachstone => write!(f, "breachstone"),
+            ItemCategory::Invitation => write!(f, "invitation"),
+            ItemCategory::DivinationCard => write!(f, "divination_card"),
+            ItemCategory::Gem => write!(f, "gem"),
+            ItemCategory::Map => write!(f, "map"),
+            ItemCategory::Essence => write!(f, "essence"),
+            ItemCategory::Fossil => write!(f, "fossil"),
+            ItemCategory::Resonator => write!(f, "resonator"),
+            ItemCategory::Oil => write!(f, "oil"),
+            ItemCategory::Incubator => write!(f, "incubator"),
+            ItemCategory::Beast => write!(f, "beast"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use poe_types::item::{ExtendedValues, FrameType, Item};
+
+    use super::ItemCategory;
+
+    fn item(frame_type: FrameType, category: &str, subcategories: &[&str], base: &str) -> Item {
+        Item {
+            frame_type: Some(frame_type),
+            base_type: base.to_owned(),
+            extended: Some(ExtendedValues {
+                category: Some(category.to_owned()),
+                subcategories: Some(subcategories.iter().map(|s| s.to_string()).collect()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn classifies_items() {
+        let cases = [
+            (
+                FrameType::Unique,
+                "armour",
+                &["helmet"][..],
+                "Hubris Circlet",
+                Some(ItemCategory::Unique),
+            ),
+            (
+                FrameType::Foil,
+                "weapons",
+                &["bow"],
+                "Imperial Bow",
+                Some(ItemCategory::Unique),
+            ),
+            (
+                FrameType::Currency,
+                "currency",
+                &[],
+                "Divine Orb",
+                Some(ItemCategory::Currency),
+            ),
+            (
+                FrameType::Currency,
+                "currency",
+                &["fossil"],
+                "Pristine Fossil",
+                Some(ItemCategory::Fossil),
+            ),
+            (
+                FrameType::Currency,
+                "currency",
+                &["resonator"],
+                "Potent Chaotic Resonator",
+                Some(ItemCategory::Resonator),
+            ),
+            (
+                FrameType::Currency,
+                "currency",
+                &[],
+                "Deafening Essence of Greed",
+                Some(ItemCategory::Essence),
+            ),
+            (
+                FrameType::Normal,
+                "maps",
+                &["fragment", "scarab"],
+                "Gilded Sulphite Scarab",
+                Some(ItemCategory::Scarab),
+            ),
+            (
+                FrameType::Normal,
+                "maps",
+                &["fragment"],
+                "Sacrifice at Dusk",
+                Some(ItemCategory::Fragment),
+            ),
+            (
+                FrameType::Normal,
+                "maps",
+                &["breachstone"],
+                "Xoph's Breachstone",
+                Some(ItemCategory::Breachstone),
+            ),
+            (
+                FrameType::Rare,
+                "maps",
+                &["invitation"],
+                "Maven's Invitation: The Atlas",
+                Some(ItemCategory::Invitation),
+            ),
+            (
+                FrameType::Magic,
+                "maps",
+                &[],
+                "Crimson Temple Map",
+                Some(ItemCategory::Map),
+            ),
+            (
+                FrameType::Gem,
+                "gems",
+                &["activegem"],
+                "Vaal Grace",
+                Some(ItemCategory::Gem),
+            ),
+            (
+                FrameType::DivinationCard,
+                "cards",
+                &[],
+                "The Doctor",
+                Some(ItemCategory::DivinationCard),
+            ),
+            (
+                FrameType::Rare,
+                "accessories",
+                &["ring"],
+                "Two-Stone Ring",
+                None,
+            ),
+            (
+                FrameType::Normal,
+                "sanctum",
+                &["research"],
+                "Forbidden Tome",
+                None,
+            ),
+        ];
+
+        for (frame_type, category, subcategories, base, expected) in cases {
+            assert_eq!(
+                ItemCategory::classify(&item(frame_type, category, subcategories, base)),
+                expected,
+                "{base}"
+            );
+        }
+    }
+
+    #[test]
+    fn items_without_a_frame_type() {
+        assert_eq!(ItemCategory::classify(&Item::default()), None);
+    }
+}
@@ -1,11 +1,21 @@
 use std::env;
 
-use clickhouse::{error::Error::RowNotFound, Row};
+use clickhouse::Row;
+use ledger_service::schema;
+use poe_types::league::LeagueIdentity;
 use serde::{Deserialize, Serialize};
 use time::OffsetDateTime;
 
-use crate::listing::Listing;
+use crate::{
+    accounts::ChurnPolicy,
+    leagues::RegisteredLeague,
+    lifecycle::ListingEvent,
+    listing::{Listing, ListingCurrency},
+    migrations,
+    rates::{Rate, RateSnapshot},
+};
 
+#[derive(Clone)]
 pub struct ClickhouseDatabase {
     client: clickhouse::Client,
 }
@@ -13,48 +23,210 @@ pub struct ClickhouseDatabase {
 #[derive(Row, Serialize, Deserialize)]
 pub struct ListingChRow {
     pub item_id: String,
+    pub stash_id: String,
+    pub account_name: String,
     pub name: String,
+    pub base_type: String,
+    pub category: String,
+    pub variant: String,
+    pub icon: String,
+    pub stack_size: u32,
     pub league: String,
     pub normalized_price: f64,
     pub listed_price: f64,
     pub listed_currency: String,
+    pub listed_amount: f64,
+    pub listed_units: f64,
     pub implicit_mods: Vec<String>,
     pub explicit_mods: Vec<String>,
+    /// kind, stat template and values of every mod, one entry per mod in each
+    pub mod_kinds: Vec<String>,
+    pub mod_stats: Vec<String>,
+    pub mod_values: Vec<Vec<f64>>,
     #[serde(with = "clickhouse::serde::time::datetime")]
     pub created_at: OffsetDateTime,
+    pub rate_snapshot_id: u64,
+    pub price_source: String,
+    pub outlier_score: f64,
+    pub is_outlier: bool,
+}
+
+#[derive(Row, Serialize, Deserialize)]
+pub struct ListingEventChRow {
+    pub event: String,
+    pub stash_id: String,
+    pub account_name: String,
+    pub item_id: String,
+    pub name: String,
+    pub league: String,
+    pub category: String,
+    pub listed_price: f64,
+    pub listed_currency: String,
+    pub normalized_price: f64,
+    pub previous_price: f64,
+    pub previous_currency: String,
+    pub previous_normalized_price: f64,
+    #[serde(with = "clickhouse::serde::time::datetime")]
+    pub first_listed_at: OffsetDateTime,
+    pub time_on_market: u64,
+    #[serde(with = "clickhouse::serde::time::datetime")]
+    pub created_at: OffsetDateTime,
+}
+
+impl From<&ListingEvent> for ListingEventChRow {
+    fn from(e: &ListingEvent) -> Self {
+        let (previous_price, previous_currency, previous_normalized_price) = match &e.previous {
+            Some(p) => (
+                p.listed_price,
+                p.listed_currency.clone(),
+                p.normalized_price,
+            ),
+            None => (0.0, String::new(), 0.0),
+        };
+
+        Self {
+            event: e.kind.to_string(),
+            stash_id: e.stash_id.clone(),
+            account_name: e.item.account_name.clone(),
+            item_id: e.item_id.clone(),
+            name: e.item.name.clone(),
+            league: e.item.league.clone(),
+            category: e.item.category.clone(),
+            listed_price: e.item.listed_price,
+            listed_currency: e.item.listed_currency.clone(),
+            normalized_price: e.item.normalized_price,
+            previous_price,
+            previous_currency,
+            previous_normalized_price,
+            first_listed_at: e.item.first_listed_at,
+            time_on_market: e.time_on_market(),
+            created_at: e.created_at,
+        }
+    }
+}
+
+#[derive(Row, Serialize, Deserialize)]
+pub struct CurrencyRateChRow {
+    pub snapshot_id: u64,
+    pub league: String,
+    pub currency: String,
+    pub chaos_equivalent: f64,
+    pub observations: u32,
+    #[serde(with = "clickhouse::serde::time::datetime")]
+    pub updated_at: OffsetDateTime,
+    #[serde(with = "clickhouse::serde::time::datetime")]
+    pub created_at: OffsetDateTime,
+}
+
+#[derive(Row, Serialize, Deserialize)]
+pub struct LeagueChRow {
+    pub name: String,
+    pub base: String,
+    pub kind: String,
+    pub hardcore: bool,
+    pub solo_self_found: bool,
+    pub ruthless: bool,
+    pub private: bool,
+    #[serde(with = "clickhouse::serde::time::datetime")]
+    pub first_seen: OffsetDateTime,
+    #[serde(with = "clickhouse::serde::time::datetime")]
+    pub last_seen: OffsetDateTime,
+}
+
+impl From<&RegisteredLeague> for LeagueChRow {
+    fn from(l: &RegisteredLeague) -> Self {
+        Self {
+            name: l.identity.name.clone(),
+            base: l.identity.base.clone(),
+            kind: l.identity.kind.to_string(),
+            hardcore: l.identity.hardcore,
+            solo_self_found: l.identity.solo_self_found,
+            ruthless: l.identity.ruthless,
+            private: l.identity.private,
+            first_seen: l.first_seen,
+            last_seen: l.last_seen,
+        }
+    }
+}
+
+/// An account in a league with the listings it has up and those it removed recently, as read
+/// from the account aggregates
+#[derive(Row, Serialize, Deserialize)]
+pub struct AccountChurnChRow {
+    pub account_name: String,
+    pub league: String,
+    pub active_listings: i64,
+    pub recent_removed: u64,
+    pub churn_rate: f64,
+}
+
+/// Everything seen of an item across its listings, which search documents are rebuilt from
+#[derive(Row, Serialize, Deserialize)]
+pub struct ItemSummaryChRow {
+    pub name: String,
+    pub base_type: String,
+    pub category: String,
+    pub icon: String,
+    pub variants: Vec<String>,
+    pub leagues: Vec<String>,
+    #[serde(with = "clickhouse::serde::time::datetime")]
+    pub first_seen: OffsetDateTime,
+    #[serde(with = "clickhouse::serde::time::datetime")]
+    pub last_seen: OffsetDateTime,
+    pub listing_count: u64,
 }
 
 impl From<&Listing> for ListingChRow {
     fn from(l: &Listing) -> Self {
         Self {
             item_id: l.item_id.clone(),
+            stash_id: l.stash_id.clone(),
+            account_name: l.account_name.clone(),
             name: l.name.clone(),
+            base_type: l.base_type.clone(),
+            category: l.category.to_string(),
+            variant: l.variant.clone(),
+            icon: l.icon.clone(),
+            stack_size: l.stack_size,
             league: l.league.clone(),
             normalized_price: l.price.normalized_price,
             listed_price: l.price.listed_price,
             listed_currency: l.price.listed_currency.to_string(),
+            listed_amount: l.price.listed_amount,
+            listed_units: l.price.listed_units,
             implicit_mods: l.implicit_mods.clone(),
             explicit_mods: l.explicit_mods.clone(),
+            mod_kinds: l.mods.iter().map(|m| m.kind.to_string()).collect(),
+            mod_stats: l.mods.iter().map(|m| m.stat.clone()).collect(),
+            mod_values: l.mods.iter().map(|m| m.values.clone()).collect(),
             created_at: l.created_at,
+            rate_snapshot_id: l.price.rate_snapshot_id,
+            price_source: l.price_source.to_string(),
+            outlier_score: l.outlier_score,
+            is_outlier: l.is_outlier,
         }
     }
 }
 
-impl ClickhouseDatabase {
-    pub async fn new() -> Self {
-        let url = env::var("CLICKHOUSE_URL").unwrap_or("http://localhost:8123".to_owned());
+/// Client for the Clickhouse instance in `CLICKHOUSE_URL`, not bound to any database
+pub fn client_from_env() -> clickhouse::Client {
+    let url = env::var("CLICKHOUSE_URL").unwrap_or("http://localhost:8123".to_owned());
 
-        let user = env::var("CLICKHOUSE_USER");
-        let password = env::var("CLICKHOUSE_PASSWORD");
+    let user = env::var("CLICKHOUSE_USER");
+    let password = env::var("CLICKHOUSE_PASSWORD");
 
-        let dbname = "ledger";
-        let mut client = clickhouse::Client::default()
-            .with_url(url)
-            .with_database(dbname);
+    let mut client = clickhouse::Client::default().with_url(url);
 
-        if let (Ok(u), Ok(p)) = (user, password) {
-            client = client.with_user(u).with_password(p);
-        }
+    if let (Ok(u), Ok(p)) = (user, password) {
+        client = client.with_user(u).with_password(p);
+    }
+
+    client
+}
+
+impl ClickhouseDatabase {
+    pub async fn new() -> Self {
+        let client = client_from_env();
 
         // ensure client connects to Clickhouse
         if let Err(e) = client.query("SELECT 1").execute().await {
@@ -64,33 +236,29 @@ impl ClickhouseDatabase {
             std::process::exit(1);
         }
 
+        // refuse to write to a schema older than the one listings are built for
+        if let Err(e) = schema::check(&client, migrations::latest_version()).await {
+            tracing::error!("{e:#}");
+            tracing::error!("exiting!");
+
+            std::process::exit(1);
+        }
+
         tracing::info!("connected to Clickhouse!");
 
-        Self { client }
+        let dbname = "ledger";
+        Self {
+            client: client.with_database(dbname),
+        }
     }
 
-    pub async fn exists(&self, id: &str) -> anyhow::Result<bool> {
-        let qr = self
-            .client
-            .query("SELECT item_id FROM listings WHERE item_id = ?")
-            .bind(id)
-            .fetch_one::<ListingChRow>()
-            .await;
-
-        match qr {
-            Ok(_) => Ok(true),
-            Err(e) => match e {
-                RowNotFound => Ok(false),
-                _ => {
-                    tracing::error!("failed to fetch listing with ID: {id} - {e}");
-
-                    Err(e.into())
-                }
-            },
-        }
+    pub async fn ping(&self) -> anyhow::Result<()> {
+        self.client.query("SELECT 1").execute().await?;
+
+        Ok(())
     }
 
-    pub async fn create_batch(&self, listings: &Vec<Listing>) -> anyhow::Result<()> {
+    pub async fn create_batch(&self, listings: &[Listing]) -> anyhow::Result<()> {
         let mut insert = self.client.insert("listings")?;
 
         for l in listings {
@@ -102,4 +270,161 @@ impl ClickhouseDatabase {
 
         Ok(())
     }
+
+    pub async fn insert_events(&self, events: &[ListingEvent]) -> anyhow::Result<()> {
+        let mut insert = self.client.insert("listing_events")?;
+
+        for e in events {
+            insert.write(&ListingEventChRow::from(e)).await?;
+        }
+
+        insert.end().await?;
+
+        Ok(())
+    }
+
+    pub async fn insert_rate_snapshot(&self, snapshot: &RateSnapshot) -> anyhow::Result<()> {
+        let mut insert = self.client.insert("currency_rates")?;
+
+        for ((league, currency), rate) in &snapshot.rates {
+            let ch_row = CurrencyRateChRow {
+                snapshot_id: snapshot.id,
+                league: league.clone(),
+                currency: currency.to_string(),
+                chaos_equivalent: rate.chaos_equivalent,
+                observations: rate.observations as u32,
+                updated_at: rate.updated_at,
+                created_at: snapshot.created_at,
+            };
+            insert.write(&ch_row).await?;
+        }
+
+        insert.end().await?;
+
+        Ok(())
+    }
+
+    pub async fn upsert_leagues(&self, leagues: &[RegisteredLeague]) -> anyhow::Result<()> {
+        let mut insert = self.client.insert("leagues")?;
+
+        for l in leagues {
+            insert.write(&LeagueChRow::from(l)).await?;
+        }
+
+        insert.end().await?;
+
+        Ok(())
+    }
+
+    /// Loads every league in the registry, identities are read from the name again so changes
+    /// to how names are read apply to stored leagues
+    pub async fn leagues(&self) -> anyhow::Result<Vec<RegisteredLeague>> {
+        let rows = self
+            .client
+            .query("SELECT ?fields FROM leagues FINAL")
+            .fetch_all::<LeagueChRow>()
+            .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| RegisteredLeague {
+                identity: LeagueIdentity::from_name(&row.name),
+                first_seen: row.first_seen,
+                last_seen: row.last_seen,
+            })
+            .collect())
+    }
+
+    /// Summarizes every item listed so far, one row per name
+    pub async fn item_summaries(&self) -> anyhow::Result<Vec<ItemSummaryChRow>> {
+        let rows = self
+            .client
+            .query(
+                "SELECT
+                    name,
+                    any(base_type) AS base_type,
+                    any(category) AS category,
+                    argMax(icon, created_at) AS icon,
+                    arrayFilter(v -> v != '', groupUniqArray(variant)) AS variants,
+                    groupUniqArray(league) AS leagues,
+                    min(created_at) AS first_seen,
+                    max(created_at) AS last_seen,
+                    count() AS listing_count
+                FROM listings
+                GROUP BY name",
+            )
+            .fetch_all::<ItemSummaryChRow>()
+            .await?;
+
+        Ok(rows)
+    }
+
+    /// Accounts which removed at least the policy's share of the listings they had up during its
+    /// window, keyed by account name and league. Active listings count every listing event
+    /// since accounts were tracked, the removals only those within the window
+    pub async fn churning_accounts(
+        &self,
+        policy: &ChurnPolicy,
+    ) -> anyhow::Result<Vec<(String, String)>> {
+        let rows = self
+            .client
+            .query(
+                "SELECT
+                    account_name,
+                    league,
+                    greatest(toInt64(sum(listed)) - toInt64(sum(removed)) - toInt64(sum(unpriced)), 0) AS active_listings,
+                    sumIf(removed, day > today() - ?) AS recent_removed,
+                    if(active_listings + recent_removed > 0,
+                        recent_removed / (active_listings + recent_removed), 0) AS churn_rate
+                FROM account_activity
+                GROUP BY account_name, league
+                HAVING active_listings + recent_removed >= ? AND churn_rate >= ?",
+            )
+            .bind(policy.window_days)
+            .bind(policy.min_listings)
+            .bind(policy.rate)
+            .fetch_all::<AccountChurnChRow>()
+            .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| (row.account_name, row.league))
+            .collect())
+    }
+
+    /// Loads the most recently stored currency rate snapshot, if any
+    pub async fn latest_rate_snapshot(&self) -> anyhow::Result<Option<RateSnapshot>> {
+        let rows = self
+            .client
+            .query(
+                "SELECT ?fields FROM currency_rates
+                WHERE snapshot_id = (SELECT max(snapshot_id) FROM currency_rates)",
+            )
+            .fetch_all::<CurrencyRateChRow>()
+            .await?;
+
+        let Some(first) = rows.first() else {
+            return Ok(None);
+        };
+
+        let mut snapshot = RateSnapshot {
+            id: first.snapshot_id,
+            created_at: first.created_at,
+            ..Default::default()
+        };
+        for row in rows {
+            let rate = Rate {
+                chaos_equivalent: row.chaos_equivalent,
+                observations: row.observations as usize,
+                updated_at: row.updated_at,
+            };
+            // rates of currencies since removed from the catalogue are dropped
+            let Some(currency) = ListingCurrency::from_tag(&row.currency) else {
+                continue;
+            };
+            snapshot.rates.insert((row.league, currency), rate);
+        }
+
+        Ok(Some(snapshot))
+    }
 }
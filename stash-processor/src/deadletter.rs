@@ -0,0 +1,396 @@
+use std::{env, time::Duration};
+
+use anyhow::Context;
+use async_nats::{
+    header::NATS_MESSAGE_ID,
+    jetstream::{
+        self,
+        consumer::{pull, AckPolicy, PullConsumer},
+    },
+    HeaderMap,
+};
+use poe_types::item::Item;
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+use tokio_stream::StreamExt;
+
+use crate::{
+    category::ItemCategory, lifecycle::StashStateStore, listing::Listing, pipeline::FailedItem,
+    processor::StashProcessor, sink::ListingSink,
+};
+
+/// Stream backing the failure subjects, see `infra/local/nats/streams/FailedStashes.json`
+pub const FAILED_STREAM: &str = "FailedStashes";
+pub const STASHES_SUBJECT: &str = "river.stashes";
+pub const FAILED_STASHES_SUBJECT: &str = "river.failed_stashes";
+pub const FAILED_ITEMS_SUBJECT: &str = "river.failed_items";
+
+/// Headers describing why a message was dead-lettered, stripped again when it is redriven
+pub const ERROR_HEADER: &str = "Ledger-Error";
+pub const FAILED_AT_HEADER: &str = "Ledger-Failed-At";
+pub const DELIVERIES_HEADER: &str = "Ledger-Deliveries";
+pub const STASH_ID_HEADER: &str = "Ledger-Stash-Id";
+pub const LEAGUE_HEADER: &str = "Ledger-League";
+pub const ACCOUNT_HEADER: &str = "Ledger-Account";
+pub const STASH_NOTE_HEADER: &str = "Ledger-Stash-Note";
+const HEADER_PREFIX: &str = "Ledger-";
+
+const REDRIVE_BATCH: usize = 100;
+
+/// RetryPolicy decides how long a message is nak'd for when writing its listings fails, and
+/// when to give up on it and dead-letter it instead
+pub struct RetryPolicy {
+    base_delay: Duration,
+    max_delay: Duration,
+    max_deliveries: u64,
+}
+
+impl RetryPolicy {
+    pub fn new(base_delay: Duration, max_delay: Duration, max_deliveries: u64) -> Self {
+        Self {
+            base_delay,
+            max_delay,
+            max_deliveries,
+        }
+    }
+
+    pub fn from_env() -> Self {
+        let number = |key: &str, default: u64| {
+            env::var(key)
+                .map(|v| {
+                    v.parse::<u64>()
+                        .unwrap_or_else(|_| panic!("{key} must be a number"))
+                })
+                .unwrap_or(default)
+        };
+
+        Self::new(
+            Duration::from_millis(number("SINK_RETRY_BASE_MS", 1_000)),
+            Duration::from_millis(number("SINK_RETRY_MAX_MS", 60_000)),
+            number("SINK_MAX_DELIVERIES", 10),
+        )
+    }
+
+    /// Delay before redelivering a message which has been delivered `deliveries` times, doubling
+    /// with every attempt up to the max delay
+    pub fn delay(&self, deliveries: u64) -> Duration {
+        let exponent = deliveries.saturating_sub(1).min(31) as u32;
+
+        self.base_delay
+            .saturating_mul(2u32.pow(exponent))
+            .min(self.max_delay)
+    }
+
+    pub fn exhausted(&self, deliveries: u64) -> bool {
+        deliveries >= self.max_deliveries
+    }
+}
+
+/// Copies the headers of a failed message, adding why and when it failed. The msg id is left
+/// out, the stream would drop a message published again within its duplicate window otherwise
+pub fn failure_headers(
+    original: Option<&HeaderMap>,
+    error: &str,
+    deliveries: u64,
+    now: OffsetDateTime,
+) -> HeaderMap {
+    let mut headers = copy_headers(original, |name| !is_msg_id(name));
+
+    // header values can't span lines
+    let error = error.replace(['\r', '\n'], " ");
+    headers.insert(ERROR_HEADER, error.as_str());
+    headers.insert(
+        FAILED_AT_HEADER,
+        now.format(&Rfc3339).unwrap_or_default().as_str(),
+    );
+    headers.insert(DELIVERIES_HEADER, deliveries.to_string().as_str());
+
+    headers
+}
+
+/// Headers of a failed item, carrying the stash it was in so it can be converted again later
+pub fn failed_item_headers(failed: &FailedItem, now: OffsetDateTime) -> HeaderMap {
+    let mut headers = failure_headers(None, &failed.error, 1, now);
+
+    headers.insert(STASH_ID_HEADER, failed.stash_id.as_str());
+    if let Some(league) = &failed.league {
+        headers.insert(LEAGUE_HEADER, league.as_str());
+    }
+    if let Some(account) = &failed.account_name {
+        headers.insert(ACCOUNT_HEADER, account.as_str());
+    }
+    if let Some(note) = &failed.stash_note {
+        headers.insert(STASH_NOTE_HEADER, note.as_str());
+    }
+
+    headers
+}
+
+/// The original headers of a dead-lettered message, without the failure headers or a msg id
+pub fn strip_failure_headers(headers: Option<&HeaderMap>) -> HeaderMap {
+    copy_headers(headers, |name| {
+        !name.starts_with(HEADER_PREFIX) && !is_msg_id(name)
+    })
+}
+
+fn copy_headers(headers: Option<&HeaderMap>, keep: impl Fn(&str) -> bool) -> HeaderMap {
+    let mut copied = HeaderMap::new();
+
+    for (name, values) in headers.iter().flat_map(|h| h.iter()) {
+        let name: &str = name.as_ref();
+        if !keep(name) {
+            continue;
+        }
+
+        for value in values {
+            copied.append(name, value.clone());
+        }
+    }
+
+    copied
+}
+
+fn is_msg_id(name: &str) -> bool {
+    name.eq_ignore_ascii_case(NATS_MESSAGE_ID.as_ref())
+}
+
+/// Publishes a message, waiting for the stream to store it so it can't be lost
+pub async fn publish_durable(
+    jetstream: &jetstream::Context,
+    subject: &'static str,
+    headers: HeaderMap,
+    payload: Vec<u8>,
+) -> anyhow::Result<()> {
+    jetstream
+        .publish_with_headers(subject, headers, payload.into())
+        .await?
+        .await?;
+
+    Ok(())
+}
+
+#[derive(Debug, Default)]
+pub struct RedriveSummary {
+    pub stashes: usize,
+    pub items: usize,
+    pub listings: usize,
+    /// items which still fail to convert, they are left in the stream
+    pub failed: usize,
+}
+
+/// Re-injects dead-lettered messages on `subject`, or on every failure subject when none is given.
+///
+/// Stashes are published back to `river.stashes` to go through the processor again. Items are
+/// converted into listings here and run through the rest of the processor's stages, then written
+/// to `sink`. Messages which were redriven are removed from the stream, with `dry_run` nothing is
+/// published, written or removed.
+pub async fn redrive<S: StashStateStore>(
+    jetstream: &jetstream::Context,
+    processor: &mut StashProcessor<S>,
+    sink: &dyn ListingSink,
+    subject: Option<&str>,
+    limit: Option<usize>,
+    dry_run: bool,
+) -> anyhow::Result<RedriveSummary> {
+    let stream = jetstream
+        .get_stream(FAILED_STREAM)
+        .await
+        .context("failed getting the failed stashes stream")?;
+    let mut consumer: PullConsumer = stream
+        .create_consumer(pull::Config {
+            filter_subject: subject.unwrap_or_default().to_owned(),
+            ack_policy: AckPolicy::Explicit,
+            inactive_threshold: Duration::from_secs(60),
+            ..Default::default()
+        })
+        .await?;
+
+    let pending = consumer.info().await?.num_pending as usize;
+    let total = limit.map_or(pending, |l| l.min(pending));
+    tracing::info!("redriving {total} of {pending} dead-lettered messages");
+
+    let mut summary = RedriveSummary::default();
+    let mut seen = 0;
+    while seen < total {
+        let mut batch = consumer
+            .fetch()
+            .max_messages((total - seen).min(REDRIVE_BATCH))
+            .messages()
+            .await?;
+
+        let mut listings = Vec::new();
+        let mut redriven_items = Vec::new();
+        let mut received = 0;
+        while let Some(m) = batch.next().await {
+            let m = m.map_err(|e| anyhow::anyhow!(e))?;
+            received += 1;
+
+            match m.subject.as_str() {
+                FAILED_STASHES_SUBJECT => {
+                    summary.stashes += 1;
+                    if dry_run {
+                        continue;
+                    }
+
+                    publish_durable(
+                        jetstream,
+                        STASHES_SUBJECT,
+                        strip_failure_headers(m.headers.as_ref()),
+                        m.payload.to_vec(),
+                    )
+                    .await?;
+                    if let Err(e) = m.ack().await {
+                        tracing::error!("couldn't ack redriven stash: {e}");
+                    }
+                }
+                FAILED_ITEMS_SUBJECT => {
+                    summary.items += 1;
+                    match item_to_listing(m.headers.as_ref(), &m.payload) {
+                        Ok(listing) => {
+                            listings.push(listing);
+                            redriven_items.push(m);
+                        }
+                        Err(e) => {
+                            tracing::warn!("dead-lettered item still fails to convert: {e:#}");
+                            summary.failed += 1;
+                        }
+                    }
+                }
+                other => tracing::warn!("ignoring dead-lettered message on {other}"),
+            }
+        }
+
+        seen += received;
+        summary.listings += listings.len();
+
+        if !dry_run && !listings.is_empty() {
+            let page = processor
+                .process_redriven(listings, OffsetDateTime::now_utc())
+                .await?;
+            processor
+                .write(sink, &page.extracted.listings, &page.events)
+                .await?;
+
+            for m in redriven_items {
+                if let Err(e) = m.ack().await {
+                    tracing::error!("couldn't ack redriven item: {e}");
+                }
+            }
+        }
+
+        if received == 0 {
+            break;
+        }
+    }
+
+    let name = consumer.cached_info().name.clone();
+    if let Err(e) = stream.delete_consumer(&name).await {
+        tracing::warn!("failed deleting redrive consumer {name}: {e}");
+    }
+
+    Ok(summary)
+}
+
+/// Converts a dead-lettered item back into a listing, using the stash recorded in its headers
+fn item_to_listing(headers: Option<&HeaderMap>, payload: &[u8]) -> anyhow::Result<Listing> {
+    let header = |name: &str| headers.and_then(|h| h.get(name)).map(|v| v.as_str());
+
+    let mut item = serde_json::from_slice::<Item>(payload).context("failed parsing item")?;
+    if let Some(league) = header(LEAGUE_HEADER) {
+        item.league = Some(league.to_owned());
+    }
+    let category = ItemCategory::classify(&item).context("items must be a tradable class")?;
+
+    let mut listing = Listing::from_item(item, category, header(STASH_NOTE_HEADER))?;
+    listing.stash_id = header(STASH_ID_HEADER).unwrap_or_default().to_owned();
+    listing.account_name = header(ACCOUNT_HEADER).unwrap_or_default().to_owned();
+
+    Ok(listing)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use async_nats::{header::NATS_MESSAGE_ID, HeaderMap};
+    use poe_types::item::{FrameType, Item};
+    use time::macros::datetime;
+
+    use super::{
+        failure_headers, item_to_listing, strip_failure_headers, RetryPolicy, DELIVERIES_HEADER,
+        ERROR_HEADER, FAILED_AT_HEADER, LEAGUE_HEADER, STASH_ID_HEADER, STASH_NOTE_HEADER,
+    };
+    use crate::batch::CONTENT_ENCODING_HEADER;
+
+    #[test]
+    fn retries_back_off() {
+        let policy = RetryPolicy::new(Duration::from_secs(1), Duration::from_secs(30), 10);
+
+        assert_eq!(policy.delay(1), Duration::from_secs(1));
+        assert_eq!(policy.delay(2), Duration::from_secs(2));
+        assert_eq!(policy.delay(4), Duration::from_secs(8));
+        assert_eq!(policy.delay(8), Duration::from_secs(30));
+        assert_eq!(policy.delay(u64::MAX), Duration::from_secs(30));
+
+        assert!(!policy.exhausted(9));
+        assert!(policy.exhausted(10));
+    }
+
+    #[test]
+    fn failure_headers_round_trip() {
+        let mut original = HeaderMap::new();
+        original.insert(CONTENT_ENCODING_HEADER, "zstd");
+        original.insert(NATS_MESSAGE_ID, "change-id-0");
+
+        let headers = failure_headers(
+            Some(&original),
+            "failed parsing\nstash batch",
+            3,
+            datetime!(2024-04-10 12:00 UTC),
+        );
+        assert_eq!(
+            headers.get(ERROR_HEADER).unwrap().as_str(),
+            "failed parsing stash batch"
+        );
+        assert_eq!(
+            headers.get(FAILED_AT_HEADER).unwrap().as_str(),
+            "2024-04-10T12:00:00Z"
+        );
+        assert_eq!(headers.get(DELIVERIES_HEADER).unwrap().as_str(), "3");
+        assert!(headers.get(NATS_MESSAGE_ID).is_none());
+
+        let stripped = strip_failure_headers(Some(&headers));
+        assert_eq!(
+            stripped.get(CONTENT_ENCODING_HEADER).unwrap().as_str(),
+            "zstd"
+        );
+        assert!(stripped.get(ERROR_HEADER).is_none());
+
+        // messages dead-lettered while their msg id was still copied drop it when redriven
+        let stripped = strip_failure_headers(Some(&original));
+        assert!(stripped.get(NATS_MESSAGE_ID).is_none());
+    }
+
+    #[test]
+    fn failed_items_keep_their_stash() {
+        let mut headers = HeaderMap::new();
+        headers.insert(STASH_ID_HEADER, "stash");
+        headers.insert(STASH_NOTE_HEADER, "~price 2 divine");
+        headers.insert(LEAGUE_HEADER, "Necropolis");
+
+        let item = serde_json::to_vec(&Item {
+            id: Some("a".to_owned()),
+            name: "Mageblood".to_owned(),
+            league: Some("Standard".to_owned()),
+            frame_type: Some(FrameType::Unique),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let listing = item_to_listing(Some(&headers), &item).unwrap();
+        assert_eq!(listing.stash_id, "stash");
+        assert_eq!(listing.price.listed_price, 2.0);
+        assert_eq!(listing.league, "Necropolis");
+
+        assert!(item_to_listing(None, &item).is_err());
+    }
+}
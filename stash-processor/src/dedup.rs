@@ -0,0 +1,137 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashSet},
+    env,
+    hash::{Hash, Hasher},
+};
+
+use crate::listing::Listing;
+
+/// DedupCache remembers recently inserted listings, so an item re-sent every time its stash tab
+/// is edited is only inserted again when its price changes.
+///
+/// Listings are keyed by a hash of their item id, price and stack size, kept in two generations
+/// of at most `capacity` keys each. When the current generation is full it becomes the previous
+/// one and the oldest generation is dropped, so memory stays bounded while keys which keep being
+/// seen survive. Hash collisions can rarely drop a new listing, which is an accepted trade off.
+pub struct DedupCache {
+    current: HashSet<u64>,
+    previous: HashSet<u64>,
+    capacity: usize,
+}
+
+impl DedupCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            current: HashSet::new(),
+            previous: HashSet::new(),
+            capacity: capacity.max(1),
+        }
+    }
+
+    pub fn from_env() -> Self {
+        let capacity = env::var("DEDUP_CACHE_SIZE")
+            .map(|v| {
+                v.parse::<usize>()
+                    .expect("DEDUP_CACHE_SIZE must be a number")
+            })
+            .unwrap_or(1_000_000);
+
+        Self::new(capacity)
+    }
+
+    /// Records the listing, returning true when it was not seen recently
+    pub fn insert(&mut self, listing: &Listing) -> bool {
+        let key = listing_key(listing);
+
+        if self.current.contains(&key) {
+            return false;
+        }
+
+        let seen = self.previous.remove(&key);
+        if self.current.len() >= self.capacity {
+            self.previous = std::mem::take(&mut self.current);
+        }
+        self.current.insert(key);
+
+        !seen
+    }
+
+    /// Keeps only the listings which were not seen recently, returning how many were dropped
+    pub fn retain_new(&mut self, listings: &mut Vec<Listing>) -> usize {
+        let before = listings.len();
+        listings.retain(|l| self.insert(l));
+
+        before - listings.len()
+    }
+
+    /// Forgets listings which failed to be written, so they aren't dropped when retried
+    pub fn forget(&mut self, listings: &[Listing]) {
+        for listing in listings {
+            let key = listing_key(listing);
+            self.current.remove(&key);
+            self.previous.remove(&key);
+        }
+    }
+}
+
+fn listing_key(listing: &Listing) -> u64 {
+    let mut hasher = DefaultHasher::new();
+
+    listing.item_id.hash(&mut hasher);
+    listing.price.listed_price.to_bits().hash(&mut hasher);
+    listing.price.listed_currency.hash(&mut hasher);
+    listing.stack_size.hash(&mut hasher);
+
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DedupCache;
+    use crate::listing::Listing;
+
+    fn listing(item_id: &str, price: f64) -> Listing {
+        let mut listing = Listing {
+            item_id: item_id.to_owned(),
+            ..Default::default()
+        };
+        listing.price.listed_price = price;
+
+        listing
+    }
+
+    #[test]
+    fn repeated_listings_are_dropped() {
+        let mut cache = DedupCache::new(10);
+
+        assert!(cache.insert(&listing("a", 1.0)));
+        assert!(!cache.insert(&listing("a", 1.0)));
+        // a reprice is a new listing
+        assert!(cache.insert(&listing("a", 2.0)));
+
+        let mut listings = vec![listing("a", 2.0), listing("b", 1.0), listing("b", 1.0)];
+        assert_eq!(cache.retain_new(&mut listings), 2);
+        assert_eq!(listings.len(), 1);
+
+        cache.forget(&listings);
+        assert!(cache.insert(&listing("b", 1.0)));
+    }
+
+    #[test]
+    fn recently_seen_keys_survive_rotation() {
+        let mut cache = DedupCache::new(2);
+
+        cache.insert(&listing("a", 1.0));
+        cache.insert(&listing("b", 1.0));
+        // rotates, a and b are now the previous generation
+        cache.insert(&listing("c", 1.0));
+
+        // seeing a again promotes it to the current generation
+        assert!(!cache.insert(&listing("a", 1.0)));
+        // rotates again, dropping b
+        cache.insert(&listing("d", 1.0));
+
+        assert!(!cache.insert(&listing("a", 1.0)));
+        assert!(cache.insert(&listing("b", 1.0)));
+    }
+}
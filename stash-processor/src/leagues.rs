@@ -0,0 +1,95 @@
+use std::collections::HashMap;
+
+use poe_types::league::LeagueIdentity;
+use time::{Duration, OffsetDateTime};
+
+/// How often the last time a league was seen is written again
+const LEAGUE_WRITE_INTERVAL: Duration = Duration::hours(1);
+
+/// RegisteredLeague is a league listings have been seen in
+#[derive(Debug, Clone, PartialEq)]
+pub struct RegisteredLeague {
+    pub identity: LeagueIdentity,
+    pub first_seen: OffsetDateTime,
+    pub last_seen: OffsetDateTime,
+}
+
+/// LeagueRegistry tracks every league listings are seen in, so leagues are known from the river
+/// instead of being configured whenever one launches
+#[derive(Default)]
+pub struct LeagueRegistry {
+    leagues: HashMap<String, (RegisteredLeague, OffsetDateTime)>,
+}
+
+impl LeagueRegistry {
+    /// Restores leagues stored by a previous run, keeping when they were first seen
+    pub fn restore(&mut self, leagues: Vec<RegisteredLeague>) {
+        for league in leagues {
+            let written_at = league.last_seen;
+            self.leagues
+                .insert(league.identity.name.clone(), (league, written_at));
+        }
+    }
+
+    /// Records the leagues of a batch of listings, returning the leagues which need writing
+    /// because they are new or were last written over an hour ago
+    pub fn observe<'a>(
+        &mut self,
+        leagues: impl IntoIterator<Item = &'a str>,
+        now: OffsetDateTime,
+    ) -> Vec<RegisteredLeague> {
+        let mut changed = Vec::new();
+
+        for name in leagues {
+            if name.is_empty() {
+                continue;
+            }
+
+            match self.leagues.get_mut(name) {
+                Some((league, written_at)) => {
+                    league.last_seen = league.last_seen.max(now);
+                    if now - *written_at >= LEAGUE_WRITE_INTERVAL {
+                        *written_at = now;
+                        changed.push(league.clone());
+                    }
+                }
+                None => {
+                    let league = RegisteredLeague {
+                        identity: LeagueIdentity::from_name(name),
+                        first_seen: now,
+                        last_seen: now,
+                    };
+                    self.leagues.insert(name.to_owned(), (league.clone(), now));
+                    changed.push(league);
+                }
+            }
+        }
+
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use time::{macros::datetime, Duration};
+
+    use super::LeagueRegistry;
+
+    #[test]
+    fn leagues_are_written_when_new_or_stale() {
+        let mut registry = LeagueRegistry::default();
+        let now = datetime!(2024-04-10 12:00 UTC);
+
+        let written = registry.observe(["Necropolis", "Necropolis", "Standard"], now);
+        assert_eq!(written.len(), 2);
+
+        let written = registry.observe(["Necropolis"], now + Duration::minutes(5));
+        assert!(written.is_empty());
+
+        let written = registry.observe(["Necropolis"], now + Duration::hours(2));
+        assert_eq!(written.len(), 1);
+        assert_eq!(written[0].first_seen, now);
+        assert_eq!(written[0].last_seen, now + Duration::hours(2));
+        assert!(written[0].identity.is_main_challenge());
+    }
+}
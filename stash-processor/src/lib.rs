@@ -0,0 +1,22 @@
+pub mod accounts;
+pub mod batch;
+pub mod buffer;
+pub mod category;
+pub mod db;
+pub mod deadletter;
+pub mod dedup;
+pub mod leagues;
+pub mod lifecycle;
+pub mod listing;
+pub mod migrations;
+pub mod mods;
+pub mod note;
+pub mod outliers;
+pub mod pipeline;
+pub mod processor;
+pub mod rates;
+pub mod replay;
+pub mod search;
+pub mod sink;
+pub mod telemetry;
+pub mod variant;
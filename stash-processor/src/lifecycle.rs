@@ -0,0 +1,620 @@
+use core::fmt;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Mutex,
+};
+
+use anyhow::bail;
+use async_nats::jetstream::{self, kv::Operation};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use crate::{listing::Listing, pipeline::ExtractedListings};
+
+const STASH_STATE_BUCKET: &str = "stash_state";
+
+/// ItemState is what was last seen of a listed item in a stash
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ItemState {
+    pub name: String,
+    pub league: String,
+    /// empty for items tracked before the account was kept
+    #[serde(default)]
+    pub account_name: String,
+    pub category: String,
+    pub listed_price: f64,
+    pub listed_currency: String,
+    pub normalized_price: f64,
+    #[serde(with = "time::serde::timestamp")]
+    pub first_listed_at: OffsetDateTime,
+}
+
+impl ItemState {
+    fn from_listing(listing: &Listing, first_listed_at: OffsetDateTime) -> Self {
+        Self {
+            name: listing.name.clone(),
+            league: listing.league.clone(),
+            account_name: listing.account_name.clone(),
+            category: listing.category.to_string(),
+            listed_price: listing.price.listed_price,
+            listed_currency: listing.price.listed_currency.to_string(),
+            normalized_price: listing.price.normalized_price,
+            first_listed_at,
+        }
+    }
+
+    fn same_price(&self, other: &Self) -> bool {
+        self.listed_price == other.listed_price && self.listed_currency == other.listed_currency
+    }
+}
+
+/// StashState is the set of listed items in a stash, keyed by item id
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StashState {
+    pub items: HashMap<String, ItemState>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListingEventKind {
+    Listed,
+    Repriced,
+    /// the item left the stash, most likely because it was sold
+    Removed,
+    /// the item is still in the stash but no longer priced, so it isn't for sale
+    Unpriced,
+}
+
+impl fmt::Display for ListingEventKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ListingEventKind::Listed => write!(f, "listed"),
+            ListingEventKind::Repriced => write!(f, "repriced"),
+            ListingEventKind::Removed => write!(f, "removed"),
+            ListingEventKind::Unpriced => write!(f, "unpriced"),
+        }
+    }
+}
+
+/// ListingEvent is a change to an item listed in a stash between two snapshots of the stash
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListingEvent {
+    pub kind: ListingEventKind,
+    pub stash_id: String,
+    pub item_id: String,
+    /// the item as of this event, for removed and unpriced items this is the last state seen
+    pub item: ItemState,
+    /// the price before a reprice
+    pub previous: Option<ItemState>,
+    pub created_at: OffsetDateTime,
+}
+
+impl ListingEvent {
+    /// Seconds between the item first being listed and this event
+    pub fn time_on_market(&self) -> u64 {
+        (self.created_at - self.item.first_listed_at)
+            .whole_seconds()
+            .max(0) as u64
+    }
+}
+
+/// Diffs the listings now in a stash against its previous state, returning the new state of the
+/// stash and the events which happened in between. Items in `unlisted` are still in a stash
+/// without being listed, so they weren't sold
+pub fn diff_stash(
+    stash_id: &str,
+    previous: &StashState,
+    listings: &[&Listing],
+    unlisted: &HashSet<String>,
+    now: OffsetDateTime,
+) -> (StashState, Vec<ListingEvent>) {
+    let mut state = StashState::default();
+    let mut events = Vec::new();
+
+    for listing in listings {
+        let event = |kind, item: &ItemState, previous: Option<ItemState>| ListingEvent {
+            kind,
+            stash_id: stash_id.to_owned(),
+            item_id: listing.item_id.clone(),
+            item: item.clone(),
+            previous,
+            created_at: now,
+        };
+
+        let item = match previous.items.get(&listing.item_id) {
+            Some(before) => {
+                let item = ItemState::from_listing(listing, before.first_listed_at);
+                if !item.same_price(before) {
+                    events.push(event(
+                        ListingEventKind::Repriced,
+                        &item,
+                        Some(before.clone()),
+                    ));
+                }
+
+                item
+            }
+            None => {
+                let item = ItemState::from_listing(listing, now);
+                events.push(event(ListingEventKind::Listed, &item, None));
+
+                item
+            }
+        };
+
+        state.items.insert(listing.item_id.clone(), item);
+    }
+
+    for (item_id, item) in &previous.items {
+        if !state.items.contains_key(item_id) {
+            let kind = match unlisted.contains(item_id) {
+                true => ListingEventKind::Unpriced,
+                false => ListingEventKind::Removed,
+            };
+
+            events.push(ListingEvent {
+                kind,
+                stash_id: stash_id.to_owned(),
+                item_id: item_id.clone(),
+                item: item.clone(),
+                previous: None,
+                created_at: now,
+            });
+        }
+    }
+
+    (state, events)
+}
+
+/// StoredState is a stash's state as read from a [`StashStateStore`], along with the revision
+/// it was read at
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct StoredState {
+    pub state: StashState,
+    /// 0 when the stash was never stored
+    pub revision: u64,
+}
+
+/// StashStateStore keeps the last seen state of every stash between stash changes. Writes are
+/// made against the revision the state was read at, and fail when the stash was written since,
+/// so processors handling the same stash can't overwrite each other's state
+#[async_trait]
+pub trait StashStateStore: Send + Sync {
+    /// The stash's state, empty when it was never stored or was deleted
+    async fn get(&self, stash_id: &str) -> anyhow::Result<StoredState>;
+    async fn put(&self, stash_id: &str, state: &StashState, revision: u64) -> anyhow::Result<()>;
+    async fn delete(&self, stash_id: &str, revision: u64) -> anyhow::Result<()>;
+}
+
+/// Stash state kept in the `stash_state` NATS KV bucket, shared by every processor
+pub struct NatsStashStateStore {
+    bucket: jetstream::kv::Store,
+}
+
+impl NatsStashStateStore {
+    pub async fn new(jetstream: &jetstream::Context) -> anyhow::Result<Self> {
+        let bucket = jetstream.get_key_value(STASH_STATE_BUCKET).await?;
+
+        Ok(Self { bucket })
+    }
+}
+
+#[async_trait]
+impl StashStateStore for NatsStashStateStore {
+    async fn get(&self, stash_id: &str) -> anyhow::Result<StoredState> {
+        let Some(entry) = self.bucket.entry(stash_id).await? else {
+            return Ok(StoredState::default());
+        };
+
+        // deleted stashes keep the revision of their delete marker, which the next write expects
+        let state = match entry.operation {
+            Operation::Put => serde_json::from_slice(&entry.value)?,
+            Operation::Delete | Operation::Purge => StashState::default(),
+        };
+
+        Ok(StoredState {
+            state,
+            revision: entry.revision,
+        })
+    }
+
+    async fn put(&self, stash_id: &str, state: &StashState, revision: u64) -> anyhow::Result<()> {
+        self.bucket
+            .update(stash_id, serde_json::to_vec(state)?.into(), revision)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn delete(&self, stash_id: &str, revision: u64) -> anyhow::Result<()> {
+        self.bucket
+            .delete_expect_revision(stash_id, Some(revision))
+            .await?;
+
+        Ok(())
+    }
+}
+
+/// Stash state kept in memory, for running without NATS
+#[derive(Default)]
+pub struct MemoryStashStateStore {
+    stashes: Mutex<HashMap<String, StoredState>>,
+}
+
+impl MemoryStashStateStore {
+    fn write(&self, stash_id: &str, state: StashState, revision: u64) -> anyhow::Result<()> {
+        let mut stashes = self.stashes.lock().unwrap();
+        let current = stashes.get(stash_id).map_or(0, |s| s.revision);
+        if current != revision {
+            bail!("stash {stash_id} is at revision {current}, not {revision}");
+        }
+
+        stashes.insert(
+            stash_id.to_owned(),
+            StoredState {
+                state,
+                revision: current + 1,
+            },
+        );
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl StashStateStore for MemoryStashStateStore {
+    async fn get(&self, stash_id: &str) -> anyhow::Result<StoredState> {
+        Ok(self
+            .stashes
+            .lock()
+            .unwrap()
+            .get(stash_id)
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    async fn put(&self, stash_id: &str, state: &StashState, revision: u64) -> anyhow::Result<()> {
+        self.write(stash_id, state.clone(), revision)
+    }
+
+    async fn delete(&self, stash_id: &str, revision: u64) -> anyhow::Result<()> {
+        self.write(stash_id, StashState::default(), revision)
+    }
+}
+
+/// ListingTracker follows the listings in each stash across stash changes.
+///
+/// New stash states are held back until [`commit`](Self::commit), which is called once the
+/// events diffed from them are written, so a message whose events failed to be written is diffed
+/// against the same state when it is redelivered. Committing fails when another processor wrote
+/// one of the stashes in the meantime, and the messages are then redelivered to be diffed against
+/// its state instead.
+///
+/// The crawler drops stashes which were made private, so their items are only seen as removed
+/// once the stash is made public again.
+pub struct ListingTracker<S: StashStateStore> {
+    store: S,
+    /// states diffed since the last commit, by stash id, with the revision of the stored state
+    /// they were diffed from
+    pending: HashMap<String, (StashState, u64)>,
+}
+
+impl<S: StashStateStore> ListingTracker<S> {
+    pub fn new(store: S) -> Self {
+        Self {
+            store,
+            pending: HashMap::new(),
+        }
+    }
+
+    /// Diffs every stash in the extracted listings against its last state, which is the pending
+    /// one if the stash was diffed since the last commit
+    pub async fn track(
+        &mut self,
+        extracted: &ExtractedListings,
+        now: OffsetDateTime,
+    ) -> anyhow::Result<Vec<ListingEvent>> {
+        let mut by_stash: HashMap<&str, Vec<&Listing>> = HashMap::new();
+        for listing in &extracted.listings {
+            by_stash
+                .entry(listing.stash_id.as_str())
+                .or_default()
+                .push(listing);
+        }
+
+        let mut events = Vec::new();
+        let mut seen = HashSet::new();
+        for stash_id in &extracted.stash_ids {
+            if !seen.insert(stash_id.as_str()) {
+                continue;
+            }
+
+            let listings = by_stash.remove(stash_id.as_str()).unwrap_or_default();
+            let (previous, revision) = self.last_state(stash_id).await?;
+            if previous.items.is_empty() && listings.is_empty() {
+                continue;
+            }
+
+            let (state, stash_events) = diff_stash(
+                stash_id,
+                &previous,
+                &listings,
+                &extracted.unlisted_item_ids,
+                now,
+            );
+            self.pending.insert(stash_id.clone(), (state, revision));
+
+            events.extend(stash_events);
+        }
+
+        Ok(events)
+    }
+
+    /// Adds listings of redriven items to the last state of their stashes, as listed unless the
+    /// stash already lists them. Redriven items are only a part of their stash, so the rest of
+    /// it is left as it was rather than diffed as removed
+    pub async fn track_redriven(
+        &mut self,
+        listings: &[Listing],
+        now: OffsetDateTime,
+    ) -> anyhow::Result<Vec<ListingEvent>> {
+        let mut events = Vec::new();
+        for listing in listings {
+            let (mut state, revision) = self.last_state(&listing.stash_id).await?;
+            if state.items.contains_key(&listing.item_id) {
+                continue;
+            }
+
+            let item = ItemState::from_listing(listing, now);
+            events.push(ListingEvent {
+                kind: ListingEventKind::Listed,
+                stash_id: listing.stash_id.clone(),
+                item_id: listing.item_id.clone(),
+                item: item.clone(),
+                previous: None,
+                created_at: now,
+            });
+
+            state.items.insert(listing.item_id.clone(), item);
+            self.pending
+                .insert(listing.stash_id.clone(), (state, revision));
+        }
+
+        Ok(events)
+    }
+
+    /// The stash's pending state if it was diffed since the last commit, or its stored state,
+    /// along with the revision of the stored state
+    async fn last_state(&self, stash_id: &str) -> anyhow::Result<(StashState, u64)> {
+        if let Some(pending) = self.pending.get(stash_id) {
+            return Ok(pending.clone());
+        }
+
+        let stored = self.store.get(stash_id).await?;
+        Ok((stored.state, stored.revision))
+    }
+
+    /// Stores the states diffed since the last commit, once their events are written. Every
+    /// state is tried, failing if any of them couldn't be stored, in which case the messages they
+    /// came from must be redelivered
+    pub async fn commit(&mut self) -> anyhow::Result<()> {
+        let mut failed = 0;
+        let mut first_error = None;
+        for (stash_id, (state, revision)) in std::mem::take(&mut self.pending) {
+            let stored = match state.items.is_empty() {
+                true => self.store.delete(&stash_id, revision).await,
+                false => self.store.put(&stash_id, &state, revision).await,
+            };
+
+            if let Err(e) = stored {
+                failed += 1;
+                first_error.get_or_insert(format!("stash {stash_id}: {e:#}"));
+            }
+        }
+
+        match first_error {
+            Some(e) => bail!("{failed} stash states weren't stored, first failure was {e}"),
+            None => Ok(()),
+        }
+    }
+
+    /// Drops the states diffed since the last commit, when their events couldn't be written
+    pub fn discard(&mut self) {
+        self.pending.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use time::{macros::datetime, Duration};
+
+    use super::{
+        diff_stash, ListingEventKind, ListingTracker, MemoryStashStateStore, StashState,
+        StashStateStore,
+    };
+    use crate::{listing::Listing, pipeline::ExtractedListings};
+
+    fn listing(item_id: &str, price: f64) -> Listing {
+        let mut listing = Listing {
+            name: "Mageblood".to_owned(),
+            item_id: item_id.to_owned(),
+            stash_id: "stash".to_owned(),
+            league: "Standard".to_owned(),
+            ..Default::default()
+        };
+        listing.price.listed_price = price;
+
+        listing
+    }
+
+    #[test]
+    fn diff_emits_lifecycle_events() {
+        let listed_at = datetime!(2024-04-10 12:00 UTC);
+        let now = listed_at + Duration::hours(2);
+
+        let (previous, _) = diff_stash(
+            "stash",
+            &StashState::default(),
+            &[
+                &listing("kept", 10.0),
+                &listing("repriced", 10.0),
+                &listing("sold", 10.0),
+                &listing("unpriced", 10.0),
+            ],
+            &HashSet::new(),
+            listed_at,
+        );
+
+        let (state, events) = diff_stash(
+            "stash",
+            &previous,
+            &[
+                &listing("kept", 10.0),
+                &listing("repriced", 8.0),
+                &listing("new", 5.0),
+            ],
+            &HashSet::from(["unpriced".to_owned()]),
+            now,
+        );
+
+        let mut kinds = events
+            .iter()
+            .map(|e| (e.item_id.as_str(), e.kind))
+            .collect::<Vec<_>>();
+        kinds.sort_by_key(|(id, _)| *id);
+        assert_eq!(
+            kinds,
+            vec![
+                ("new", ListingEventKind::Listed),
+                ("repriced", ListingEventKind::Repriced),
+                ("sold", ListingEventKind::Removed),
+                ("unpriced", ListingEventKind::Unpriced),
+            ]
+        );
+
+        let repriced = events.iter().find(|e| e.item_id == "repriced").unwrap();
+        assert_eq!(repriced.previous.as_ref().unwrap().listed_price, 10.0);
+        assert_eq!(repriced.item.listed_price, 8.0);
+        assert_eq!(repriced.time_on_market(), 2 * 60 * 60);
+
+        let sold = events.iter().find(|e| e.item_id == "sold").unwrap();
+        assert_eq!(sold.time_on_market(), 2 * 60 * 60);
+
+        assert_eq!(state.items.len(), 3);
+        assert_eq!(state.items["kept"].first_listed_at, listed_at);
+        assert_eq!(state.items["new"].first_listed_at, now);
+    }
+
+    #[tokio::test]
+    async fn emptied_stashes_are_forgotten() {
+        let mut tracker = ListingTracker::new(MemoryStashStateStore::default());
+        let now = datetime!(2024-04-10 12:00 UTC);
+
+        let listed = ExtractedListings {
+            listings: vec![listing("a", 1.0)],
+            stash_ids: vec!["stash".to_owned()],
+            ..Default::default()
+        };
+        let events = tracker.track(&listed, now).await.unwrap();
+        assert_eq!(events[0].kind, ListingEventKind::Listed);
+        tracker.commit().await.unwrap();
+
+        let emptied = ExtractedListings {
+            stash_ids: vec!["stash".to_owned()],
+            ..Default::default()
+        };
+        let events = tracker.track(&emptied, now).await.unwrap();
+        assert_eq!(events[0].kind, ListingEventKind::Removed);
+        tracker.commit().await.unwrap();
+        assert!(tracker
+            .store
+            .get("stash")
+            .await
+            .unwrap()
+            .state
+            .items
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn states_are_stored_once_committed() {
+        let mut tracker = ListingTracker::new(MemoryStashStateStore::default());
+        let now = datetime!(2024-04-10 12:00 UTC);
+        let listed = ExtractedListings {
+            listings: vec![listing("a", 1.0)],
+            stash_ids: vec!["stash".to_owned()],
+            ..Default::default()
+        };
+
+        tracker.track(&listed, now).await.unwrap();
+        assert_eq!(tracker.store.get("stash").await.unwrap().revision, 0);
+        // later changes in the same batch diff against the pending state
+        assert!(tracker.track(&listed, now).await.unwrap().is_empty());
+
+        // events which failed to be written are emitted again for the redelivered message
+        tracker.discard();
+        let events = tracker.track(&listed, now).await.unwrap();
+        assert_eq!(events[0].kind, ListingEventKind::Listed);
+
+        tracker.commit().await.unwrap();
+        assert_eq!(tracker.store.get("stash").await.unwrap().revision, 1);
+        assert!(tracker.track(&listed, now).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn redriven_listings_are_added_to_their_stash() {
+        let mut tracker = ListingTracker::new(MemoryStashStateStore::default());
+        let now = datetime!(2024-04-10 12:00 UTC);
+        let listed = ExtractedListings {
+            listings: vec![listing("a", 1.0)],
+            stash_ids: vec!["stash".to_owned()],
+            ..Default::default()
+        };
+        tracker.track(&listed, now).await.unwrap();
+        tracker.commit().await.unwrap();
+
+        let events = tracker
+            .track_redriven(&[listing("a", 1.0), listing("b", 2.0)], now)
+            .await
+            .unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].item_id, "b");
+        assert_eq!(events[0].kind, ListingEventKind::Listed);
+        tracker.commit().await.unwrap();
+
+        let state = tracker.store.get("stash").await.unwrap().state;
+        assert_eq!(state.items.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn states_written_since_they_were_read_are_not_overwritten() {
+        let mut tracker = ListingTracker::new(MemoryStashStateStore::default());
+        let now = datetime!(2024-04-10 12:00 UTC);
+        let listed = ExtractedListings {
+            listings: vec![listing("a", 1.0)],
+            stash_ids: vec!["stash".to_owned()],
+            ..Default::default()
+        };
+        tracker.track(&listed, now).await.unwrap();
+
+        // another processor stores the stash first
+        let (theirs, _) = diff_stash(
+            "stash",
+            &StashState::default(),
+            &[&listing("a", 1.0)],
+            &HashSet::new(),
+            now,
+        );
+        tracker.store.put("stash", &theirs, 0).await.unwrap();
+
+        assert!(tracker.commit().await.is_err());
+        tracker.discard();
+
+        // the redelivered message is diffed against their state
+        assert!(tracker.track(&listed, now).await.unwrap().is_empty());
+        tracker.commit().await.unwrap();
+        assert_eq!(tracker.store.get("stash").await.unwrap().revision, 2);
+    }
+}
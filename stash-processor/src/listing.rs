@@ -1,21 +1,43 @@
 use core::fmt;
 
 use anyhow::Context;
-use once_cell::sync::Lazy;
-use poe_types::item::Item;
-use regex::Regex;
-use serde::{Deserialize, Serialize};
+use poe_types::{currency::CurrencyCatalogue, item::Item};
+use serde::{de, Deserialize, Deserializer, Serialize};
 use time::OffsetDateTime;
 
-#[derive(Debug, Serialize, Deserialize)]
+use crate::{
+    category::ItemCategory,
+    mods::{item_mods, NumericMod},
+    note::{parse_note, Note, NoteError},
+    variant::item_variant,
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Listing {
+    /// the name of uniques, or the base type of every other category
     pub name: String,
+    pub base_type: String,
+    pub category: ItemCategory,
+    /// what sets the item apart from others with the same name, see [`item_variant`]
+    pub variant: String,
+    /// url of the item's art
+    pub icon: String,
+    /// number of units listed, 1 for items which don't stack
+    pub stack_size: u32,
     pub item_id: String,
+    pub stash_id: String,
+    pub account_name: String,
     pub league: String,
     pub price: ComplexPrice,
     pub implicit_mods: Vec<String>,
     pub explicit_mods: Vec<String>,
+    /// every mod of the item with its rolled values, see [`item_mods`]
+    pub mods: Vec<NumericMod>,
     pub created_at: OffsetDateTime,
+    pub price_source: PriceSource,
+    /// how likely the listing is to be price fixing, see [`crate::outliers::OutlierDetector`]
+    pub outlier_score: f64,
+    pub is_outlier: bool,
 }
 
 impl Listing {
@@ -24,14 +46,75 @@ impl Listing {
 
         Self {
             name: Default::default(),
+            base_type: Default::default(),
+            category: Default::default(),
+            variant: Default::default(),
+            icon: Default::default(),
+            stack_size: 1,
             item_id: Default::default(),
+            stash_id: Default::default(),
+            account_name: Default::default(),
             league: Default::default(),
             price: Default::default(),
             implicit_mods: Default::default(),
             explicit_mods: Default::default(),
+            mods: Default::default(),
             created_at: timestamp,
+            price_source: Default::default(),
+            outlier_score: Default::default(),
+            is_outlier: Default::default(),
         }
     }
+
+    /// Converts an item into a listing, items without a note of their own inherit the price
+    /// note of the stash they are in
+    pub fn from_item(
+        item: Item,
+        category: ItemCategory,
+        stash_note: Option<&str>,
+    ) -> anyhow::Result<Self> {
+        let variant = item_variant(&item, category);
+        let mods = item_mods(&item);
+        let id = item.id.context("items are expected to have an id")?;
+
+        let name = match category {
+            ItemCategory::Unique => item.name,
+            _ => item.base_type.clone(),
+        };
+        if name.is_empty() {
+            anyhow::bail!("items are expected to have a name or base type");
+        }
+
+        let league = item
+            .league
+            .filter(|l| !l.is_empty())
+            .context("items are expected to have a league")?;
+
+        let (price, price_source) = listing_price(item.note.as_deref(), stash_note)?
+            .context("items must have a price note or be in a priced stash")?;
+
+        let timestamp = OffsetDateTime::now_utc();
+        Ok(Self {
+            name,
+            base_type: item.base_type,
+            category,
+            variant,
+            icon: item.icon,
+            stack_size: item.stack_size.unwrap_or(1).max(1) as u32,
+            item_id: id,
+            stash_id: Default::default(),
+            account_name: Default::default(),
+            league,
+            price,
+            implicit_mods: item.implicit_mods.unwrap_or_default(),
+            explicit_mods: item.explicit_mods.unwrap_or_default(),
+            mods,
+            created_at: timestamp,
+            price_source,
+            outlier_score: Default::default(),
+            is_outlier: Default::default(),
+        })
+    }
 }
 
 impl Default for Listing {
@@ -41,240 +124,231 @@ impl Default for Listing {
 }
 
 /// ComplexPrice contains a normalized value of a listing at the current time of the chaos to divine conversion
-#[derive(Debug, Default, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct ComplexPrice {
-    /// TODO(stash-processor): value of item normalized to chaos equivalent
+    /// value of item normalized to chaos equivalent, 0 when no rate was known for the currency
     pub normalized_price: f64,
-    /// raw listed price
+    /// raw listed price of a single unit, notes on stackable items price each unit in the stack
     pub listed_price: f64,
     /// raw listed currency
     pub listed_currency: ListingCurrency,
+    /// amount of currency asked for `listed_units` of the item, a note of `~price 100/20 chaos`
+    /// asks 100 chaos for every 20 units
+    pub listed_amount: f64,
+    pub listed_units: f64,
+    /// id of the currency rate snapshot used to normalize the price
+    pub rate_snapshot_id: u64,
 }
 
-#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
-pub enum ListingCurrency {
-    ChaosOrb,
-    DivineOrb,
-    ExaltedOrb,
-    AwakenedSextant,
-    MirrorOfKalandra,
-    AlchemyOrb,
-    FusingOrb,
-    AnnulmentOrb,
-    ChanceOrb,
-    AlterationOrb,
-    ScouringOrb,
-    RegalOrb,
+/// PriceSource is where the price of a listing was read from
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PriceSource {
+    /// the item's own note
     #[default]
-    Unknown,
+    Item,
+    /// the name of the stash tab the item is in
+    Stash,
 }
 
-impl From<&str> for ListingCurrency {
-    fn from(value: &str) -> Self {
-        match value {
-            "exa" => ListingCurrency::ExaltedOrb,
-            "divine" => ListingCurrency::DivineOrb,
-            "chaos" => ListingCurrency::ChaosOrb,
-            "awakened-sextant" => ListingCurrency::AwakenedSextant,
-            "mirror" => ListingCurrency::MirrorOfKalandra,
-            "alch" => ListingCurrency::AlchemyOrb,
-            "fusing" => ListingCurrency::FusingOrb,
-            "annul" => ListingCurrency::AnnulmentOrb,
-            "chance" => ListingCurrency::ChanceOrb,
-            "alt" => ListingCurrency::AlterationOrb,
-            "scour" => ListingCurrency::ScouringOrb,
-            "regal" => ListingCurrency::RegalOrb,
-            _ => ListingCurrency::Unknown,
-        }
-    }
-}
-
-impl fmt::Display for ListingCurrency {
+impl fmt::Display for PriceSource {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            ListingCurrency::ChaosOrb => write!(f, "chaos"),
-            ListingCurrency::DivineOrb => write!(f, "divine"),
-            ListingCurrency::ExaltedOrb => write!(f, "exa"),
-            ListingCurrency::AwakenedSextant => write!(f, "awakened-sextant"),
-            ListingCurrency::MirrorOfKalandra => write!(f, "mirror"),
-            ListingCurrency::AlchemyOrb => write!(f, "alch"),
-            ListingCurrency::FusingOrb => write!(f, "fusing"),
-            ListingCurrency::AnnulmentOrb => write!(f, "annul"),
-            ListingCurrency::ChanceOrb => write!(f, "chance"),
-            ListingCurrency::AlterationOrb => write!(f, "alt"),
-            ListingCurrency::ScouringOrb => write!(f, "scour"),
-            ListingCurrency::RegalOrb => write!(f, "regal"),
-            ListingCurrency::Unknown => write!(f, "unknown"),
+            PriceSource::Item => write!(f, "item"),
+            PriceSource::Stash => write!(f, "stash"),
         }
     }
 }
 
-impl TryFrom<Item> for Listing {
-    type Error = anyhow::Error;
+/// ListingCurrency is the currency a listing is priced in, stored as its canonical tag in the
+/// [`CurrencyCatalogue`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
+#[serde(transparent)]
+pub struct ListingCurrency(&'static str);
+
+impl ListingCurrency {
+    pub const CHAOS: Self = Self("chaos");
+    pub const DIVINE: Self = Self("divine");
+
+    /// Looks up a currency by its tag or one of its aliases in the catalogue
+    pub fn from_tag(tag: &str) -> Option<Self> {
+        CurrencyCatalogue::embedded()
+            .get(tag)
+            .map(|c| Self(c.tag.as_str()))
+    }
 
-    fn try_from(item: Item) -> Result<Self, Self::Error> {
-        let id = item.id.context("items are expected to have an id")?;
-        let note = item.note.context("items must have a note to be priced")?;
-        let price = note_to_complex_price(&note)?;
+    /// Maps the base type of a currency item to the currency it can be listed in
+    pub fn from_base_type(base_type: &str) -> Option<Self> {
+        CurrencyCatalogue::embedded()
+            .by_name(base_type)
+            .map(|c| Self(c.tag.as_str()))
+    }
 
-        let timestamp = OffsetDateTime::now_utc();
-        Ok(Self {
-            name: item.name,
-            item_id: id,
-            league: item.league.unwrap_or("Necropolis".to_owned()),
-            price: price.unwrap_or_default(),
-            implicit_mods: item.implicit_mods.unwrap_or_default(),
-            explicit_mods: item.explicit_mods.unwrap_or_default(),
-            created_at: timestamp,
-        })
+    pub fn tag(&self) -> &'static str {
+        self.0
     }
 }
 
-pub fn note_to_complex_price(note: &str) -> anyhow::Result<Option<ComplexPrice>> {
-    static PRICE_REGEXP: Lazy<Regex> = Lazy::new(|| {
-        Regex::new(r"~(price|b/o) ([\d\.]+(?:/[\d\.]+)?) ([\w-]+)").expect("price regex must parse")
-    });
-
-    match PRICE_REGEXP.captures(note) {
-        Some(caps) => {
-            if caps.len() == 4 {
-                let mut raw_value = 0 as f64;
-
-                if let Some((num, denom)) = caps.get(2).unwrap().as_str().split_once('/') {
-                    let raw_num = num
-                        .parse::<f64>()
-                        .context("failed parsing numerator: {num} to f64")?;
-                    let raw_denom = denom
-                        .parse::<f64>()
-                        .context("failed parsing denominator: {denom} to f64")?;
-
-                    if raw_denom > 0 as f64 {
-                        raw_value = raw_num / raw_denom;
-                    }
-                } else {
-                    let raw_price_string = caps.get(2).unwrap().as_str();
-                    raw_value = raw_price_string
-                        .parse::<f64>()
-                        .context("failed parsing price: {raw_price_string} to f64")?;
-                }
-
-                let currency_string = caps.get(3).unwrap().as_str();
-                let currency = ListingCurrency::from(currency_string);
-
-                return Ok(Some(ComplexPrice {
-                    normalized_price: 0 as f64,
-                    listed_price: raw_value,
-                    listed_currency: currency,
-                }));
-            }
-
-            Ok(None)
-        }
-        None => Ok(None),
+impl Default for ListingCurrency {
+    fn default() -> Self {
+        Self::CHAOS
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use crate::listing::ListingCurrency;
-
-    use super::note_to_complex_price;
+impl<'de> Deserialize<'de> for ListingCurrency {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let tag = String::deserialize(deserializer)?;
 
-    #[test]
-    fn simple_chaos_note() {
-        let note = "~price 70 chaos";
-        let price = note_to_complex_price(note).expect("should parse");
+        Self::from_tag(&tag)
+            .ok_or_else(|| de::Error::custom(format!("unknown currency tag: {tag}")))
+    }
+}
 
-        let p = price.expect("should unwrap");
-        assert_eq!(70 as f64, p.listed_price);
-        assert_eq!(ListingCurrency::ChaosOrb, p.listed_currency);
+impl fmt::Display for ListingCurrency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
     }
+}
 
-    #[test]
-    fn simple_exalt_note() {
-        let note = "~price 20 exa";
-        let price = note_to_complex_price(note).expect("should parse");
+impl TryFrom<Item> for Listing {
+    type Error = anyhow::Error;
+
+    fn try_from(item: Item) -> Result<Self, Self::Error> {
+        let category = ItemCategory::classify(&item).context("items must be a tradable class")?;
 
-        let p = price.expect("should unwrap");
-        assert_eq!(20 as f64, p.listed_price);
-        assert_eq!(ListingCurrency::ExaltedOrb, p.listed_currency);
+        Self::from_item(item, category, None)
     }
+}
 
-    #[test]
-    fn simple_divine_note() {
-        let note = "~b/o 10 divine";
-        let price = note_to_complex_price(note).expect("should parse");
+/// The price of an item and where it was read from, items without a price note of their own
+/// inherit the price note of the stash they are in unless their note is `~skip` or an offer.
+/// None when neither prices the item
+pub fn listing_price(
+    item_note: Option<&str>,
+    stash_note: Option<&str>,
+) -> Result<Option<(ComplexPrice, PriceSource)>, NoteError> {
+    let stash_price = || -> Result<Option<(ComplexPrice, PriceSource)>, NoteError> {
+        match stash_note.map(parse_note).transpose()? {
+            Some(Note::Price(price)) => Ok(Some((price.into(), PriceSource::Stash))),
+            _ => Ok(None),
+        }
+    };
 
-        let p = price.expect("should unwrap");
-        assert_eq!(10 as f64, p.listed_price);
-        assert_eq!(ListingCurrency::DivineOrb, p.listed_currency);
+    match item_note.map(parse_note).transpose()? {
+        Some(Note::Price(price)) => Ok(Some((price.into(), PriceSource::Item))),
+        Some(Note::Skip | Note::Offer) => Ok(None),
+        Some(Note::Text) | None => stash_price(),
     }
+}
 
-    #[test]
-    fn fractional_chaos_note() {
-        let note = "~price 100/10 chaos";
-        let price = note_to_complex_price(note).expect("should parse");
-
-        let p = price.expect("should unwrap");
-        assert_eq!(10 as f64, p.listed_price);
-        assert_eq!(ListingCurrency::ChaosOrb, p.listed_currency);
+#[cfg(test)]
+mod tests {
+    use poe_types::item::Item;
+
+    use crate::{
+        category::ItemCategory,
+        listing::{listing_price, Listing, ListingCurrency, PriceSource},
+    };
+
+    fn item(note: Option<&str>) -> Item {
+        Item {
+            id: Some("item".to_owned()),
+            name: "Mageblood".to_owned(),
+            league: Some("Standard".to_owned()),
+            note: note.map(|n| n.to_owned()),
+            ..Default::default()
+        }
     }
 
     #[test]
-    fn fractional_divine_note() {
-        let note = "~price 5/20 divine";
-        let price = note_to_complex_price(note).expect("should parse");
-
-        let p = price.expect("should unwrap");
-        assert_eq!(0.25 as f64, p.listed_price);
-        assert_eq!(ListingCurrency::DivineOrb, p.listed_currency);
+    fn item_note_takes_precedence_over_stash() {
+        let listing = Listing::from_item(
+            item(Some("~price 5 chaos")),
+            ItemCategory::Unique,
+            Some("~price 1 divine"),
+        )
+        .expect("should convert");
+
+        assert_eq!(PriceSource::Item, listing.price_source);
+        assert_eq!(5.0, listing.price.listed_price);
+        assert_eq!(ListingCurrency::CHAOS, listing.price.listed_currency);
     }
 
     #[test]
-    fn float_divine_note() {
-        let note = "~price 0.8 divine";
-        let price = note_to_complex_price(note).expect("should parse");
+    fn unnoted_item_inherits_stash_price() {
+        let listing = Listing::from_item(item(None), ItemCategory::Unique, Some("~b/o 1 divine"))
+            .expect("should convert");
 
-        let p = price.expect("should unwrap");
-        assert_eq!(0.8 as f64, p.listed_price);
-        assert_eq!(ListingCurrency::DivineOrb, p.listed_currency);
+        assert_eq!(PriceSource::Stash, listing.price_source);
+        assert_eq!(1.0, listing.price.listed_price);
+        assert_eq!(ListingCurrency::DIVINE, listing.price.listed_currency);
     }
 
     #[test]
-    fn alch_currency_note() {
-        let note = "~price 3 alch";
-        let price = note_to_complex_price(note).expect("should parse");
-
-        let p = price.expect("should unwrap");
-        assert_eq!(3 as f64, p.listed_price);
-        assert_eq!(ListingCurrency::AlchemyOrb, p.listed_currency);
+    fn text_notes_inherit_stash_price() {
+        let listing = Listing::from_item(
+            item(Some("gl hf")),
+            ItemCategory::Unique,
+            Some("~price 1 divine"),
+        )
+        .expect("should convert");
+        assert_eq!(PriceSource::Stash, listing.price_source);
+        assert_eq!(ListingCurrency::DIVINE, listing.price.listed_currency);
+
+        // skipped items and offers aren't priced by their stash
+        for note in ["~skip", "~offer"] {
+            let price = listing_price(Some(note), Some("~price 1 divine")).unwrap();
+            assert!(price.is_none());
+        }
+        assert!(listing_price(Some("~price 1 chaos orbs"), None).is_err());
     }
 
     #[test]
-    fn mirror_currency_note() {
-        let note = "~price 2 mirror";
-        let price = note_to_complex_price(note).expect("should parse");
-
-        let p = price.expect("should unwrap");
-        assert_eq!(2 as f64, p.listed_price);
-        assert_eq!(ListingCurrency::MirrorOfKalandra, p.listed_currency);
+    fn unpriced_item_and_stash() {
+        assert!(Listing::from_item(item(None), ItemCategory::Unique, None).is_err());
+        assert!(Listing::from_item(item(None), ItemCategory::Unique, Some("dump tab")).is_err());
+        assert!(Listing::from_item(item(Some("gl hf")), ItemCategory::Unique, None).is_err());
     }
 
     #[test]
-    fn unknown_currency_note() {
-        let note = "~price 10 offer-gift";
-        let price = note_to_complex_price(note).expect("should parse");
-
-        let p = price.expect("should unwrap");
-        assert_eq!(10 as f64, p.listed_price);
-        assert_eq!(ListingCurrency::Unknown, p.listed_currency);
+    fn currencies_are_stored_by_canonical_tag() {
+        assert_eq!(
+            ListingCurrency::from_tag("chaos"),
+            Some(ListingCurrency::CHAOS)
+        );
+        assert_eq!(
+            ListingCurrency::from_tag("divine"),
+            Some(ListingCurrency::DIVINE)
+        );
+
+        let exalted = ListingCurrency::from_tag("exa").expect("exa is an alias");
+        assert_eq!(exalted.to_string(), "exalted");
+        assert_eq!(
+            ListingCurrency::from_base_type("Exalted Orb"),
+            Some(exalted)
+        );
+        assert_eq!(serde_json::to_string(&exalted).unwrap(), r#""exalted""#);
+
+        assert!(ListingCurrency::from_tag("unknown").is_none());
+        assert!(serde_json::from_str::<ListingCurrency>(r#""unknown""#).is_err());
     }
 
     #[test]
-    fn invalid_note() {
-        let note = "random note on item";
-        let price = note_to_complex_price(note).expect("should parse");
-
-        assert!(price.is_none());
+    fn non_uniques_are_named_by_base_type() {
+        let currency = Item {
+            id: Some("item".to_owned()),
+            type_line: "Divine Orb".to_owned(),
+            base_type: "Divine Orb".to_owned(),
+            stack_size: Some(12),
+            league: Some("Standard".to_owned()),
+            note: Some("~price 180 chaos".to_owned()),
+            ..Default::default()
+        };
+
+        let listing =
+            Listing::from_item(currency, ItemCategory::Currency, None).expect("should convert");
+
+        assert_eq!("Divine Orb", listing.name);
+        assert_eq!(ItemCategory::Currency, listing.category);
+        assert_eq!(12, listing.stack_size);
     }
 }
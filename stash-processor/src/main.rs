@@ -1,29 +1,142 @@
-pub mod db;
-pub mod listing;
-pub mod search;
-
-use std::env;
+use std::{
+    env,
+    path::PathBuf,
+    sync::{atomic::Ordering, Arc},
+    time::Instant,
+};
 
 use anyhow::Context;
 use async_nats::jetstream::{self, consumer::PullConsumer};
-use poe_types::{item::FrameType, stash::PublicStashChange};
+use clap::{Parser, Subcommand, ValueEnum};
+use ledger_service::{shutdown::Shutdown, telemetry as service_telemetry};
+use stash_processor::{
+    accounts::ChurnPolicy,
+    batch,
+    buffer::{Flush, ListingBuffer},
+    db,
+    deadletter::{self, RetryPolicy, FAILED_ITEMS_SUBJECT, FAILED_STASHES_SUBJECT},
+    dedup::DedupCache,
+    leagues::LeagueRegistry,
+    lifecycle::{ListingTracker, NatsStashStateStore},
+    migrations,
+    outliers::OutlierDetector,
+    processor::StashProcessor,
+    rates::CurrencyRates,
+    replay::{self, Replayer},
+    search::{self, ItemDocument},
+    sink::{ListingSink, Sinks},
+    telemetry::{Metrics, TelemetryState},
+};
+use time::OffsetDateTime;
 use tokio_stream::StreamExt;
 use tracing_subscriber::{layer::SubscriberExt, EnvFilter, Registry};
 
-use crate::listing::Listing;
+#[derive(Parser)]
+#[command(about = "Processes public stash changes from NATS into listings")]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Process stash changes from the PublicStashStream, the default when no command is given
+    Run,
+    /// Re-inject dead-lettered stashes and items, once a fix for what made them fail is deployed
+    Redrive {
+        /// Only redrive stashes or items
+        #[arg(long, value_enum)]
+        only: Option<DeadLetters>,
+        /// Redrive at most this many messages
+        #[arg(long)]
+        limit: Option<usize>,
+        /// Count the messages which would be redriven without redriving them
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Apply pending Clickhouse schema migrations from `sql`, which `run` requires
+    Migrate {
+        /// List the pending migrations without applying them
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Rebuild the search index from every listing stored in Clickhouse
+    Reindex {
+        /// Count the documents which would be indexed without indexing them
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Run stash pages saved as json, or river archive segments, through the pipeline without NATS
+    Replay {
+        /// Files or directories of `.json` pages and `.zst` archive segments
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+        /// Only summarize the listings which would be written, instead of writing them
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum DeadLetters {
+    Stashes,
+    Items,
+}
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
     setup_logger();
 
+    match cli.command.unwrap_or(Command::Run) {
+        Command::Run => run().await,
+        Command::Redrive {
+            only,
+            limit,
+            dry_run,
+        } => redrive(only, limit, dry_run).await,
+        Command::Replay { paths, dry_run } => replay(&paths, dry_run).await,
+        Command::Migrate { dry_run } => migrate(dry_run).await,
+        Command::Reindex { dry_run } => reindex(dry_run).await,
+    }
+}
+
+async fn connect_nats() -> anyhow::Result<async_nats::Client> {
     let nats_url = env::var("NATS_URL").unwrap_or("nats://localhost:4222".to_string());
-    let nats = async_nats::connect(&nats_url)
+
+    async_nats::connect(&nats_url)
         .await
-        .context(format!("failed to connect to NATS_URL: {nats_url}"))?;
-    let jetstream = jetstream::new(nats);
+        .context(format!("failed to connect to NATS_URL: {nats_url}"))
+}
 
-    let ch_db = db::ClickhouseDatabase::new().await;
-    let meili_handler = search::MeilisearchHandler::new().await;
+/// The Clickhouse database currency rates, leagues and churning accounts are kept in, reusing
+/// the sinks' connection when listings are written to it too
+async fn clickhouse(sinks: &Sinks) -> db::ClickhouseDatabase {
+    match sinks.clickhouse() {
+        Some(db) => db.clone(),
+        None => db::ClickhouseDatabase::new().await,
+    }
+}
+
+async fn run() -> anyhow::Result<()> {
+    let nats = connect_nats().await?;
+    let jetstream = jetstream::new(nats.clone());
+
+    let sinks = Sinks::from_env().await?;
+    let ch_db = clickhouse(&sinks).await;
+
+    let metrics = Arc::new(Metrics::default());
+    let telemetry_state = TelemetryState {
+        nats,
+        db: ch_db.clone(),
+        search: sinks.search().cloned(),
+        metrics: metrics.clone(),
+    };
+    tokio::spawn(async move {
+        if let Err(e) = service_telemetry::serve(Arc::new(telemetry_state)).await {
+            tracing::error!("telemetry server stopped with error: {e}");
+        }
+    });
 
     let stream_name = "PublicStashStream";
     let consumer_name = "StashProcessor";
@@ -32,83 +145,169 @@ async fn main() -> anyhow::Result<()> {
         .await
         .unwrap();
 
+    service_telemetry::spawn_monitor(consumer.clone(), metrics.clone());
+
+    let mut rates = CurrencyRates::from_env();
+    match ch_db.latest_rate_snapshot().await {
+        Ok(Some(snapshot)) => rates.restore(snapshot),
+        Ok(None) => tracing::info!("no currency rate snapshot stored yet"),
+        Err(e) => tracing::error!("failed loading the latest currency rate snapshot: {e}"),
+    }
+
+    let mut leagues = LeagueRegistry::default();
+    match ch_db.leagues().await {
+        Ok(stored) => leagues.restore(stored),
+        Err(e) => tracing::error!("failed loading the league registry: {e}"),
+    }
+
+    let mut processor = StashProcessor::new(
+        rates,
+        OutlierDetector::from_env(),
+        ListingTracker::new(NatsStashStateStore::new(&jetstream).await?),
+        DedupCache::from_env(),
+    );
+    let mut churn = ChurnPolicy::from_env();
+    let retry = RetryPolicy::from_env();
+
+    let mut buffer = ListingBuffer::from_env();
+    let mut flush_interval = tokio::time::interval(buffer.max_age());
+
     let messages = consumer.messages().await?;
+    let shutdown = Shutdown::from_env();
+    let signalled = shutdown.signalled();
 
     tokio::pin!(messages);
+    tokio::pin!(signalled);
+
+    // messages are only pulled between batches, so a message being processed when the process is
+    // asked to stop is finished and flushed along with the rest of the buffer
+    loop {
+        let msg = tokio::select! {
+            _ = &mut signalled => break,
+            msg = messages.next() => match msg {
+                Some(msg) => msg,
+                None => break,
+            },
+            _ = flush_interval.tick() => {
+                if buffer.should_flush(Instant::now()) {
+                    flush(
+                        buffer.take(),
+                        &sinks,
+                        &mut processor,
+                        &metrics,
+                        &jetstream,
+                        &retry,
+                    )
+                    .await;
+                }
+
+                continue;
+            }
+        };
 
-    while let Some(msg) = messages.next().await {
         match msg {
             Ok(m) => {
-                let stash = match serde_json::from_slice::<PublicStashChange>(&m.payload) {
+                let stashes = match batch::decode_stash_message(m.headers.as_ref(), &m.payload) {
                     Ok(s) => s,
                     Err(e) => {
-                        tracing::error!("failed parsing a stash change: {e}");
-                        if let Err(e) = jetstream
-                            .publish("river.failed_stashes", m.payload.to_owned())
-                            .await
-                        {
-                            tracing::error!("couldn't push unprocessable stash change to failed stashes stream: {e}");
-                        }
-
-                        if let Err(e) = m.ack_with(jetstream::AckKind::Term).await {
-                            tracing::error!("failed to ack unprocessable stash: {e}");
-                        }
+                        tracing::error!("failed decoding a stash message: {e:#}");
+                        metrics.failed_stashes_total.fetch_add(1, Ordering::Relaxed);
+                        dead_letter(&m, &format!("{e:#}"), &jetstream, &retry).await;
 
                         continue;
                     }
                 };
 
-                let mut listings_batch = Vec::new();
-                for raw_item in stash.items {
-                    let is_priced = raw_item.note.is_some();
-                    let is_unique = raw_item.frame_type.as_ref().is_some_and(|f| {
-                        matches!(
-                            f,
-                            FrameType::Unique | FrameType::Foil | FrameType::SupporterFoil
-                        )
-                    });
-                    let name_exists = !raw_item.name.is_empty();
-                    let has_item_id = raw_item.id.is_some();
-
-                    if is_priced && is_unique && name_exists && has_item_id {
-                        match Listing::try_from(raw_item.clone()) {
-                            Ok(listing) => {
-                                listings_batch.push(listing);
-                            }
-                            Err(e) => {
-                                tracing::error!("failed converting item to a listing: {e}");
-
-                                if let Ok(item_json) = serde_json::to_string(&raw_item) {
-                                    let _ = jetstream
-                                        .publish("river.failed_items", item_json.into())
-                                        .await;
-                                }
-                            }
-                        };
+                if churn.due(Instant::now()) {
+                    match ch_db.churning_accounts(&churn).await {
+                        Ok(accounts) => processor.outliers_mut().set_churning_accounts(accounts),
+                        Err(e) => tracing::error!("failed loading churning accounts: {e}"),
                     }
                 }
 
-                match ch_db.create_batch(&listings_batch).await {
-                    Ok(_) => {
-                        if let Err(e) = meili_handler
-                            .add_document_batch("uniques", &listings_batch)
-                            .await
-                        {
-                            tracing::error!("failed to index a batch of uniques: {e}");
+                let now = OffsetDateTime::now_utc();
+                let stash_count = stashes.len();
+                let mut page = match processor.process(stashes, now).await {
+                    Ok(page) => page,
+                    Err(e) => {
+                        // the buffered messages' stash states were dropped along with this
+                        // one's, so they are retried too rather than written without them
+                        tracing::error!("{e:#}");
+                        let error = format!("{e:#}");
+                        let batch = buffer.take();
+                        processor.discard(&batch.listings);
+                        for buffered in batch.messages {
+                            retry_or_dead_letter(
+                                &buffered.message,
+                                &error,
+                                &metrics,
+                                &jetstream,
+                                &retry,
+                            )
+                            .await;
                         }
+                        retry_or_dead_letter(&m, &error, &metrics, &jetstream, &retry).await;
 
-                        if let Err(e) = m.ack().await {
-                            tracing::error!("couldn't ack message: {e}");
-                        }
+                        continue;
                     }
-                    Err(e) => {
-                        tracing::error!("failed to add listings to the DB: {e}");
+                };
+
+                if let Some(snapshot) = &page.rate_snapshot {
+                    if let Err(e) = ch_db.insert_rate_snapshot(snapshot).await {
+                        tracing::error!("failed to store currency rate snapshot: {e}");
+                    }
+                }
 
-                        if let Err(e) = m.ack_with(jetstream::AckKind::Term).await {
-                            tracing::error!("failed to ack unprocessable stash: {e}");
+                let seen = leagues.observe(
+                    page.extracted.listings.iter().map(|l| l.league.as_str()),
+                    now,
+                );
+                if !seen.is_empty() {
+                    if let Err(e) = ch_db.upsert_leagues(&seen).await {
+                        tracing::error!("failed to store leagues: {e}");
+                    }
+                }
+
+                metrics
+                    .outlier_listings_total
+                    .fetch_add(page.outliers as u64, Ordering::Relaxed);
+                metrics
+                    .duplicate_listings_total
+                    .fetch_add(page.duplicates as u64, Ordering::Relaxed);
+
+                for failed in page.extracted.failed_items.drain(..) {
+                    metrics.failed_items_total.fetch_add(1, Ordering::Relaxed);
+
+                    if let Ok(item_json) = serde_json::to_vec(&failed.item) {
+                        let headers = deadletter::failed_item_headers(&failed, now);
+                        if let Err(e) = deadletter::publish_durable(
+                            &jetstream,
+                            FAILED_ITEMS_SUBJECT,
+                            headers,
+                            item_json,
+                        )
+                        .await
+                        {
+                            tracing::error!(
+                                "couldn't push failed item to failed items stream: {e}"
+                            );
                         }
                     }
-                };
+                }
+
+                let bytes = m.payload.len();
+                buffer.push(m, bytes, stash_count, page.extracted.listings, page.events);
+                if buffer.should_flush(Instant::now()) {
+                    flush(
+                        buffer.take(),
+                        &sinks,
+                        &mut processor,
+                        &metrics,
+                        &jetstream,
+                        &retry,
+                    )
+                    .await;
+                }
             }
             Err(e) => {
                 tracing::error!("failed to read item: {e}");
@@ -116,6 +315,246 @@ async fn main() -> anyhow::Result<()> {
         }
     }
 
+    if !buffer.is_empty() {
+        let batch = buffer.take();
+        tracing::info!(
+            "flushing {} listings from {} messages before stopping",
+            batch.listings.len(),
+            batch.messages.len()
+        );
+        shutdown
+            .drain(flush(
+                batch,
+                &sinks,
+                &mut processor,
+                &metrics,
+                &jetstream,
+                &retry,
+            ))
+            .await?;
+    }
+    tracing::info!("stopped processing stash changes");
+
+    Ok(())
+}
+
+/// Writes a flushed buffer, acking its messages only once their listings and listing events are
+/// stored and the stash states the events were diffed into are saved. When any of it fails the
+/// messages are nak'd with a backoff, and dead-lettered once out of retries.
+///
+/// The batch's stash states are dropped on failure, so a retried message is diffed against the
+/// same states and its listing events are emitted again. Events are written before the states
+/// are saved, so when saving fails some events may be written twice, but none are lost.
+async fn flush(
+    batch: Flush<jetstream::Message>,
+    sinks: &Sinks,
+    processor: &mut StashProcessor<NatsStashStateStore>,
+    metrics: &Metrics,
+    jetstream: &jetstream::Context,
+    retry: &RetryPolicy,
+) {
+    match processor.write(sinks, &batch.listings, &batch.events).await {
+        Ok(_) => {
+            tracing::debug!(
+                "flushed {} listings from {} messages",
+                batch.listings.len(),
+                batch.messages.len()
+            );
+            metrics
+                .listing_events_total
+                .fetch_add(batch.events.len() as u64, Ordering::Relaxed);
+
+            for m in batch.messages {
+                metrics.record_message(m.bytes, m.stashes, m.listings);
+
+                if let Err(e) = m.message.ack().await {
+                    tracing::error!("couldn't ack message: {e}");
+                }
+            }
+        }
+        Err(e) => {
+            tracing::error!("{e:#}");
+            metrics.db_errors_total.fetch_add(1, Ordering::Relaxed);
+
+            let error = format!("{e:#}");
+            for m in batch.messages {
+                retry_or_dead_letter(&m.message, &error, metrics, jetstream, retry).await;
+            }
+        }
+    };
+}
+
+/// Naks a message which failed to be processed or written with a backoff, dead-lettering it
+/// instead once it is out of retries
+async fn retry_or_dead_letter(
+    m: &jetstream::Message,
+    error: &str,
+    metrics: &Metrics,
+    jetstream: &jetstream::Context,
+    retry: &RetryPolicy,
+) {
+    let deliveries = deliveries(m);
+    if retry.exhausted(deliveries) {
+        dead_letter(m, error, jetstream, retry).await;
+        return;
+    }
+
+    metrics
+        .retried_messages_total
+        .fetch_add(1, Ordering::Relaxed);
+    let delay = retry.delay(deliveries);
+    if let Err(e) = m.ack_with(jetstream::AckKind::Nak(Some(delay))).await {
+        tracing::error!("failed to nak message: {e}");
+    }
+}
+
+/// Moves a message which can't be processed to the failed stashes stream, it is only nak'd when
+/// that fails so it isn't lost
+async fn dead_letter(
+    m: &jetstream::Message,
+    error: &str,
+    jetstream: &jetstream::Context,
+    retry: &RetryPolicy,
+) {
+    let deliveries = deliveries(m);
+    let headers = deadletter::failure_headers(
+        m.headers.as_ref(),
+        error,
+        deliveries,
+        OffsetDateTime::now_utc(),
+    );
+
+    let ack = match deadletter::publish_durable(
+        jetstream,
+        FAILED_STASHES_SUBJECT,
+        headers,
+        m.payload.to_vec(),
+    )
+    .await
+    {
+        Ok(_) => jetstream::AckKind::Term,
+        Err(e) => {
+            tracing::error!(
+                "couldn't push unprocessable stash change to failed stashes stream: {e}"
+            );
+            jetstream::AckKind::Nak(Some(retry.delay(deliveries)))
+        }
+    };
+
+    if let Err(e) = m.ack_with(ack).await {
+        tracing::error!("failed to ack unprocessable stash: {e}");
+    }
+}
+
+/// Number of times a message has been delivered, including this delivery
+fn deliveries(m: &jetstream::Message) -> u64 {
+    m.info().map(|i| i.delivered.max(1) as u64).unwrap_or(1)
+}
+
+async fn redrive(
+    only: Option<DeadLetters>,
+    limit: Option<usize>,
+    dry_run: bool,
+) -> anyhow::Result<()> {
+    let jetstream = jetstream::new(connect_nats().await?);
+    let sinks = Sinks::from_env().await?;
+    let ch_db = clickhouse(&sinks).await;
+
+    let mut rates = CurrencyRates::from_env();
+    if let Some(snapshot) = ch_db.latest_rate_snapshot().await? {
+        rates.restore(snapshot);
+    }
+
+    let mut outliers = OutlierDetector::from_env();
+    outliers.set_churning_accounts(ch_db.churning_accounts(&ChurnPolicy::from_env()).await?);
+
+    let mut processor = StashProcessor::new(
+        rates,
+        outliers,
+        ListingTracker::new(NatsStashStateStore::new(&jetstream).await?),
+        DedupCache::from_env(),
+    );
+
+    let subject = only.map(|o| match o {
+        DeadLetters::Stashes => FAILED_STASHES_SUBJECT,
+        DeadLetters::Items => FAILED_ITEMS_SUBJECT,
+    });
+    let summary =
+        deadletter::redrive(&jetstream, &mut processor, &sinks, subject, limit, dry_run).await?;
+
+    tracing::info!(
+        "{} {} stashes and {} items into {} listings, {} items still failed",
+        if dry_run { "would redrive" } else { "redrove" },
+        summary.stashes,
+        summary.items,
+        summary.listings,
+        summary.failed
+    );
+
+    Ok(())
+}
+
+async fn replay(paths: &[PathBuf], dry_run: bool) -> anyhow::Result<()> {
+    let sinks = match dry_run {
+        true => None,
+        false => Some(Sinks::from_env().await?),
+    };
+
+    let mut replayer = Replayer::from_env();
+    for file in replay::replay_files(paths)? {
+        tracing::info!("replaying {}", file.display());
+        replayer.summary.files += 1;
+
+        for page in replay::read_pages(&file)? {
+            replayer
+                .replay(page, sinks.as_ref().map(|s| s as &dyn ListingSink))
+                .await?;
+        }
+    }
+
+    print!("{}", replayer.summary);
+
+    Ok(())
+}
+
+async fn migrate(dry_run: bool) -> anyhow::Result<()> {
+    let client = db::client_from_env();
+
+    let migrations = migrations::migrate(&client, dry_run).await?;
+    for m in &migrations {
+        tracing::info!("{} {}", if dry_run { "pending" } else { "applied" }, m.name);
+    }
+    if !dry_run {
+        tracing::info!(
+            "Clickhouse schema is at version {}",
+            migrations::latest_version()
+        );
+    }
+
+    Ok(())
+}
+
+async fn reindex(dry_run: bool) -> anyhow::Result<()> {
+    let ch_db = db::ClickhouseDatabase::new().await;
+
+    let docs = ch_db
+        .item_summaries()
+        .await?
+        .into_iter()
+        .map(ItemDocument::from)
+        .collect::<Vec<_>>();
+    if dry_run {
+        tracing::info!("would index {} items", docs.len());
+        return Ok(());
+    }
+
+    let count = docs.len();
+    search::MeilisearchHandler::new()
+        .await
+        .reindex(docs)
+        .await?;
+    tracing::info!("indexed {count} items");
+
     Ok(())
 }
 
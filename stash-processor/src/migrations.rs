@@ -0,0 +1,177 @@
+use anyhow::Context;
+use ledger_service::schema::current_version;
+
+/// Migration is a single statement changing the ledger schema, applied in version order
+pub struct Migration {
+    pub version: u32,
+    /// file name in `sql`, prefixed with the version
+    pub name: &'static str,
+    pub sql: &'static str,
+    /// query returning a non-zero UInt8 when the migration's change is already in place, for
+    /// statements which aren't safe to run again if the version wasn't recorded after they ran
+    pub applied_if: Option<&'static str>,
+}
+
+macro_rules! migration {
+    ($version:literal, $name:literal) => {
+        Migration {
+            version: $version,
+            name: $name,
+            sql: include_str!(concat!("../sql/", $name, ".sql")),
+            applied_if: None,
+        }
+    };
+    ($version:literal, $name:literal, applied_if = $query:expr) => {
+        Migration {
+            applied_if: Some($query),
+            ..migration!($version, $name)
+        }
+    };
+}
+
+/// Whether `listings` has already been exchanged for the ReplacingMergeTree table, exchanging
+/// again would swap the old table back
+const LISTINGS_EXCHANGED: &str = "SELECT count() > 0 FROM system.tables
+    WHERE database = 'ledger' AND name = 'listings' AND engine = 'ReplacingMergeTree'";
+
+/// Whether `listings_replacing` is anything but the old MergeTree listings table, in which case
+/// it holds the only copy of the listings and mustn't be dropped
+const MERGE_TREE_LISTINGS_DROPPED: &str = "SELECT count() = 0 FROM system.tables
+    WHERE database = 'ledger' AND name = 'listings_replacing' AND engine = 'MergeTree'";
+
+/// Every migration, embedded from `stash-processor/sql`. Add new ones to the end with the next
+/// version, migrations which have been applied somewhere must never change. The first is the
+/// listings table as it was created before there were migrations, so databases from then are
+/// brought up to date by the rest
+pub const MIGRATIONS: &[Migration] = &[
+    migration!(1, "0001_create_listings_table"),
+    migration!(2, "0002_add_listing_columns"),
+    migration!(3, "0003_create_replacing_listings_table"),
+    migration!(4, "0004_copy_listings_to_replacing_table"),
+    migration!(
+        5,
+        "0005_exchange_replacing_listings_table",
+        applied_if = LISTINGS_EXCHANGED
+    ),
+    migration!(
+        6,
+        "0006_drop_merge_tree_listings_table",
+        applied_if = MERGE_TREE_LISTINGS_DROPPED
+    ),
+    migration!(7, "0007_create_listing_events_table"),
+    migration!(8, "0008_create_currency_rates_table"),
+    migration!(9, "0009_add_listing_variant"),
+    migration!(10, "0010_add_listing_mod_values"),
+    migration!(11, "0011_add_listing_icon"),
+    migration!(12, "0012_create_leagues_table"),
+    migration!(13, "0013_add_listing_event_account"),
+    migration!(14, "0014_create_account_activity_table"),
+    migration!(15, "0015_create_account_activity_view"),
+    migration!(16, "0016_add_account_activity_unpriced"),
+    migration!(17, "0017_drop_account_activity_view"),
+    migration!(18, "0018_create_account_activity_unpriced_view"),
+];
+
+/// Statements creating the database and the table recording applied migrations, run before any
+/// migration
+const BOOTSTRAP: &[&str] = &[
+    "CREATE DATABASE IF NOT EXISTS ledger",
+    "CREATE TABLE IF NOT EXISTS ledger.schema_migrations (
+        version UInt32,
+        name String,
+        applied_at DateTime DEFAULT now()
+    ) ENGINE = MergeTree ORDER BY version",
+];
+
+/// Version of the schema this build expects
+pub fn latest_version() -> u32 {
+    MIGRATIONS.last().map_or(0, |m| m.version)
+}
+
+/// Migrations which haven't been applied to a schema at `version`
+pub fn pending(version: u32) -> impl Iterator<Item = &'static Migration> {
+    MIGRATIONS.iter().filter(move |m| m.version > version)
+}
+
+/// Applies every pending migration in order, recording each once it succeeds. Returns the
+/// migrations applied, or which would be with `dry_run`
+pub async fn migrate(
+    client: &clickhouse::Client,
+    dry_run: bool,
+) -> anyhow::Result<Vec<&'static Migration>> {
+    let version = current_version(client).await?;
+    let pending = pending(version).collect::<Vec<_>>();
+    if dry_run {
+        return Ok(pending);
+    }
+
+    for statement in BOOTSTRAP {
+        client.query(statement).execute().await?;
+    }
+
+    for m in &pending {
+        if already_applied(client, m).await? {
+            tracing::info!("migration {} is already in place, recording it", m.name);
+        } else {
+            tracing::info!("applying migration {}", m.name);
+
+            client
+                .query(m.sql)
+                .execute()
+                .await
+                .with_context(|| format!("failed applying migration {}", m.name))?;
+        }
+        client
+            .query("INSERT INTO ledger.schema_migrations (version, name) VALUES (?, ?)")
+            .bind(m.version)
+            .bind(m.name)
+            .execute()
+            .await?;
+    }
+
+    Ok(pending)
+}
+
+async fn already_applied(client: &clickhouse::Client, m: &Migration) -> anyhow::Result<bool> {
+    let Some(query) = m.applied_if else {
+        return Ok(false);
+    };
+
+    let applied = client
+        .query(query)
+        .fetch_one::<u8>()
+        .await
+        .with_context(|| format!("failed checking whether {} is applied", m.name))?;
+
+    Ok(applied != 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{latest_version, pending, MIGRATIONS};
+
+    #[test]
+    fn migrations_are_ordered() {
+        for (i, m) in MIGRATIONS.iter().enumerate() {
+            assert_eq!(m.version as usize, i + 1, "{} is out of order", m.name);
+            assert!(m.name.starts_with(&format!("{:04}_", m.version)));
+
+            // the http interface only runs a single statement per query
+            let statements = m.sql.split(';').filter(|s| !s.trim().is_empty()).count();
+            assert_eq!(statements, 1, "{} must be a single statement", m.name);
+        }
+
+        // exchanging or dropping tables again after a crash before the version was recorded
+        // would lose the listings
+        for m in MIGRATIONS {
+            let upper = m.sql.to_uppercase();
+            if upper.starts_with("EXCHANGE") || upper.starts_with("DROP TABLE") {
+                assert!(m.applied_if.is_some(), "{} must check it's applied", m.name);
+            }
+        }
+
+        assert_eq!(latest_version(), MIGRATIONS.len() as u32);
+        assert_eq!(pending(0).count(), MIGRATIONS.len());
+        assert_eq!(pending(latest_version()).count(), 0);
+    }
+}
@@ -0,0 +1,207 @@
+use core::fmt;
+
+use poe_types::item::Item;
+use serde::{Deserialize, Serialize};
+
+/// ModKind is which mod list of an item a mod is in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ModKind {
+    Implicit,
+    Explicit,
+    Crafted,
+    Fractured,
+    Enchant,
+    Crucible,
+}
+
+impl fmt::Display for ModKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ModKind::Implicit => write!(f, "implicit"),
+            ModKind::Explicit => write!(f, "explicit"),
+            ModKind::Crafted => write!(f, "crafted"),
+            ModKind::Fractured => write!(f, "fractured"),
+            ModKind::Enchant => write!(f, "enchant"),
+            ModKind::Crucible => write!(f, "crucible"),
+        }
+    }
+}
+
+/// NumericMod is a mod split into the template of its stat and the values it rolled, so
+/// `+87 to maximum Life` is the stat `+# to maximum Life` with the value 87
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NumericMod {
+    pub kind: ModKind,
+    pub stat: String,
+    pub values: Vec<f64>,
+}
+
+impl NumericMod {
+    pub fn parse(kind: ModKind, line: &str) -> Self {
+        let (stat, values) = normalize_mod(line);
+
+        Self { kind, stat, values }
+    }
+}
+
+/// Every mod of an item, in the order the mod lists are shown in game
+pub fn item_mods(item: &Item) -> Vec<NumericMod> {
+    [
+        (ModKind::Enchant, &item.enchant_mods),
+        (ModKind::Implicit, &item.implicit_mods),
+        (ModKind::Fractured, &item.fractured_mods),
+        (ModKind::Explicit, &item.explicit_mods),
+        (ModKind::Crafted, &item.crafted_mods),
+        (ModKind::Crucible, &item.crucible_mods),
+    ]
+    .into_iter()
+    .flat_map(|(kind, mods)| {
+        mods.iter()
+            .flatten()
+            .map(move |line| NumericMod::parse(kind, line))
+    })
+    .collect()
+}
+
+/// Replaces every number in a mod with `#`, returning the template along with the numbers.
+///
+/// Signed numbers become `+#` with a signed value, so `-10% to Fire Resistance` and
+/// `+40% to Fire Resistance` share a stat the same way they do on the trade site. A `-` between
+/// numbers, as in `Adds 1-5 Lightning Damage`, is kept as is.
+pub fn normalize_mod(line: &str) -> (String, Vec<f64>) {
+    let bytes = line.as_bytes();
+    let is_digit = |i: usize| bytes.get(i).is_some_and(u8::is_ascii_digit);
+
+    let mut stat = String::with_capacity(line.len());
+    let mut values = Vec::new();
+
+    let mut i = 0;
+    while i < bytes.len() {
+        let signed = matches!(bytes[i], b'+' | b'-')
+            && is_digit(i + 1)
+            && (i == 0 || !bytes[i - 1].is_ascii_alphanumeric());
+
+        if signed || is_digit(i) {
+            let start = if signed { i + 1 } else { i };
+            let mut end = start;
+            while is_digit(end) || (bytes.get(end) == Some(&b'.') && is_digit(end + 1)) {
+                end += 1;
+            }
+
+            // only digits and dots separating digits were taken, so this can't fail
+            let value = line[start..end].parse::<f64>().unwrap_or_default();
+            match (signed, bytes[i]) {
+                (true, b'-') => values.push(-value),
+                _ => values.push(value),
+            }
+            stat.push_str(if signed { "+#" } else { "#" });
+
+            i = end;
+            continue;
+        }
+
+        let c = line[i..].chars().next().unwrap_or_default();
+        stat.push(c);
+        i += c.len_utf8();
+    }
+
+    (stat, values)
+}
+
+#[cfg(test)]
+mod tests {
+    use poe_types::item::Item;
+
+    use super::{item_mods, normalize_mod, ModKind, NumericMod};
+
+    #[test]
+    fn normalizes_mods() {
+        let cases: &[(&str, &str, &[f64])] = &[
+            ("+87 to maximum Life", "+# to maximum Life", &[87.0]),
+            (
+                "-10% to Fire Resistance",
+                "+#% to Fire Resistance",
+                &[-10.0],
+            ),
+            (
+                "Adds 1-52 Lightning Damage to Attacks",
+                "Adds #-# Lightning Damage to Attacks",
+                &[1.0, 52.0],
+            ),
+            (
+                "Adds 10 to 20 Physical Damage",
+                "Adds # to # Physical Damage",
+                &[10.0, 20.0],
+            ),
+            (
+                "0.4% of Physical Attack Damage Leeched as Life",
+                "#% of Physical Attack Damage Leeched as Life",
+                &[0.4],
+            ),
+            (
+                "Socketed Gems are Supported by Level 20 Added Fire Damage",
+                "Socketed Gems are Supported by Level # Added Fire Damage",
+                &[20.0],
+            ),
+            (
+                "Cannot be Frozen\n+1 to Maximum Power Charges",
+                "Cannot be Frozen\n+# to Maximum Power Charges",
+                &[1.0],
+            ),
+            (
+                "Corrupted Blood cannot be inflicted on you",
+                "Corrupted Blood cannot be inflicted on you",
+                &[],
+            ),
+            (
+                "Grants Level 1 Ünique Skill.",
+                "Grants Level # Ünique Skill.",
+                &[1.0],
+            ),
+        ];
+
+        for (line, stat, values) in cases {
+            let (s, v) = normalize_mod(line);
+            assert_eq!(
+                (s.as_str(), v.as_slice()),
+                (*stat, *values),
+                "normalizing {line:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn collects_every_mod_list() {
+        let item = Item {
+            implicit_mods: Some(vec!["+25% to Cold Resistance".to_owned()]),
+            explicit_mods: Some(vec!["+87 to maximum Life".to_owned()]),
+            crafted_mods: Some(vec!["+1 to Level of Socketed Gems".to_owned()]),
+            enchant_mods: Some(vec!["Allocates Elemental Equilibrium".to_owned()]),
+            ..Default::default()
+        };
+
+        let kinds = item_mods(&item)
+            .into_iter()
+            .map(|m| m.kind)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            vec![
+                ModKind::Enchant,
+                ModKind::Implicit,
+                ModKind::Explicit,
+                ModKind::Crafted
+            ]
+        );
+
+        assert_eq!(
+            NumericMod::parse(ModKind::Explicit, "+87 to maximum Life"),
+            NumericMod {
+                kind: ModKind::Explicit,
+                stat: "+# to maximum Life".to_owned(),
+                values: vec![87.0],
+            }
+        );
+    }
+}
@@ -0,0 +1,347 @@
+use core::fmt;
+
+use thiserror::Error;
+
+use crate::listing::{ComplexPrice, ListingCurrency};
+
+/// Note is what a price note on an item or stash asks for
+#[derive(Debug, Clone, PartialEq)]
+pub enum Note {
+    /// `~price` or `~b/o` followed by an amount and currency
+    Price(PriceNote),
+    /// `~skip`, the item isn't priced even when its stash is
+    Skip,
+    /// `~c/o` or `~offer`, the seller takes offers instead of naming a price
+    Offer,
+    /// anything else, notes which aren't tags don't price the item
+    Text,
+}
+
+/// PriceKind is whether a price is final or open to negotiation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PriceKind {
+    /// `~price`
+    Exact,
+    /// `~b/o`
+    Buyout,
+}
+
+impl fmt::Display for PriceKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PriceKind::Exact => write!(f, "exact"),
+            PriceKind::Buyout => write!(f, "buyout"),
+        }
+    }
+}
+
+/// PriceNote asks `amount` of `currency` for every `units` of an item
+#[derive(Debug, Clone, PartialEq)]
+pub struct PriceNote {
+    pub kind: PriceKind,
+    pub amount: f64,
+    pub units: f64,
+    pub currency: ListingCurrency,
+}
+
+impl PriceNote {
+    pub fn unit_price(&self) -> f64 {
+        self.amount / self.units
+    }
+}
+
+impl From<PriceNote> for ComplexPrice {
+    fn from(note: PriceNote) -> Self {
+        Self {
+            normalized_price: 0.0,
+            listed_price: note.unit_price(),
+            listed_currency: note.currency,
+            listed_amount: note.amount,
+            listed_units: note.units,
+            rate_snapshot_id: 0,
+        }
+    }
+}
+
+/// NoteError is why a note tagged as a price couldn't be read
+#[derive(Debug, Error, PartialEq)]
+pub enum NoteError {
+    #[error("price note is missing an amount")]
+    MissingAmount,
+    #[error("price note has an invalid amount: `{0}`")]
+    InvalidAmount(String),
+    #[error(
+        "price note amount could be read with a comma as decimal or thousands separator: `{0}`"
+    )]
+    AmbiguousAmount(String),
+    #[error("price note amounts must be positive: `{0}`")]
+    NonPositiveAmount(String),
+    #[error("price note is missing a currency")]
+    MissingCurrency,
+    #[error("price note has an unknown currency: `{0}`")]
+    UnknownCurrency(String),
+    #[error("price note has unexpected text after the currency: `{0}`")]
+    TrailingText(String),
+}
+
+/// Parses an item or stash note.
+///
+/// The grammar is `~price|~b/o <amount>[/<units>] <currency>`, where amounts may use a comma as
+/// the decimal separator, or as the thousands separator where that's the only reading, and any
+/// whitespace may separate the parts, or none where unambiguous.
+/// Currencies must be a tag or alias in the currency catalogue.
+pub fn parse_note(note: &str) -> Result<Note, NoteError> {
+    let note = note.trim();
+
+    let (keyword, rest) = note.split_once(char::is_whitespace).unwrap_or((note, ""));
+    let kind = match keyword {
+        "~price" => PriceKind::Exact,
+        "~b/o" => PriceKind::Buyout,
+        "~skip" => return Ok(Note::Skip),
+        "~c/o" | "~offer" => return Ok(Note::Offer),
+        _ => return Ok(Note::Text),
+    };
+
+    let (amount, rest) = parse_amount(rest)?;
+    let (units, rest) = match rest.trim_start().strip_prefix('/') {
+        Some(rest) => parse_amount(rest)?,
+        None => (1.0, rest),
+    };
+
+    let rest = rest.trim_start();
+    let end = rest
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '-'))
+        .unwrap_or(rest.len());
+    let (currency, rest) = rest.split_at(end);
+    if currency.is_empty() {
+        return Err(NoteError::MissingCurrency);
+    }
+
+    let tag = currency.to_ascii_lowercase();
+    let currency = ListingCurrency::from_tag(&tag).ok_or(NoteError::UnknownCurrency(tag))?;
+
+    let rest = rest.trim();
+    if !rest.is_empty() {
+        return Err(NoteError::TrailingText(rest.to_owned()));
+    }
+
+    Ok(Note::Price(PriceNote {
+        kind,
+        amount,
+        units,
+        currency,
+    }))
+}
+
+/// Parses the amount at the start of `s`, returning it along with the rest of `s`
+fn parse_amount(s: &str) -> Result<(f64, &str), NoteError> {
+    let s = s.trim_start();
+    let end = s
+        .find(|c: char| !(c.is_ascii_digit() || c == '.' || c == ','))
+        .unwrap_or(s.len());
+    let (raw, rest) = s.split_at(end);
+
+    if raw.is_empty() {
+        return match s.starts_with('-') {
+            true => Err(NoteError::NonPositiveAmount(first_word(s).to_owned())),
+            false => Err(NoteError::MissingAmount),
+        };
+    }
+
+    let amount = without_separators(raw)?
+        .parse::<f64>()
+        .map_err(|_| NoteError::InvalidAmount(raw.to_owned()))?;
+    if amount <= 0.0 || !amount.is_finite() {
+        return Err(NoteError::NonPositiveAmount(raw.to_owned()));
+    }
+
+    Ok((amount, rest))
+}
+
+/// Rewrites an amount's commas for parsing. The game writes decimals with the client's locale, so
+/// a comma is usually a decimal separator, but amounts with a point or several commas can only be
+/// using them to group thousands. `1,000` reads either way and is rejected
+fn without_separators(raw: &str) -> Result<String, NoteError> {
+    if !raw.contains(',') {
+        return Ok(raw.to_owned());
+    }
+
+    let (int, fraction) = match raw.split_once('.') {
+        Some((int, fraction)) => (int, Some(fraction)),
+        None => (raw, None),
+    };
+    let groups = int.split(',').collect::<Vec<_>>();
+
+    if let ([whole, decimals], None) = (groups.as_slice(), fraction) {
+        // no thousands are grouped after a leading 0
+        if decimals.len() == 3 && !whole.is_empty() && !whole.starts_with('0') {
+            return Err(NoteError::AmbiguousAmount(raw.to_owned()));
+        }
+
+        return Ok(format!("{whole}.{decimals}"));
+    }
+
+    let grouped = (1..=3).contains(&groups[0].len())
+        && !groups[0].starts_with('0')
+        && groups[1..].iter().all(|g| g.len() == 3);
+    if !grouped {
+        return Err(NoteError::InvalidAmount(raw.to_owned()));
+    }
+
+    Ok(match fraction {
+        Some(fraction) => format!("{}.{fraction}", groups.concat()),
+        None => groups.concat(),
+    })
+}
+
+fn first_word(s: &str) -> &str {
+    s.split(char::is_whitespace).next().unwrap_or_default()
+}
+
+/// The price a note asks for, if it is a price note
+pub fn note_to_complex_price(note: &str) -> Result<Option<ComplexPrice>, NoteError> {
+    match parse_note(note)? {
+        Note::Price(price) => Ok(Some(price.into())),
+        Note::Skip | Note::Offer | Note::Text => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{note_to_complex_price, parse_note, Note, NoteError, PriceKind, PriceNote};
+    use crate::listing::ListingCurrency;
+
+    fn price(kind: PriceKind, amount: f64, units: f64, currency: &str) -> Result<Note, NoteError> {
+        Ok(Note::Price(PriceNote {
+            kind,
+            amount,
+            units,
+            currency: ListingCurrency::from_tag(currency)
+                .expect("currency must be in the catalogue"),
+        }))
+    }
+
+    fn exact(amount: f64, currency: &str) -> Result<Note, NoteError> {
+        price(PriceKind::Exact, amount, 1.0, currency)
+    }
+
+    #[test]
+    fn parses_note_corpus() {
+        use NoteError::*;
+        use PriceKind::*;
+
+        let corpus: &[(&str, Result<Note, NoteError>)] = &[
+            // keywords
+            ("~price 70 chaos", exact(70.0, "chaos")),
+            ("~b/o 10 divine", price(Buyout, 10.0, 1.0, "divine")),
+            ("~skip", Ok(Note::Skip)),
+            ("~skip 5 chaos", Ok(Note::Skip)),
+            ("~c/o 5 chaos", Ok(Note::Offer)),
+            ("~offer", Ok(Note::Offer)),
+            ("random note on item", Ok(Note::Text)),
+            ("", Ok(Note::Text)),
+            ("WTS ~price 1 chaos", Ok(Note::Text)),
+            ("~pricey 1 chaos", Ok(Note::Text)),
+            ("~PRICE 1 chaos", Ok(Note::Text)),
+            // amounts
+            ("~price 0.8 divine", exact(0.8, "divine")),
+            ("~price 0,8 divine", exact(0.8, "divine")),
+            ("~price .5 divine", exact(0.5, "divine")),
+            ("~price 100/20 chaos", price(Exact, 100.0, 20.0, "chaos")),
+            ("~price 5/20 divine", price(Exact, 5.0, 20.0, "divine")),
+            ("~price 1,5/3 divine", price(Exact, 1.5, 3.0, "divine")),
+            ("~price 100 / 20 chaos", price(Exact, 100.0, 20.0, "chaos")),
+            (
+                "~b/o 1000000 chaos",
+                price(Buyout, 1_000_000.0, 1.0, "chaos"),
+            ),
+            ("~price 0,125 divine", exact(0.125, "divine")),
+            ("~price 1,000.5 chaos", exact(1000.5, "chaos")),
+            (
+                "~b/o 1,000,000 chaos",
+                price(Buyout, 1_000_000.0, 1.0, "chaos"),
+            ),
+            (
+                "~price 1,25/1,000.5 chaos",
+                price(Exact, 1.25, 1000.5, "chaos"),
+            ),
+            // whitespace
+            ("  ~price 1 chaos  ", exact(1.0, "chaos")),
+            ("~price   1   chaos", exact(1.0, "chaos")),
+            ("~price\t1\tchaos", exact(1.0, "chaos")),
+            ("~price\u{a0}1\u{a0}chaos", exact(1.0, "chaos")),
+            ("~price 1chaos", exact(1.0, "chaos")),
+            ("~price 1 chaos\n", exact(1.0, "chaos")),
+            // currencies
+            ("~price 20 exa", exact(20.0, "exalted")),
+            ("~price 20 exalted", exact(20.0, "exalted")),
+            ("~price 3 alch", exact(3.0, "alch")),
+            ("~price 2 mirror", exact(2.0, "mirror")),
+            ("~price 40 mirror-shard", exact(40.0, "mirror-shard")),
+            ("~price 1 gcp", exact(1.0, "gcp")),
+            ("~price 1 vaal", exact(1.0, "vaal")),
+            (
+                "~price 4 sacrifice-at-dusk",
+                exact(4.0, "sacrifice-at-dusk"),
+            ),
+            (
+                "~price 2 winged-divination-scarab",
+                exact(2.0, "winged-divination-scarab"),
+            ),
+            ("~price 5 Chaos", exact(5.0, "chaos")),
+            // errors
+            ("~price", Err(MissingAmount)),
+            ("~price chaos", Err(MissingAmount)),
+            ("~price 10", Err(MissingCurrency)),
+            ("~price 10/ chaos", Err(MissingAmount)),
+            ("~price 1.2.3 chaos", Err(InvalidAmount("1.2.3".to_owned()))),
+            ("~price 1,2,3 chaos", Err(InvalidAmount("1,2,3".to_owned()))),
+            (
+                "~price 1,000 chaos",
+                Err(AmbiguousAmount("1,000".to_owned())),
+            ),
+            (
+                "~price 12,500 chaos",
+                Err(AmbiguousAmount("12,500".to_owned())),
+            ),
+            (
+                "~price 10,00.5 chaos",
+                Err(InvalidAmount("10,00.5".to_owned())),
+            ),
+            ("~price 0 chaos", Err(NonPositiveAmount("0".to_owned()))),
+            ("~price 10/0 chaos", Err(NonPositiveAmount("0".to_owned()))),
+            ("~price -5 chaos", Err(NonPositiveAmount("-5".to_owned()))),
+            ("~price many chaos", Err(MissingAmount)),
+            (
+                "~price 10 offer-gift",
+                Err(UnknownCurrency("offer-gift".to_owned())),
+            ),
+            ("~price 10 chaos orbs", Err(TrailingText("orbs".to_owned()))),
+            ("~price 10 chaos!", Err(TrailingText("!".to_owned()))),
+        ];
+
+        for (note, expected) in corpus {
+            assert_eq!(&parse_note(note), expected, "parsing {note:?}");
+        }
+    }
+
+    #[test]
+    fn price_notes_are_per_unit() {
+        let p = note_to_complex_price("~price 100/20 chaos")
+            .expect("should parse")
+            .expect("should be a price");
+        assert_eq!(5.0, p.listed_price);
+        assert_eq!(100.0, p.listed_amount);
+        assert_eq!(20.0, p.listed_units);
+        assert_eq!(ListingCurrency::CHAOS, p.listed_currency);
+
+        let p = note_to_complex_price("~b/o 3 exalted")
+            .expect("should parse")
+            .expect("should be a price");
+        assert_eq!(3.0, p.listed_price);
+        assert_eq!("exalted", p.listed_currency.tag());
+
+        assert!(note_to_complex_price("~skip").unwrap().is_none());
+        assert!(note_to_complex_price("~price 1 chaos orbs").is_err());
+    }
+}
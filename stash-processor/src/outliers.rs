@@ -0,0 +1,294 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    env,
+};
+
+use crate::{
+    listing::{Listing, ListingCurrency},
+    rates::median,
+};
+
+const COPIES_WEIGHT: f64 = 0.35;
+const DEVIATION_WEIGHT: f64 = 0.5;
+const IDENTICAL_PRICES_WEIGHT: f64 = 0.25;
+const CHURN_WEIGHT: f64 = 0.25;
+
+/// OutlierDetector scores listings on how likely they are to be price fixing rather than a real
+/// offer, marking those scoring at least `threshold` as outliers.
+///
+/// A listing's score is the sum of the weights of the signals it shows:
+/// - its account lists more than `max_copies` copies of the item in the same batch
+/// - its normalized price is more than `price_ratio` times above or below the median of the
+///   recent non outlier prices of the item
+/// - its stash holds at least `identical_prices` differently named items all at the same price
+/// - its account is churning, removing nearly every listing it puts up, see
+///   [`ChurnPolicy`](crate::accounts::ChurnPolicy)
+///
+/// With the default threshold a large deviation is enough on its own, while mass listing needs
+/// a second signal, so bulk sellers pricing at the market aren't marked.
+pub struct OutlierDetector {
+    /// recent normalized prices of each item, keyed by league, name and variant
+    recent: HashMap<(String, String, String), VecDeque<f64>>,
+    /// churning accounts, keyed by account name and league
+    churning: HashSet<(String, String)>,
+    window: usize,
+    min_history: usize,
+    max_copies: usize,
+    price_ratio: f64,
+    identical_prices: usize,
+    threshold: f64,
+}
+
+impl OutlierDetector {
+    pub fn new(
+        window: usize,
+        min_history: usize,
+        max_copies: usize,
+        price_ratio: f64,
+        identical_prices: usize,
+        threshold: f64,
+    ) -> Self {
+        Self {
+            recent: HashMap::new(),
+            churning: HashSet::new(),
+            window: window.max(1),
+            min_history,
+            max_copies,
+            price_ratio,
+            identical_prices,
+            threshold,
+        }
+    }
+
+    pub fn from_env() -> Self {
+        let number = |key: &str, default: usize| {
+            env::var(key)
+                .map(|v| {
+                    v.parse::<usize>()
+                        .unwrap_or_else(|_| panic!("{key} must be a number"))
+                })
+                .unwrap_or(default)
+        };
+        let float = |key: &str, default: f64| {
+            env::var(key)
+                .map(|v| {
+                    v.parse::<f64>()
+                        .unwrap_or_else(|_| panic!("{key} must be a number"))
+                })
+                .unwrap_or(default)
+        };
+
+        Self::new(
+            number("OUTLIER_PRICE_WINDOW", 100),
+            number("OUTLIER_MIN_HISTORY", 10),
+            number("OUTLIER_MAX_COPIES", 5),
+            float("OUTLIER_PRICE_RATIO", 5.0),
+            number("OUTLIER_IDENTICAL_PRICES", 10),
+            float("OUTLIER_THRESHOLD", 0.5),
+        )
+    }
+
+    /// Replaces the accounts which are churning, read from the account aggregates
+    pub fn set_churning_accounts(&mut self, accounts: impl IntoIterator<Item = (String, String)>) {
+        self.churning = accounts.into_iter().collect();
+    }
+
+    /// Scores every listing in a batch, returning how many were marked as outliers. Prices of
+    /// listings which aren't outliers become part of the recent prices used for later batches
+    pub fn score(&mut self, listings: &mut [Listing]) -> usize {
+        let mut copies: HashMap<(&str, &str, &str), usize> = HashMap::new();
+        let mut stash_prices: HashMap<(&str, u64, ListingCurrency), Vec<&str>> = HashMap::new();
+        for l in listings.iter() {
+            *copies
+                .entry((&l.account_name, &l.league, &l.name))
+                .or_default() += 1;
+            stash_prices
+                .entry((
+                    &l.stash_id,
+                    l.price.listed_price.to_bits(),
+                    l.price.listed_currency,
+                ))
+                .or_default()
+                .push(&l.name);
+        }
+
+        let scores = listings
+            .iter()
+            .map(|l| {
+                let mut score = 0.0;
+
+                if !l.account_name.is_empty()
+                    && copies[&(l.account_name.as_str(), l.league.as_str(), l.name.as_str())]
+                        > self.max_copies
+                {
+                    score += COPIES_WEIGHT;
+                }
+
+                if self.deviates(l) {
+                    score += DEVIATION_WEIGHT;
+                }
+
+                if self
+                    .churning
+                    .contains(&(l.account_name.clone(), l.league.clone()))
+                {
+                    score += CHURN_WEIGHT;
+                }
+
+                let key = (
+                    l.stash_id.as_str(),
+                    l.price.listed_price.to_bits(),
+                    l.price.listed_currency,
+                );
+                let mut names = stash_prices[&key].clone();
+                names.sort_unstable();
+                names.dedup();
+                if names.len() >= self.identical_prices {
+                    score += IDENTICAL_PRICES_WEIGHT;
+                }
+
+                score
+            })
+            .collect::<Vec<f64>>();
+
+        let mut outliers = 0;
+        for (listing, score) in listings.iter_mut().zip(scores) {
+            listing.outlier_score = score;
+            listing.is_outlier = score >= self.threshold;
+
+            if listing.is_outlier {
+                outliers += 1;
+            } else if listing.price.normalized_price > 0.0 {
+                let recent = self
+                    .recent
+                    .entry((
+                        listing.league.clone(),
+                        listing.name.clone(),
+                        listing.variant.clone(),
+                    ))
+                    .or_default();
+                if recent.len() >= self.window {
+                    recent.pop_front();
+                }
+                recent.push_back(listing.price.normalized_price);
+            }
+        }
+
+        outliers
+    }
+
+    fn deviates(&self, listing: &Listing) -> bool {
+        let price = listing.price.normalized_price;
+        if price <= 0.0 {
+            return false;
+        }
+
+        let Some(recent) = self
+            .recent
+            .get(&(
+                listing.league.clone(),
+                listing.name.clone(),
+                listing.variant.clone(),
+            ))
+            .filter(|r| r.len() >= self.min_history)
+        else {
+            return false;
+        };
+
+        let mut prices = recent.iter().copied().collect::<Vec<_>>();
+        match median(&mut prices) {
+            Some(median) => price * self.price_ratio < median || price > median * self.price_ratio,
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::OutlierDetector;
+    use crate::listing::Listing;
+
+    fn listing(account: &str, stash: &str, name: &str, price: f64) -> Listing {
+        let mut listing = Listing {
+            name: name.to_owned(),
+            account_name: account.to_owned(),
+            stash_id: stash.to_owned(),
+            league: "Standard".to_owned(),
+            ..Default::default()
+        };
+        listing.price.listed_price = price;
+        listing.price.normalized_price = price;
+
+        listing
+    }
+
+    fn detector() -> OutlierDetector {
+        OutlierDetector::new(100, 10, 5, 5.0, 10, 0.5)
+    }
+
+    #[test]
+    fn deviating_prices_are_outliers() {
+        let mut detector = detector();
+
+        let mut history = (0..20)
+            .map(|i| listing(&format!("seller{i}"), &format!("s{i}"), "Mageblood", 200.0))
+            .collect::<Vec<_>>();
+        assert_eq!(detector.score(&mut history), 0);
+
+        let mut batch = vec![
+            listing("fixer", "a", "Mageblood", 1.0),
+            listing("honest", "b", "Mageblood", 180.0),
+        ];
+        assert_eq!(detector.score(&mut batch), 1);
+        assert!(batch[0].is_outlier);
+        assert!(!batch[1].is_outlier);
+    }
+
+    #[test]
+    fn mass_listing_needs_a_second_signal() {
+        let mut detector = detector();
+
+        // a bulk seller listing many copies in separate stashes isn't enough on its own
+        let mut bulk = (0..10)
+            .map(|i| listing("bulk", &format!("s{i}"), "Divine Orb", 200.0))
+            .collect::<Vec<_>>();
+        assert_eq!(detector.score(&mut bulk), 0);
+        assert_eq!(bulk[0].outlier_score, 0.35);
+
+        // copies in a tab pricing a dozen different items the same are
+        let mut fixed = (0..12)
+            .map(|i| listing("fixer", "tab", &format!("Unique {}", i % 12), 1.0))
+            .chain((0..6).map(|_| listing("fixer", "tab", "Unique 0", 1.0)))
+            .collect::<Vec<_>>();
+        detector.score(&mut fixed);
+
+        assert!(fixed
+            .iter()
+            .filter(|l| l.name == "Unique 0")
+            .all(|l| l.is_outlier));
+        assert!(fixed
+            .iter()
+            .filter(|l| l.name == "Unique 1")
+            .all(|l| !l.is_outlier && l.outlier_score == 0.25));
+    }
+
+    #[test]
+    fn churning_accounts_need_a_second_signal() {
+        let mut detector = detector();
+        detector.set_churning_accounts([("churner".to_owned(), "Standard".to_owned())]);
+
+        let mut batch = vec![
+            listing("churner", "a", "Mageblood", 200.0),
+            listing("honest", "b", "Mageblood", 200.0),
+        ];
+        assert_eq!(detector.score(&mut batch), 0);
+        assert_eq!(batch[0].outlier_score, 0.25);
+        assert_eq!(batch[1].outlier_score, 0.0);
+
+        // along with a tab pricing a dozen different items the same it is
+        let mut fixed = (0..10)
+            .map(|i| listing("churner", "tab", &format!("Unique {i}"), 1.0))
+            .collect::<Vec<_>>();
+        assert_eq!(detector.score(&mut fixed), 10);
+    }
+}
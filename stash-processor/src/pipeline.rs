@@ -0,0 +1,187 @@
+use core::fmt;
+use std::collections::{BTreeMap, HashSet};
+
+use poe_types::{item::Item, stash::PublicStashChange};
+
+use crate::{
+    category::ItemCategory,
+    listing::{listing_price, Listing},
+    note::note_to_complex_price,
+};
+
+/// ExtractedListings are the listings found in a set of stash changes, along with the items
+/// which looked priceable but failed to convert into a listing
+#[derive(Default)]
+pub struct ExtractedListings {
+    pub listings: Vec<Listing>,
+    pub failed_items: Vec<FailedItem>,
+    /// ids of every stash in the changes, including those without any listings left
+    pub stash_ids: Vec<String>,
+    /// ids of items in the stashes which aren't listed, as they are unpriced or failed, so a
+    /// listing of them which went away wasn't sold
+    pub unlisted_item_ids: HashSet<String>,
+    /// number of items which weren't considered for a listing, by why
+    pub skipped: BTreeMap<SkipReason, usize>,
+}
+
+/// SkipReason is why an item isn't considered for a listing at all
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SkipReason {
+    /// not a category traded by name or base type, like rare equipment
+    Untradable,
+    MissingId,
+    /// neither the item nor its stash has a price note, or the item is `~skip` or an offer
+    Unpriced,
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SkipReason::Untradable => write!(f, "untradable"),
+            SkipReason::MissingId => write!(f, "missing_id"),
+            SkipReason::Unpriced => write!(f, "unpriced"),
+        }
+    }
+}
+
+/// FailedItem is an item which failed to convert into a listing, along with the stash it was
+/// in so it can be converted again once the cause is fixed
+pub struct FailedItem {
+    pub item: Item,
+    pub stash_id: String,
+    /// the stash's league, which the item's is replaced with when it is converted
+    pub league: Option<String>,
+    pub account_name: Option<String>,
+    pub stash_note: Option<String>,
+    pub error: String,
+}
+
+/// The name of the stash when it is a price note, which prices every item inside without a note
+pub fn stash_price_note(stash: &PublicStashChange) -> Option<&str> {
+    stash
+        .stash
+        .as_deref()
+        .filter(|name| matches!(note_to_complex_price(name), Ok(Some(_))))
+}
+
+/// Extracts the priced items of every tradable category from a set of stash changes
+pub fn extract_listings(stashes: Vec<PublicStashChange>) -> ExtractedListings {
+    let mut extracted = ExtractedListings::default();
+
+    for stash in stashes {
+        let stash_note = stash_price_note(&stash).map(|n| n.to_owned());
+        extracted.stash_ids.push(stash.id.clone());
+
+        for raw_item in stash.items {
+            // notes which fail to parse still count as priced, so the item is kept as failed
+            let is_priced = !matches!(
+                listing_price(raw_item.note.as_deref(), stash_note.as_deref()),
+                Ok(None)
+            );
+            let has_item_id = raw_item.id.is_some();
+
+            let category = match (ItemCategory::classify(&raw_item), has_item_id, is_priced) {
+                (Some(category), true, true) => category,
+                (None, _, _) => {
+                    *extracted.skipped.entry(SkipReason::Untradable).or_default() += 1;
+                    continue;
+                }
+                (_, false, _) => {
+                    *extracted.skipped.entry(SkipReason::MissingId).or_default() += 1;
+                    continue;
+                }
+                (_, _, false) => {
+                    *extracted.skipped.entry(SkipReason::Unpriced).or_default() += 1;
+                    extracted.unlisted_item_ids.extend(raw_item.id);
+                    continue;
+                }
+            };
+
+            // the stash's league is authoritative, items only repeat it
+            let mut item = raw_item.clone();
+            if stash.league.is_some() {
+                item.league.clone_from(&stash.league);
+            }
+
+            match Listing::from_item(item, category, stash_note.as_deref()) {
+                Ok(mut listing) => {
+                    listing.stash_id.clone_from(&stash.id);
+                    listing.account_name = stash.account_name.clone().unwrap_or_default();
+                    extracted.listings.push(listing);
+                }
+                Err(e) => {
+                    tracing::error!("failed converting item to a listing: {e}");
+                    extracted.unlisted_item_ids.extend(raw_item.id.clone());
+                    extracted.failed_items.push(FailedItem {
+                        item: raw_item,
+                        stash_id: stash.id.clone(),
+                        league: stash.league.clone(),
+                        account_name: stash.account_name.clone(),
+                        stash_note: stash_note.clone(),
+                        error: format!("{e:#}"),
+                    });
+                }
+            };
+        }
+    }
+
+    extracted
+}
+
+#[cfg(test)]
+mod tests {
+    use poe_types::{
+        item::{FrameType, Item},
+        stash::PublicStashChange,
+    };
+
+    use std::collections::{BTreeMap, HashSet};
+
+    use super::{extract_listings, SkipReason};
+    use crate::listing::PriceSource;
+
+    #[test]
+    fn items_in_priced_stashes_are_listed() {
+        let unique = |id: &str, note: Option<&str>| Item {
+            id: Some(id.to_owned()),
+            name: "Mageblood".to_owned(),
+            frame_type: Some(FrameType::Unique),
+            note: note.map(|n| n.to_owned()),
+            ..Default::default()
+        };
+
+        let stashes = vec![
+            PublicStashChange {
+                stash: Some("~price 1 divine".to_owned()),
+                league: Some("Necropolis".to_owned()),
+                items: vec![unique("a", None), unique("b", Some("~price 50 chaos"))],
+                ..Default::default()
+            },
+            PublicStashChange {
+                stash: Some("dump tab".to_owned()),
+                league: Some("Necropolis".to_owned()),
+                items: vec![unique("c", None), Item::default()],
+                ..Default::default()
+            },
+        ];
+
+        let extracted = extract_listings(stashes);
+        let sources = extracted
+            .listings
+            .iter()
+            .map(|l| (l.item_id.as_str(), l.price_source))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            sources,
+            vec![("a", PriceSource::Stash), ("b", PriceSource::Item)]
+        );
+        assert!(extracted.listings.iter().all(|l| l.league == "Necropolis"));
+        assert!(extracted.failed_items.is_empty());
+        assert_eq!(
+            extracted.skipped,
+            BTreeMap::from([(SkipReason::Untradable, 1), (SkipReason::Unpriced, 1)])
+        );
+        assert_eq!(extracted.unlisted_item_ids, HashSet::from(["c".to_owned()]));
+    }
+}
@@ -0,0 +1,296 @@
+use anyhow::Context;
+use poe_types::stash::PublicStashChange;
+use time::OffsetDateTime;
+
+use crate::{
+    dedup::DedupCache,
+    lifecycle::{ListingEvent, ListingTracker, StashStateStore},
+    listing::Listing,
+    outliers::OutlierDetector,
+    pipeline::{extract_listings, ExtractedListings},
+    rates::{CurrencyRates, RateSnapshot},
+    sink::ListingSink,
+};
+
+/// ProcessedPage is what a page of stash changes turned into, ready to be written
+pub struct ProcessedPage {
+    /// the listings to write, scored and without those written recently
+    pub extracted: ExtractedListings,
+    pub events: Vec<ListingEvent>,
+    pub outliers: usize,
+    pub duplicates: usize,
+    /// the rates the page's listings were normalized with, when the page refreshed them
+    pub rate_snapshot: Option<RateSnapshot>,
+}
+
+/// StashProcessor runs stash changes through every stage between a page and its listings being
+/// written, keeping the state the stages build up across pages. The processor, replay and
+/// ledger-dev all process pages with it, only differing in where pages come from and go to.
+///
+/// Pages are processed into listings and events first, which can be buffered, and written
+/// afterwards. The stash states the events were diffed into are only saved once written.
+pub struct StashProcessor<S: StashStateStore> {
+    rates: CurrencyRates,
+    outliers: OutlierDetector,
+    tracker: ListingTracker<S>,
+    dedup: DedupCache,
+}
+
+impl<S: StashStateStore> StashProcessor<S> {
+    pub fn new(
+        rates: CurrencyRates,
+        outliers: OutlierDetector,
+        tracker: ListingTracker<S>,
+        dedup: DedupCache,
+    ) -> Self {
+        Self {
+            rates,
+            outliers,
+            tracker,
+            dedup,
+        }
+    }
+
+    /// A processor tracking stashes in `store`, with every stage configured from the environment
+    pub fn from_env(store: S) -> Self {
+        Self::new(
+            CurrencyRates::from_env(),
+            OutlierDetector::from_env(),
+            ListingTracker::new(store),
+            DedupCache::from_env(),
+        )
+    }
+
+    pub fn rates_mut(&mut self) -> &mut CurrencyRates {
+        &mut self.rates
+    }
+
+    pub fn outliers_mut(&mut self) -> &mut OutlierDetector {
+        &mut self.outliers
+    }
+
+    /// Observes the rates priced in a page, extracts and normalizes its listings, diffs its
+    /// stashes into listing events, drops the listings written recently and scores the rest.
+    /// Scoring comes last so a listing seen again doesn't count towards recent prices twice.
+    ///
+    /// When the stashes can't be diffed the states diffed since the last write are dropped, so
+    /// pages processed since then but not yet written must be retried along with this one
+    pub async fn process(
+        &mut self,
+        stashes: Vec<PublicStashChange>,
+        now: OffsetDateTime,
+    ) -> anyhow::Result<ProcessedPage> {
+        self.rates.observe_stashes(&stashes, now);
+        let rate_snapshot = self.rates.refresh(now).cloned();
+
+        let mut extracted = extract_listings(stashes);
+        self.rates.normalize(&mut extracted.listings);
+
+        let events = match self.tracker.track(&extracted, now).await {
+            Ok(events) => events,
+            Err(e) => {
+                self.tracker.discard();
+                return Err(e.context("failed to track listings across stash changes"));
+            }
+        };
+        let duplicates = self.dedup.retain_new(&mut extracted.listings);
+        let outliers = self.outliers.score(&mut extracted.listings);
+
+        Ok(ProcessedPage {
+            extracted,
+            events,
+            outliers,
+            duplicates,
+            rate_snapshot,
+        })
+    }
+
+    /// Runs listings converted from redriven items through the stages after extraction. They
+    /// are added to the states of their stashes without diffing the rest of the stash, and no
+    /// rates are observed as the pages they came from were already
+    pub async fn process_redriven(
+        &mut self,
+        listings: Vec<Listing>,
+        now: OffsetDateTime,
+    ) -> anyhow::Result<ProcessedPage> {
+        let mut extracted = ExtractedListings {
+            listings,
+            ..Default::default()
+        };
+        self.rates.normalize(&mut extracted.listings);
+
+        let events = match self.tracker.track_redriven(&extracted.listings, now).await {
+            Ok(events) => events,
+            Err(e) => {
+                self.tracker.discard();
+                return Err(e.context("failed to track redriven listings"));
+            }
+        };
+        let duplicates = self.dedup.retain_new(&mut extracted.listings);
+        let outliers = self.outliers.score(&mut extracted.listings);
+
+        Ok(ProcessedPage {
+            extracted,
+            events,
+            outliers,
+            duplicates,
+            rate_snapshot: None,
+        })
+    }
+
+    /// Writes processed listings and events, then saves the stash states diffed since the last
+    /// write. When any of it fails the states are dropped and the listings forgotten, so the
+    /// pages they came from are processed the same way when retried
+    pub async fn write(
+        &mut self,
+        sink: &dyn ListingSink,
+        listings: &[Listing],
+        events: &[ListingEvent],
+    ) -> anyhow::Result<()> {
+        let written = async {
+            sink.write_listings(listings)
+                .await
+                .context("failed to write listings")?;
+            sink.write_events(events)
+                .await
+                .context("failed to write listing events")?;
+
+            self.tracker
+                .commit()
+                .await
+                .context("failed to save stash states")
+        }
+        .await;
+
+        if written.is_err() {
+            self.discard(listings);
+        }
+
+        written
+    }
+
+    /// Drops the stash states diffed since the last write and forgets the processed listings,
+    /// for pages which won't be written and are retried instead
+    pub fn discard(&mut self, listings: &[Listing]) {
+        self.dedup.forget(listings);
+        self.tracker.discard();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_trait::async_trait;
+    use poe_types::{
+        item::{FrameType, Item},
+        stash::PublicStashChange,
+    };
+    use time::OffsetDateTime;
+
+    use super::StashProcessor;
+    use crate::{
+        lifecycle::{MemoryStashStateStore, StashState, StashStateStore, StoredState},
+        listing::Listing,
+        sink::{ListingSink, MemorySink},
+    };
+
+    struct FailingSink;
+
+    #[async_trait]
+    impl ListingSink for FailingSink {
+        fn name(&self) -> &'static str {
+            "failing"
+        }
+
+        async fn write_listings(&self, _listings: &[Listing]) -> anyhow::Result<()> {
+            anyhow::bail!("sink is down")
+        }
+    }
+
+    /// Fails to read the `unreadable` stash
+    #[derive(Default)]
+    struct UnreadableStore(MemoryStashStateStore);
+
+    #[async_trait]
+    impl StashStateStore for UnreadableStore {
+        async fn get(&self, stash_id: &str) -> anyhow::Result<StoredState> {
+            match stash_id {
+                "unreadable" => anyhow::bail!("bucket is down"),
+                _ => self.0.get(stash_id).await,
+            }
+        }
+
+        async fn put(
+            &self,
+            stash_id: &str,
+            state: &StashState,
+            revision: u64,
+        ) -> anyhow::Result<()> {
+            self.0.put(stash_id, state, revision).await
+        }
+
+        async fn delete(&self, stash_id: &str, revision: u64) -> anyhow::Result<()> {
+            self.0.delete(stash_id, revision).await
+        }
+    }
+
+    fn stashes(id: &str) -> Vec<PublicStashChange> {
+        vec![PublicStashChange {
+            id: id.to_owned(),
+            league: Some("Standard".to_owned()),
+            items: vec![Item {
+                id: Some("a".to_owned()),
+                name: "Mageblood".to_owned(),
+                base_type: "Heavy Belt".to_owned(),
+                frame_type: Some(FrameType::Unique),
+                note: Some("~price 200 chaos".to_owned()),
+                ..Default::default()
+            }],
+            ..Default::default()
+        }]
+    }
+
+    #[tokio::test]
+    async fn failed_writes_are_processed_again() {
+        let mut processor = StashProcessor::from_env(MemoryStashStateStore::default());
+        let now = OffsetDateTime::now_utc();
+
+        let page = processor.process(stashes("stash"), now).await.unwrap();
+        assert_eq!(page.extracted.listings.len(), 1);
+        assert_eq!(page.events.len(), 1);
+        let written = processor
+            .write(&FailingSink, &page.extracted.listings, &page.events)
+            .await;
+        assert!(written.is_err());
+
+        // the failed page's listing isn't a duplicate and its stash is diffed again
+        let page = processor.process(stashes("stash"), now).await.unwrap();
+        assert_eq!(page.duplicates, 0);
+        assert_eq!(page.events.len(), 1);
+        let sink = MemorySink::default();
+        processor
+            .write(&sink, &page.extracted.listings, &page.events)
+            .await
+            .unwrap();
+        assert_eq!(sink.listings().len(), 1);
+
+        let page = processor.process(stashes("stash"), now).await.unwrap();
+        assert_eq!(page.duplicates, 1);
+        assert!(page.events.is_empty());
+    }
+
+    #[tokio::test]
+    async fn failed_tracking_drops_pending_states() {
+        let mut processor = StashProcessor::from_env(UnreadableStore::default());
+        let now = OffsetDateTime::now_utc();
+
+        let page = processor.process(stashes("stash"), now).await.unwrap();
+        assert_eq!(page.events.len(), 1);
+        assert!(processor.process(stashes("unreadable"), now).await.is_err());
+
+        // the first page's state was dropped, so writing saves nothing and it's diffed again
+        let sink = MemorySink::default();
+        processor.write(&sink, &[], &[]).await.unwrap();
+        let page = processor.process(stashes("stash"), now).await.unwrap();
+        assert_eq!(page.events.len(), 1);
+    }
+}
@@ -0,0 +1,450 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    env,
+};
+
+use poe_types::stash::PublicStashChange;
+use time::{Duration, OffsetDateTime};
+
+use crate::{
+    listing::{listing_price, Listing, ListingCurrency},
+    pipeline::stash_price_note,
+};
+
+/// Most observations kept per currency, older ones are dropped first
+const MAX_OBSERVATIONS: usize = 500;
+/// Observations further than this many scaled median absolute deviations from the median are ignored
+const OUTLIER_DEVIATIONS: f64 = 3.0;
+/// Scales the median absolute deviation to estimate the standard deviation of normally distributed data
+const MAD_SCALE: f64 = 1.4826;
+
+/// Rate is the chaos equivalent of a single unit of a currency
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rate {
+    pub chaos_equivalent: f64,
+    pub observations: usize,
+    pub updated_at: OffsetDateTime,
+}
+
+/// RateSnapshot is the set of currency rates per league at a point in time.
+///
+/// Listings record the id of the snapshot used to normalize them, so prices can be traced back
+/// to the rates they were converted with.
+#[derive(Debug, Clone)]
+pub struct RateSnapshot {
+    pub id: u64,
+    pub created_at: OffsetDateTime,
+    pub rates: HashMap<(String, ListingCurrency), Rate>,
+}
+
+impl Default for RateSnapshot {
+    fn default() -> Self {
+        Self {
+            id: 0,
+            created_at: OffsetDateTime::UNIX_EPOCH,
+            rates: HashMap::new(),
+        }
+    }
+}
+
+impl RateSnapshot {
+    pub fn chaos_equivalent(&self, league: &str, currency: ListingCurrency) -> Option<f64> {
+        match currency {
+            ListingCurrency::CHAOS => Some(1.0),
+            c => self
+                .rates
+                .get(&(league.to_owned(), c))
+                .map(|r| r.chaos_equivalent),
+        }
+    }
+}
+
+/// CurrencyRates derives the chaos equivalent of each listing currency from the currency listed
+/// in public stashes.
+///
+/// Both sides of a trade are used: a Divine Orb listed for chaos and a Chaos Orb listed for a
+/// fraction of a divine are observations of the divine rate. Rates are robust medians of the
+/// observations younger than `max_age`. A currency without enough fresh observations keeps its
+/// previous rate until it is older than `stale_after`, after which it is dropped and listings in
+/// that currency are no longer normalized.
+pub struct CurrencyRates {
+    observations: HashMap<(String, ListingCurrency), VecDeque<(OffsetDateTime, f64)>>,
+    snapshot: RateSnapshot,
+    max_age: Duration,
+    stale_after: Duration,
+    min_observations: usize,
+    snapshot_interval: Duration,
+}
+
+impl CurrencyRates {
+    pub fn new(
+        max_age: Duration,
+        stale_after: Duration,
+        min_observations: usize,
+        snapshot_interval: Duration,
+    ) -> Self {
+        Self {
+            observations: HashMap::new(),
+            snapshot: RateSnapshot::default(),
+            max_age,
+            stale_after,
+            min_observations: min_observations.max(1),
+            snapshot_interval,
+        }
+    }
+
+    pub fn from_env() -> Self {
+        let seconds = |key: &str, default: i64| {
+            env::var(key)
+                .map(|v| {
+                    v.parse::<i64>()
+                        .unwrap_or_else(|_| panic!("{key} must be a number of seconds"))
+                })
+                .map(Duration::seconds)
+                .unwrap_or(Duration::seconds(default))
+        };
+
+        let min_observations = env::var("CURRENCY_RATE_MIN_OBSERVATIONS")
+            .map(|v| {
+                v.parse::<usize>()
+                    .expect("CURRENCY_RATE_MIN_OBSERVATIONS must be a number")
+            })
+            .unwrap_or(5);
+
+        Self::new(
+            seconds("CURRENCY_RATE_MAX_AGE_SECS", 6 * 60 * 60),
+            seconds("CURRENCY_RATE_STALE_SECS", 24 * 60 * 60),
+            min_observations,
+            seconds("CURRENCY_RATE_SNAPSHOT_SECS", 5 * 60),
+        )
+    }
+
+    /// Starts from a previously taken snapshot, so listings are normalized straight away after a restart
+    pub fn restore(&mut self, snapshot: RateSnapshot) {
+        self.snapshot = snapshot;
+    }
+
+    pub fn snapshot(&self) -> &RateSnapshot {
+        &self.snapshot
+    }
+
+    /// Records the rates implied by the priced currency in a set of stash changes
+    pub fn observe_stashes(&mut self, stashes: &[PublicStashChange], at: OffsetDateTime) {
+        for stash in stashes {
+            let stash_note = stash_price_note(stash);
+
+            for item in &stash.items {
+                let Some(league) = stash.league.as_ref().or(item.league.as_ref()) else {
+                    continue;
+                };
+                let Some(currency) = ListingCurrency::from_base_type(&item.base_type) else {
+                    continue;
+                };
+                let Ok(Some((price, _))) = listing_price(item.note.as_deref(), stash_note) else {
+                    continue;
+                };
+
+                if price.listed_price <= 0.0 || !price.listed_price.is_finite() {
+                    continue;
+                }
+
+                // prices of stackable currency are per unit
+                match (currency, price.listed_currency) {
+                    (ListingCurrency::CHAOS, ListingCurrency::CHAOS) => {}
+                    (ListingCurrency::CHAOS, listed) => {
+                        self.observe(league, listed, 1.0 / price.listed_price, at)
+                    }
+                    (c, ListingCurrency::CHAOS) => self.observe(league, c, price.listed_price, at),
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    pub fn observe(
+        &mut self,
+        league: &str,
+        currency: ListingCurrency,
+        chaos_equivalent: f64,
+        at: OffsetDateTime,
+    ) {
+        let observations = self
+            .observations
+            .entry((league.to_owned(), currency))
+            .or_default();
+
+        observations.push_back((at, chaos_equivalent));
+        while observations.len() > MAX_OBSERVATIONS {
+            observations.pop_front();
+        }
+    }
+
+    /// Takes a new snapshot when the snapshot interval has passed, returning it so it can be stored
+    pub fn refresh(&mut self, now: OffsetDateTime) -> Option<&RateSnapshot> {
+        if now - self.snapshot.created_at < self.snapshot_interval {
+            return None;
+        }
+
+        let oldest = now - self.max_age;
+        let mut rates = HashMap::new();
+
+        for (key, observations) in self.observations.iter_mut() {
+            while observations.front().is_some_and(|(at, _)| *at < oldest) {
+                observations.pop_front();
+            }
+
+            if observations.len() < self.min_observations {
+                continue;
+            }
+
+            let mut values = observations.iter().map(|(_, v)| *v).collect::<Vec<_>>();
+            if let Some(median) = robust_median(&mut values) {
+                let rate = Rate {
+                    chaos_equivalent: median,
+                    observations: observations.len(),
+                    updated_at: now,
+                };
+                rates.insert(key.clone(), rate);
+            }
+        }
+        self.observations.retain(|_, o| !o.is_empty());
+
+        for (key, rate) in &self.snapshot.rates {
+            if rates.contains_key(key) {
+                continue;
+            }
+
+            if now - rate.updated_at < self.stale_after {
+                rates.insert(key.clone(), rate.clone());
+            } else {
+                tracing::warn!(
+                    "dropping stale {} rate for league: {}, last updated at {}",
+                    key.1,
+                    key.0,
+                    rate.updated_at
+                );
+            }
+        }
+
+        let id = (now.unix_timestamp().max(0) as u64).max(self.snapshot.id + 1);
+        self.snapshot = RateSnapshot {
+            id,
+            created_at: now,
+            rates,
+        };
+
+        tracing::info!(
+            "took currency rate snapshot: {id} with {} rates",
+            self.snapshot.rates.len()
+        );
+
+        Some(&self.snapshot)
+    }
+
+    /// Fills in the chaos equivalent price of listings using the current snapshot
+    pub fn normalize(&self, listings: &mut [Listing]) {
+        for listing in listings {
+            let rate = self
+                .snapshot
+                .chaos_equivalent(&listing.league, listing.price.listed_currency);
+
+            listing.price.normalized_price = rate
+                .map(|r| listing.price.listed_price * r)
+                .unwrap_or_default();
+            listing.price.rate_snapshot_id = self.snapshot.id;
+        }
+    }
+}
+
+/// Median of the values after discarding outliers by their median absolute deviation
+fn robust_median(values: &mut [f64]) -> Option<f64> {
+    let median = median(values)?;
+
+    let mut deviations = values
+        .iter()
+        .map(|v| (v - median).abs())
+        .collect::<Vec<_>>();
+    let mad = self::median(&mut deviations)?;
+    if mad == 0.0 {
+        return Some(median);
+    }
+
+    let limit = OUTLIER_DEVIATIONS * MAD_SCALE * mad;
+    let mut inliers = values
+        .iter()
+        .copied()
+        .filter(|v| (v - median).abs() <= limit)
+        .collect::<Vec<_>>();
+
+    self::median(&mut inliers)
+}
+
+pub(crate) fn median(values: &mut [f64]) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+
+    values.sort_by(|a, b| a.total_cmp(b));
+    let mid = values.len() / 2;
+
+    match values.len().is_multiple_of(2) {
+        true => Some((values[mid - 1] + values[mid]) / 2.0),
+        false => Some(values[mid]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use poe_types::{item::Item, stash::PublicStashChange};
+    use time::{macros::datetime, Duration};
+
+    use super::{robust_median, CurrencyRates};
+    use crate::listing::{Listing, ListingCurrency};
+
+    fn currency(tag: &str) -> ListingCurrency {
+        ListingCurrency::from_tag(tag).unwrap()
+    }
+
+    fn rates() -> CurrencyRates {
+        CurrencyRates::new(
+            Duration::hours(6),
+            Duration::hours(24),
+            3,
+            Duration::minutes(5),
+        )
+    }
+
+    fn currency_stash(items: &[(&str, &str)]) -> PublicStashChange {
+        let items = items
+            .iter()
+            .map(|(base_type, note)| Item {
+                league: Some("Standard".to_owned()),
+                type_line: base_type.to_string(),
+                base_type: base_type.to_string(),
+                note: Some(note.to_string()),
+                ..Default::default()
+            })
+            .collect();
+
+        PublicStashChange {
+            league: Some("Standard".to_owned()),
+            items,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn robust_median_ignores_outliers() {
+        let mut values = [200.0, 201.0, 199.0, 202.0, 1.0, 198.0, 10000.0];
+        assert_eq!(robust_median(&mut values), Some(200.0));
+
+        let mut values = [5.0, 5.0, 5.0, 1000.0];
+        assert_eq!(robust_median(&mut values), Some(5.0));
+
+        assert_eq!(robust_median(&mut []), None);
+    }
+
+    #[test]
+    fn rates_from_both_sides_of_a_trade() {
+        let mut rates = rates();
+        let now = datetime!(2024-04-10 12:00 UTC);
+
+        rates.observe_stashes(
+            &[currency_stash(&[
+                ("Divine Orb", "~price 200 chaos"),
+                ("Divine Orb", "~b/o 190 chaos"),
+                ("Chaos Orb", "~price 1/210 divine"),
+                ("Orb of Alteration", "~price 50/500 chaos"),
+                ("Exalted Orb", "~price 1 divine"),
+            ])],
+            now,
+        );
+
+        let snapshot = rates.refresh(now).expect("first refresh takes a snapshot");
+        assert_eq!(
+            snapshot.chaos_equivalent("Standard", ListingCurrency::DIVINE),
+            Some(200.0)
+        );
+        assert_eq!(
+            snapshot.chaos_equivalent("Standard", ListingCurrency::CHAOS),
+            Some(1.0)
+        );
+        // too few observations for alterations, and exalts listed for divines aren't used
+        assert_eq!(snapshot.chaos_equivalent("Standard", currency("alt")), None);
+        assert_eq!(
+            snapshot.chaos_equivalent("Standard", currency("exalted")),
+            None
+        );
+        assert_eq!(
+            snapshot.chaos_equivalent("Affliction", ListingCurrency::DIVINE),
+            None
+        );
+    }
+
+    #[test]
+    fn snapshots_are_taken_on_an_interval() {
+        let mut rates = rates();
+        let now = datetime!(2024-04-10 12:00 UTC);
+
+        let first = rates.refresh(now).unwrap().id;
+        assert!(rates.refresh(now + Duration::minutes(1)).is_none());
+
+        let second = rates.refresh(now + Duration::minutes(5)).unwrap().id;
+        assert!(second > first);
+    }
+
+    #[test]
+    fn stale_rates_are_kept_then_dropped() {
+        let mut rates = rates();
+        let now = datetime!(2024-04-10 12:00 UTC);
+
+        for _ in 0..3 {
+            rates.observe("Standard", ListingCurrency::DIVINE, 150.0, now);
+        }
+        rates.refresh(now);
+
+        // observations have expired, the previous rate is still used
+        let later = now + Duration::hours(12);
+        let snapshot = rates.refresh(later).unwrap();
+        assert_eq!(
+            snapshot.chaos_equivalent("Standard", ListingCurrency::DIVINE),
+            Some(150.0)
+        );
+
+        let much_later = now + Duration::hours(25);
+        let snapshot = rates.refresh(much_later).unwrap();
+        assert_eq!(
+            snapshot.chaos_equivalent("Standard", ListingCurrency::DIVINE),
+            None
+        );
+    }
+
+    #[test]
+    fn listings_are_normalized_with_the_snapshot() {
+        let mut rates = rates();
+        let now = datetime!(2024-04-10 12:00 UTC);
+
+        for _ in 0..3 {
+            rates.observe("Standard", ListingCurrency::DIVINE, 150.0, now);
+        }
+        let snapshot_id = rates.refresh(now).unwrap().id;
+
+        let mut listings = vec![Listing::default(), Listing::default()];
+        for (listing, currency) in listings
+            .iter_mut()
+            .zip([ListingCurrency::DIVINE, currency("mirror-shard")])
+        {
+            listing.league = "Standard".to_owned();
+            listing.price.listed_price = 2.0;
+            listing.price.listed_currency = currency;
+        }
+
+        rates.normalize(&mut listings);
+
+        assert_eq!(listings[0].price.normalized_price, 300.0);
+        assert_eq!(listings[1].price.normalized_price, 0.0);
+        assert!(listings
+            .iter()
+            .all(|l| l.price.rate_snapshot_id == snapshot_id));
+    }
+}
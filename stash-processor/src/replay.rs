@@ -0,0 +1,282 @@
+use core::fmt;
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::BufReader,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use async_trait::async_trait;
+use poe_types::stash::PublicStashChange;
+use serde::Deserialize;
+use time::OffsetDateTime;
+
+use crate::{
+    lifecycle::MemoryStashStateStore, listing::Listing, pipeline::SkipReason,
+    processor::StashProcessor, sink::ListingSink,
+};
+
+/// A page of the river, as returned by the public stash API in a `PublicStashesResponse`
+#[derive(Deserialize)]
+pub struct RiverPage {
+    pub next_change_id: Option<String>,
+    pub stashes: Vec<PublicStashChange>,
+}
+
+/// Finds the files to replay, directories are searched recursively for `.json` pages and
+/// `.zst` river archive segments, in name order
+pub fn replay_files(paths: &[PathBuf]) -> anyhow::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+
+    for path in paths {
+        if path.is_dir() {
+            let mut entries = fs::read_dir(path)
+                .with_context(|| format!("failed reading {}", path.display()))?
+                .map(|e| e.map(|e| e.path()))
+                .collect::<Result<Vec<_>, _>>()?;
+            entries.sort();
+
+            let nested = entries
+                .into_iter()
+                .filter(|p| p.is_dir() || is_replayable(p))
+                .collect::<Vec<_>>();
+            files.extend(replay_files(&nested)?);
+        } else {
+            files.push(path.clone());
+        }
+    }
+
+    Ok(files)
+}
+
+fn is_replayable(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|e| e.to_str()),
+        Some("json" | "zst")
+    )
+}
+
+/// Reads the river pages in a file. A `.zst` archive segment is a series of compressed pages,
+/// anything else is read as a single page of json
+pub fn read_pages(path: &Path) -> anyhow::Result<Vec<RiverPage>> {
+    let file = BufReader::new(
+        File::open(path).with_context(|| format!("failed opening {}", path.display()))?,
+    );
+
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("zst") => {
+            // every page is its own zstd frame, which the decoder reads back to back
+            let decoder = zstd::stream::read::Decoder::new(file)?;
+
+            serde_json::Deserializer::from_reader(decoder)
+                .into_iter::<RiverPage>()
+                .collect::<Result<Vec<_>, _>>()
+                .with_context(|| format!("failed parsing pages in {}", path.display()))
+        }
+        _ => {
+            let page = serde_json::from_reader(file)
+                .with_context(|| format!("failed parsing page {}", path.display()))?;
+
+            Ok(vec![page])
+        }
+    }
+}
+
+/// ReplaySummary counts what happened to every item replayed
+#[derive(Debug, Default)]
+pub struct ReplaySummary {
+    pub files: usize,
+    pub pages: usize,
+    pub stashes: usize,
+    pub items: usize,
+    pub priced: usize,
+    pub outliers: usize,
+    pub duplicates: usize,
+    pub skipped: BTreeMap<SkipReason, usize>,
+    /// items which failed to convert into a listing, by error
+    pub failed: BTreeMap<String, usize>,
+}
+
+impl fmt::Display for ReplaySummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "replayed {} pages with {} stashes from {} files",
+            self.pages, self.stashes, self.files
+        )?;
+        writeln!(f, "items: {}", self.items)?;
+        writeln!(
+            f,
+            "priced: {} ({} outliers, {} duplicates)",
+            self.priced, self.outliers, self.duplicates
+        )?;
+
+        writeln!(f, "skipped: {}", self.skipped.values().sum::<usize>())?;
+        for (reason, count) in &self.skipped {
+            writeln!(f, "  {reason}: {count}")?;
+        }
+
+        writeln!(f, "failed: {}", self.failed.values().sum::<usize>())?;
+        for (error, count) in &self.failed {
+            writeln!(f, "  {error}: {count}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// DiscardSink drops everything written to it, for dry runs
+struct DiscardSink;
+
+#[async_trait]
+impl ListingSink for DiscardSink {
+    fn name(&self) -> &'static str {
+        "discard"
+    }
+
+    async fn write_listings(&self, _listings: &[Listing]) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+/// Replayer runs river pages through the same stages as the processor, without NATS.
+///
+/// Currency rates and stash state start empty and are built up from the replayed pages, and
+/// listings are timestamped when they are replayed as pages don't record when they were crawled.
+pub struct Replayer {
+    processor: StashProcessor<MemoryStashStateStore>,
+    pub summary: ReplaySummary,
+}
+
+impl Replayer {
+    pub fn from_env() -> Self {
+        Self {
+            processor: StashProcessor::from_env(MemoryStashStateStore::default()),
+            summary: ReplaySummary::default(),
+        }
+    }
+
+    /// Extracts the listings in a page, writing them to the sink unless there is none
+    pub async fn replay(
+        &mut self,
+        page: RiverPage,
+        sink: Option<&dyn ListingSink>,
+    ) -> anyhow::Result<()> {
+        let now = OffsetDateTime::now_utc();
+
+        self.summary.pages += 1;
+        self.summary.stashes += page.stashes.len();
+        self.summary.items += page.stashes.iter().map(|s| s.items.len()).sum::<usize>();
+
+        let processed = self.processor.process(page.stashes, now).await?;
+        let extracted = &processed.extracted;
+
+        for (reason, count) in &extracted.skipped {
+            *self.summary.skipped.entry(*reason).or_default() += count;
+        }
+        for failed in &extracted.failed_items {
+            *self.summary.failed.entry(failed.error.clone()).or_default() += 1;
+        }
+        self.summary.outliers += processed.outliers;
+        self.summary.duplicates += processed.duplicates;
+        self.summary.priced += extracted.listings.len();
+
+        // a dry run still saves stash states, so later pages are diffed against earlier ones
+        let sink = sink.unwrap_or(&DiscardSink);
+        self.processor
+            .write(sink, &extracted.listings, &processed.events)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use poe_types::{
+        item::{FrameType, Item},
+        stash::PublicStashChange,
+    };
+
+    use super::{read_pages, replay_files, Replayer, RiverPage};
+    use crate::{
+        pipeline::SkipReason,
+        sink::{ListingSink, MemorySink},
+    };
+
+    fn page() -> RiverPage {
+        let item = |id: &str, note: Option<&str>, frame_type| Item {
+            id: Some(id.to_owned()),
+            name: "Mageblood".to_owned(),
+            base_type: "Heavy Belt".to_owned(),
+            frame_type: Some(frame_type),
+            note: note.map(|n| n.to_owned()),
+            ..Default::default()
+        };
+
+        RiverPage {
+            next_change_id: None,
+            stashes: vec![PublicStashChange {
+                id: "stash".to_owned(),
+                league: Some("Standard".to_owned()),
+                items: vec![
+                    item("a", Some("~price 200 chaos"), FrameType::Unique),
+                    item("b", Some("~b/o 3 divine"), FrameType::Unique),
+                    item("c", None, FrameType::Unique),
+                    item("d", Some("~price 1 chaos"), FrameType::Rare),
+                ],
+                ..Default::default()
+            }],
+        }
+    }
+
+    #[tokio::test]
+    async fn replay_summarizes_items() {
+        let mut replayer = Replayer::from_env();
+        let sink = MemorySink::default();
+
+        replayer
+            .replay(page(), Some(&sink as &dyn ListingSink))
+            .await
+            .unwrap();
+        // replaying the same page again only finds duplicates
+        replayer.replay(page(), None).await.unwrap();
+
+        let summary = &replayer.summary;
+        assert_eq!(summary.pages, 2);
+        assert_eq!(summary.items, 8);
+        assert_eq!(summary.priced, 2);
+        assert_eq!(summary.duplicates, 2);
+        assert_eq!(summary.skipped[&SkipReason::Unpriced], 2);
+        assert_eq!(summary.skipped[&SkipReason::Untradable], 2);
+        assert_eq!(summary.failed.values().sum::<usize>(), 0);
+        assert_eq!(sink.listings().len(), 2);
+        assert_eq!(sink.events().len(), 2);
+    }
+
+    #[test]
+    fn reads_archive_segments() {
+        let dir = env::temp_dir().join(format!("replay-{}", std::process::id()));
+        fs::create_dir_all(dir.join("nested")).unwrap();
+
+        let json = |id: &str| format!(r#"{{"next_change_id": "{id}", "stashes": []}}"#);
+        let mut segment = zstd::encode_all(json("1").as_bytes(), 3).unwrap();
+        segment.extend(zstd::encode_all(json("2").as_bytes(), 3).unwrap());
+
+        fs::write(dir.join("nested/river-1.zst"), segment).unwrap();
+        fs::write(dir.join("nested/river-1.idx"), "").unwrap();
+        fs::write(dir.join("page.json"), json("3")).unwrap();
+
+        let files = replay_files(std::slice::from_ref(&dir)).unwrap();
+        let change_ids = files
+            .iter()
+            .flat_map(|f| read_pages(f).unwrap())
+            .map(|p| p.next_change_id.unwrap())
+            .collect::<Vec<_>>();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(files.len(), 2);
+        assert_eq!(change_ids, vec!["1", "2", "3"]);
+    }
+}
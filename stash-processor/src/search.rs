@@ -1,24 +1,108 @@
-use std::env;
+use std::{
+    collections::{BTreeSet, HashMap},
+    env,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
+use anyhow::Context;
+use meilisearch_sdk::{errors::ErrorCode, settings::Settings, task_info::TaskInfo};
 use serde::{Deserialize, Serialize};
 
-use crate::listing::Listing;
+use crate::{db::ItemSummaryChRow, listing::Listing};
 
+/// Index the documents of every listed item are written to
+pub const ITEMS_INDEX: &str = "items";
+
+/// Abbreviations the community uses for items, searching for one finds the item
+const SYNONYMS: &[(&str, &[&str])] = &[
+    ("hh", &["headhunter"]),
+    ("mb", &["mageblood"]),
+    ("ie", &["impossible escape"]),
+    ("toh", &["thread of hope"]),
+    ("ff", &["forbidden flame"]),
+    ("ashes", &["ashes of the stars"]),
+    ("watchers", &["watcher's eye"]),
+    ("div", &["divine orb"]),
+    ("ex", &["exalted orb"]),
+    ("gcp", &["gemcutter's prism"]),
+    ("alch", &["orb of alchemy"]),
+];
+
+/// How long to wait for the tasks of a reindex to complete
+const REINDEX_TIMEOUT: Duration = Duration::from_secs(600);
+
+#[derive(Clone)]
 pub struct MeilisearchHandler {
     client: meilisearch_sdk::Client,
+    /// documents indexed by this process, so counts can be updated without reading them back
+    documents: Arc<Mutex<HashMap<String, ItemDocument>>>,
 }
 
-#[derive(Serialize, Deserialize)]
-pub struct UniqueListingDocument {
+/// ItemDocument is what search knows about an item, built up from every listing of it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ItemDocument {
     pub id: String,
     pub name: String,
+    pub base_type: String,
+    pub category: String,
+    pub icon: String,
+    pub variants: BTreeSet<String>,
+    pub leagues: BTreeSet<String>,
+    /// unix timestamps of the first and last listing of the item
+    pub first_seen: i64,
+    pub last_seen: i64,
+    pub listing_count: u64,
+}
+
+impl ItemDocument {
+    pub fn new(listing: &Listing) -> Self {
+        let seen = listing.created_at.unix_timestamp();
+
+        Self {
+            id: name_to_id(&listing.name),
+            name: listing.name.clone(),
+            base_type: listing.base_type.clone(),
+            category: listing.category.to_string(),
+            icon: listing.icon.clone(),
+            variants: BTreeSet::new(),
+            leagues: BTreeSet::new(),
+            first_seen: seen,
+            last_seen: seen,
+            listing_count: 0,
+        }
+    }
+
+    /// Adds a listing of the item to the document
+    pub fn observe(&mut self, listing: &Listing) {
+        let seen = listing.created_at.unix_timestamp();
+
+        if !listing.icon.is_empty() && seen >= self.last_seen {
+            self.icon = listing.icon.clone();
+        }
+        if !listing.variant.is_empty() {
+            self.variants.insert(listing.variant.clone());
+        }
+        self.leagues.insert(listing.league.clone());
+        self.first_seen = self.first_seen.min(seen);
+        self.last_seen = self.last_seen.max(seen);
+        self.listing_count += 1;
+    }
 }
 
-impl UniqueListingDocument {
-    pub fn new(name: &str) -> Self {
+impl From<ItemSummaryChRow> for ItemDocument {
+    fn from(row: ItemSummaryChRow) -> Self {
         Self {
-            id: name_to_id(name),
-            name: name.to_owned(),
+            id: name_to_id(&row.name),
+            name: row.name,
+            base_type: row.base_type,
+            category: row.category,
+            icon: row.icon,
+            variants: row.variants.into_iter().collect(),
+            leagues: row.leagues.into_iter().collect(),
+            first_seen: row.first_seen.unix_timestamp(),
+            last_seen: row.last_seen.unix_timestamp(),
+            listing_count: row.listing_count,
         }
     }
 }
@@ -39,24 +123,138 @@ impl MeilisearchHandler {
 
         tracing::info!("connected to meilisearch!");
 
-        Self { client }
+        let handler = Self {
+            client,
+            documents: Default::default(),
+        };
+        // search is best effort, so keep going with whatever settings the index has
+        if let Err(e) = handler.apply_settings().await {
+            tracing::error!("failed applying meilisearch index settings: {e}");
+        }
+
+        handler
     }
 
-    pub async fn add_document_batch(
+    pub async fn ping(&self) -> anyhow::Result<()> {
+        self.client.health().await?;
+
+        Ok(())
+    }
+
+    /// Sets which attributes of item documents can be searched, filtered and sorted by
+    pub async fn apply_settings(&self) -> anyhow::Result<TaskInfo> {
+        let synonyms = SYNONYMS
+            .iter()
+            .map(|(abbreviation, names)| (*abbreviation, names.to_vec()))
+            .collect::<HashMap<_, _>>();
+
+        let settings = Settings::new()
+            .with_searchable_attributes(["name", "base_type", "variants"])
+            .with_filterable_attributes(["category", "base_type", "variants", "leagues"])
+            .with_sortable_attributes(["listing_count", "first_seen", "last_seen"])
+            .with_synonyms(synonyms);
+
+        let task = self
+            .client
+            .index(ITEMS_INDEX)
+            .set_settings(&settings)
+            .await?;
+
+        Ok(task)
+    }
+
+    /// Adds a batch of listings to the documents of their items.
+    ///
+    /// Documents this process hasn't written yet are read back from the index first, so counts
+    /// carry on across restarts. Listings of a batch which fails to index may be counted again
+    /// when it is retried, `stash-processor reindex` rebuilds exact documents.
+    pub async fn index_listings<'a>(
         &self,
-        index: &str,
-        listings: &Vec<Listing>,
+        listings: impl IntoIterator<Item = &'a Listing>,
     ) -> anyhow::Result<()> {
-        let iox = self.client.index(index);
+        let index = self.client.index(ITEMS_INDEX);
 
-        let mut docs = Vec::new();
+        let mut by_id: HashMap<String, Vec<&Listing>> = HashMap::new();
         for l in listings {
-            let doc = UniqueListingDocument::new(&l.name);
+            by_id.entry(name_to_id(&l.name)).or_default().push(l);
+        }
+        if by_id.is_empty() {
+            return Ok(());
+        }
+
+        let missing = {
+            let documents = self.documents.lock().unwrap();
+            by_id
+                .keys()
+                .filter(|id| !documents.contains_key(*id))
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+        let fetched = futures::future::join_all(missing.iter().map(|id| async {
+            match index.get_document::<ItemDocument>(id).await {
+                Ok(doc) => Ok(Some(doc)),
+                Err(meilisearch_sdk::errors::Error::Meilisearch(e))
+                    if e.error_code == ErrorCode::DocumentNotFound =>
+                {
+                    Ok(None)
+                }
+                Err(e) => Err(e),
+            }
+        }))
+        .await;
 
-            docs.push(doc);
+        let docs = {
+            let mut documents = self.documents.lock().unwrap();
+            for doc in fetched {
+                if let Some(doc) = doc? {
+                    documents.entry(doc.id.clone()).or_insert(doc);
+                }
+            }
+
+            by_id
+                .into_iter()
+                .map(|(id, listings)| {
+                    let doc = documents
+                        .entry(id)
+                        .or_insert_with(|| ItemDocument::new(listings[0]));
+                    for l in listings {
+                        doc.observe(l);
+                    }
+
+                    doc.clone()
+                })
+                .collect::<Vec<_>>()
+        };
+
+        index.add_or_replace(&docs, Some("id")).await?;
+
+        Ok(())
+    }
+
+    /// Replaces every document in the index, applying the index settings first
+    pub async fn reindex(&self, docs: Vec<ItemDocument>) -> anyhow::Result<()> {
+        let index = self.client.index(ITEMS_INDEX);
+
+        let mut tasks = vec![
+            self.apply_settings().await?,
+            index.delete_all_documents().await?,
+        ];
+        tasks.extend(
+            index
+                .add_documents_in_batches(&docs, None, Some("id"))
+                .await?,
+        );
+
+        for task in tasks {
+            let task = task
+                .wait_for_completion(&self.client, None, Some(REINDEX_TIMEOUT))
+                .await?;
+            if task.is_failure() {
+                return Err(task.unwrap_failure()).context("failed reindexing items");
+            }
         }
 
-        iox.add_or_update(&docs, Some("id")).await?;
+        *self.documents.lock().unwrap() = docs.into_iter().map(|d| (d.id.clone(), d)).collect();
 
         Ok(())
     }
@@ -70,7 +268,54 @@ fn name_to_id(name: &str) -> String {
 
 #[cfg(test)]
 mod tests {
-    use crate::search::name_to_id;
+    use time::macros::datetime;
+
+    use super::ItemDocument;
+    use crate::{category::ItemCategory, listing::Listing, search::name_to_id};
+
+    #[test]
+    fn documents_collect_listings() {
+        let listing = |league: &str, variant: &str, created_at| Listing {
+            name: "Shavronne's Wrappings".to_owned(),
+            base_type: "Occultist's Vestment".to_owned(),
+            category: ItemCategory::Unique,
+            variant: variant.to_owned(),
+            league: league.to_owned(),
+            created_at,
+            ..Default::default()
+        };
+
+        let first = listing("Standard", "", datetime!(2024-04-10 12:00 UTC));
+        let mut doc = ItemDocument::new(&first);
+        doc.observe(&first);
+        doc.observe(&listing(
+            "Necropolis",
+            "6 links",
+            datetime!(2024-04-12 12:00 UTC),
+        ));
+        doc.observe(&listing(
+            "Standard",
+            "6 links",
+            datetime!(2024-04-11 12:00 UTC),
+        ));
+
+        assert_eq!(doc.id, "shavronneswrappings");
+        assert_eq!(doc.category, "unique");
+        assert_eq!(doc.listing_count, 3);
+        assert_eq!(doc.variants.iter().collect::<Vec<_>>(), ["6 links"]);
+        assert_eq!(
+            doc.leagues.iter().collect::<Vec<_>>(),
+            ["Necropolis", "Standard"]
+        );
+        assert_eq!(
+            doc.first_seen,
+            datetime!(2024-04-10 12:00 UTC).unix_timestamp()
+        );
+        assert_eq!(
+            doc.last_seen,
+            datetime!(2024-04-12 12:00 UTC).unix_timestamp()
+        );
+    }
 
     #[test]
     fn simple_name() {
@@ -0,0 +1,19 @@
+use async_trait::async_trait;
+
+use super::ListingSink;
+use crate::{db::ClickhouseDatabase, lifecycle::ListingEvent, listing::Listing};
+
+#[async_trait]
+impl ListingSink for ClickhouseDatabase {
+    fn name(&self) -> &'static str {
+        "clickhouse"
+    }
+
+    async fn write_listings(&self, listings: &[Listing]) -> anyhow::Result<()> {
+        self.create_batch(listings).await
+    }
+
+    async fn write_events(&self, events: &[ListingEvent]) -> anyhow::Result<()> {
+        self.insert_events(events).await
+    }
+}
@@ -0,0 +1,21 @@
+use async_trait::async_trait;
+
+use super::ListingSink;
+use crate::{listing::Listing, search::MeilisearchHandler};
+
+/// Listings of every category are indexed for search, as one document per item
+#[async_trait]
+impl ListingSink for MeilisearchHandler {
+    fn name(&self) -> &'static str {
+        "meilisearch"
+    }
+
+    async fn write_listings(&self, listings: &[Listing]) -> anyhow::Result<()> {
+        self.index_listings(listings).await
+    }
+
+    /// search is best effort, listings are still acked when indexing fails
+    fn required(&self) -> bool {
+        false
+    }
+}
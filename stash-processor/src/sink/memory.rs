@@ -0,0 +1,45 @@
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+
+use super::ListingSink;
+use crate::{lifecycle::ListingEvent, listing::Listing};
+
+/// MemorySink keeps everything written to it, for testing the processor without any storage
+#[derive(Default)]
+pub struct MemorySink {
+    listings: Mutex<Vec<Listing>>,
+    events: Mutex<Vec<ListingEvent>>,
+}
+
+impl MemorySink {
+    pub fn listings(&self) -> Vec<Listing> {
+        self.listings.lock().unwrap().clone()
+    }
+
+    pub fn events(&self) -> Vec<ListingEvent> {
+        self.events.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl ListingSink for MemorySink {
+    fn name(&self) -> &'static str {
+        "memory"
+    }
+
+    async fn write_listings(&self, listings: &[Listing]) -> anyhow::Result<()> {
+        self.listings
+            .lock()
+            .unwrap()
+            .extend(listings.iter().cloned());
+
+        Ok(())
+    }
+
+    async fn write_events(&self, events: &[ListingEvent]) -> anyhow::Result<()> {
+        self.events.lock().unwrap().extend_from_slice(events);
+
+        Ok(())
+    }
+}
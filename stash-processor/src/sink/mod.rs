@@ -0,0 +1,195 @@
+use std::{env, path::PathBuf, sync::Arc};
+
+use async_trait::async_trait;
+
+use crate::{
+    db::ClickhouseDatabase, lifecycle::ListingEvent, listing::Listing, search::MeilisearchHandler,
+};
+
+mod clickhouse;
+mod meilisearch;
+mod memory;
+mod ndjson;
+mod parquet;
+
+pub use self::{memory::MemorySink, ndjson::NdjsonSink, parquet::ParquetSink};
+
+/// ListingSink is a destination listings are written to once extracted
+#[async_trait]
+pub trait ListingSink: Send + Sync {
+    /// Name of the sink, as used in `LISTING_SINKS`
+    fn name(&self) -> &'static str;
+
+    async fn write_listings(&self, listings: &[Listing]) -> anyhow::Result<()>;
+
+    async fn write_events(&self, _events: &[ListingEvent]) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Whether listings must be written to this sink before their messages are acked. Failures
+    /// of sinks which aren't required are only logged
+    fn required(&self) -> bool {
+        true
+    }
+}
+
+/// Sinks writes to every configured sink in turn.
+///
+/// When a required sink fails the write fails, even if earlier sinks succeeded, so a retried
+/// batch can be written to those sinks twice.
+#[derive(Default, Clone)]
+pub struct Sinks {
+    sinks: Vec<Arc<dyn ListingSink>>,
+    /// the stores among the sinks, so they are only connected to once
+    clickhouse: Option<ClickhouseDatabase>,
+    search: Option<MeilisearchHandler>,
+}
+
+impl Sinks {
+    pub fn new(sinks: Vec<Arc<dyn ListingSink>>) -> Self {
+        Self {
+            sinks,
+            ..Default::default()
+        }
+    }
+
+    /// Builds the sinks named in `LISTING_SINKS`, a comma separated list of `clickhouse`,
+    /// `meilisearch`, `ndjson` and `parquet`, defaulting to `clickhouse,meilisearch`. Only the
+    /// stores named are connected to. File sinks write to `NDJSON_SINK_DIR` and
+    /// `PARQUET_SINK_DIR`
+    pub async fn from_env() -> anyhow::Result<Self> {
+        let names = env::var("LISTING_SINKS").unwrap_or("clickhouse,meilisearch".to_owned());
+        let dir =
+            |key: &str, default: &str| PathBuf::from(env::var(key).unwrap_or(default.to_owned()));
+
+        let mut sinks = Self::default();
+        for name in names.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+            let sink: Arc<dyn ListingSink> = match name {
+                "clickhouse" => {
+                    let db = ClickhouseDatabase::new().await;
+                    sinks.clickhouse = Some(db.clone());
+                    Arc::new(db)
+                }
+                "meilisearch" => {
+                    let search = MeilisearchHandler::new().await;
+                    sinks.search = Some(search.clone());
+                    Arc::new(search)
+                }
+                "ndjson" => Arc::new(NdjsonSink::new(dir("NDJSON_SINK_DIR", "listings"))?),
+                "parquet" => Arc::new(ParquetSink::new(dir("PARQUET_SINK_DIR", "listings"))?),
+                other => anyhow::bail!(
+                    "unknown listing sink: {other}, expected clickhouse, meilisearch, ndjson or parquet"
+                ),
+            };
+            sinks.sinks.push(sink);
+        }
+
+        if !sinks.sinks.iter().any(|s| s.required()) {
+            anyhow::bail!("LISTING_SINKS must include a sink other than meilisearch");
+        }
+
+        tracing::info!(
+            "writing listings to: {}",
+            sinks
+                .sinks
+                .iter()
+                .map(|s| s.name())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+
+        Ok(sinks)
+    }
+
+    /// The Clickhouse database listings are written to, when it is one of the sinks
+    pub fn clickhouse(&self) -> Option<&ClickhouseDatabase> {
+        self.clickhouse.as_ref()
+    }
+
+    /// The search index listings are written to, when it is one of the sinks
+    pub fn search(&self) -> Option<&MeilisearchHandler> {
+        self.search.as_ref()
+    }
+}
+
+#[async_trait]
+impl ListingSink for Sinks {
+    fn name(&self) -> &'static str {
+        "sinks"
+    }
+
+    async fn write_listings(&self, listings: &[Listing]) -> anyhow::Result<()> {
+        for sink in &self.sinks {
+            if let Err(e) = sink.write_listings(listings).await {
+                if sink.required() {
+                    return Err(e.context(format!("failed writing listings to {}", sink.name())));
+                }
+
+                tracing::error!("failed writing listings to {}: {e}", sink.name());
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn write_events(&self, events: &[ListingEvent]) -> anyhow::Result<()> {
+        for sink in &self.sinks {
+            if let Err(e) = sink.write_events(events).await {
+                if sink.required() {
+                    return Err(e.context(format!("failed writing events to {}", sink.name())));
+                }
+
+                tracing::error!("failed writing events to {}: {e}", sink.name());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use async_trait::async_trait;
+
+    use super::{ListingSink, MemorySink, Sinks};
+    use crate::listing::Listing;
+
+    struct FailingSink {
+        required: bool,
+    }
+
+    #[async_trait]
+    impl ListingSink for FailingSink {
+        fn name(&self) -> &'static str {
+            "failing"
+        }
+
+        async fn write_listings(&self, _listings: &[Listing]) -> anyhow::Result<()> {
+            anyhow::bail!("sink is down")
+        }
+
+        fn required(&self) -> bool {
+            self.required
+        }
+    }
+
+    #[tokio::test]
+    async fn optional_sinks_do_not_fail_writes() {
+        let memory = Arc::new(MemorySink::default());
+        let sinks = Sinks::new(vec![
+            Arc::new(FailingSink { required: false }),
+            memory.clone(),
+        ]);
+
+        sinks.write_listings(&[Listing::default()]).await.unwrap();
+        assert_eq!(memory.listings().len(), 1);
+
+        let sinks = Sinks::new(vec![
+            memory.clone(),
+            Arc::new(FailingSink { required: true }),
+        ]);
+        assert!(sinks.write_listings(&[Listing::default()]).await.is_err());
+    }
+}
@@ -0,0 +1,125 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use async_trait::async_trait;
+use serde::Serialize;
+use time::{macros::format_description, OffsetDateTime};
+use tokio::{fs::OpenOptions, io::AsyncWriteExt, sync::Mutex};
+
+use super::ListingSink;
+use crate::{
+    db::{ListingChRow, ListingEventChRow},
+    lifecycle::ListingEvent,
+    listing::Listing,
+};
+
+/// NdjsonSink appends listings and events as newline delimited json, with the same fields as
+/// their Clickhouse rows, to one file per kind and day in a directory
+pub struct NdjsonSink {
+    dir: PathBuf,
+    /// serializes appends, so lines of concurrent writes don't interleave
+    lock: Mutex<()>,
+}
+
+impl NdjsonSink {
+    pub fn new(dir: PathBuf) -> anyhow::Result<Self> {
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("failed creating ndjson sink dir: {}", dir.display()))?;
+
+        Ok(Self {
+            dir,
+            lock: Mutex::new(()),
+        })
+    }
+
+    async fn append<T: Serialize>(
+        &self,
+        kind: &str,
+        rows: impl Iterator<Item = T>,
+    ) -> anyhow::Result<()> {
+        let mut buf = Vec::new();
+        for row in rows {
+            serde_json::to_writer(&mut buf, &row)?;
+            buf.push(b'\n');
+        }
+        if buf.is_empty() {
+            return Ok(());
+        }
+
+        let _guard = self.lock.lock().await;
+        let path = daily_file(&self.dir, kind, OffsetDateTime::now_utc());
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .await
+            .with_context(|| format!("failed opening {}", path.display()))?;
+
+        file.write_all(&buf).await?;
+        file.flush().await?;
+
+        Ok(())
+    }
+}
+
+fn daily_file(dir: &Path, kind: &str, now: OffsetDateTime) -> PathBuf {
+    let date = now
+        .format(format_description!("[year]-[month]-[day]"))
+        .expect("dates must format");
+
+    dir.join(format!("{kind}-{date}.ndjson"))
+}
+
+#[async_trait]
+impl ListingSink for NdjsonSink {
+    fn name(&self) -> &'static str {
+        "ndjson"
+    }
+
+    async fn write_listings(&self, listings: &[Listing]) -> anyhow::Result<()> {
+        self.append("listings", listings.iter().map(ListingChRow::from))
+            .await
+    }
+
+    async fn write_events(&self, events: &[ListingEvent]) -> anyhow::Result<()> {
+        self.append("listing_events", events.iter().map(ListingEventChRow::from))
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use time::macros::datetime;
+
+    use super::{daily_file, NdjsonSink};
+    use crate::{listing::Listing, sink::ListingSink};
+
+    #[tokio::test]
+    async fn appends_lines() {
+        let dir = env::temp_dir().join(format!("ndjson-sink-{}", std::process::id()));
+        let sink = NdjsonSink::new(dir.clone()).unwrap();
+
+        let listing = Listing {
+            name: "Mageblood".to_owned(),
+            ..Default::default()
+        };
+        sink.write_listings(&[listing]).await.unwrap();
+        sink.write_listings(&[Listing::default()]).await.unwrap();
+
+        let path = daily_file(&dir, "listings", time::OffsetDateTime::now_utc());
+        let written = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let lines = written.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        let row: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(row["name"], "Mageblood");
+
+        assert!(
+            daily_file(&dir, "listings", datetime!(2024-04-10 12:00 UTC))
+                .ends_with("listings-2024-04-10.ndjson")
+        );
+    }
+}
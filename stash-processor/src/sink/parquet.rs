@@ -0,0 +1,303 @@
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::Context;
+use async_trait::async_trait;
+use parquet::{
+    basic::{Compression, ZstdLevel},
+    data_type::{BoolType, ByteArray, ByteArrayType, DoubleType, Int32Type, Int64Type},
+    file::{properties::WriterProperties, writer::SerializedFileWriter},
+    schema::parser::parse_message_type,
+};
+use time::OffsetDateTime;
+
+use super::ListingSink;
+use crate::{db::ListingChRow, listing::Listing};
+
+/// Columns of the listing files, in the order they are written
+const LISTING_SCHEMA: &str = "
+message listing {
+    REQUIRED BYTE_ARRAY item_id (STRING);
+    REQUIRED BYTE_ARRAY stash_id (STRING);
+    REQUIRED BYTE_ARRAY account_name (STRING);
+    REQUIRED BYTE_ARRAY name (STRING);
+    REQUIRED BYTE_ARRAY base_type (STRING);
+    REQUIRED BYTE_ARRAY category (STRING);
+    REQUIRED BYTE_ARRAY variant (STRING);
+    REQUIRED BYTE_ARRAY icon (STRING);
+    REQUIRED INT32 stack_size (INTEGER(32, false));
+    REQUIRED BYTE_ARRAY league (STRING);
+    REQUIRED DOUBLE normalized_price;
+    REQUIRED DOUBLE listed_price;
+    REQUIRED BYTE_ARRAY listed_currency (STRING);
+    REQUIRED DOUBLE listed_amount;
+    REQUIRED DOUBLE listed_units;
+    REPEATED BYTE_ARRAY implicit_mods (STRING);
+    REPEATED BYTE_ARRAY explicit_mods (STRING);
+    REPEATED BYTE_ARRAY mod_kinds (STRING);
+    REPEATED BYTE_ARRAY mod_stats (STRING);
+    REPEATED group mod_values {
+        REPEATED DOUBLE value;
+    }
+    REQUIRED INT64 created_at (TIMESTAMP(MILLIS, true));
+    REQUIRED INT64 rate_snapshot_id (INTEGER(64, false));
+    REQUIRED BYTE_ARRAY price_source (STRING);
+    REQUIRED DOUBLE outlier_score;
+    REQUIRED BOOLEAN is_outlier;
+}
+";
+
+/// ParquetSink writes every batch of listings to a new zstd compressed parquet file in a
+/// directory, for loading into analytics tools. Listing events aren't written
+pub struct ParquetSink {
+    dir: PathBuf,
+}
+
+impl ParquetSink {
+    pub fn new(dir: PathBuf) -> anyhow::Result<Self> {
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("failed creating parquet sink dir: {}", dir.display()))?;
+
+        Ok(Self { dir })
+    }
+}
+
+#[async_trait]
+impl ListingSink for ParquetSink {
+    fn name(&self) -> &'static str {
+        "parquet"
+    }
+
+    async fn write_listings(&self, listings: &[Listing]) -> anyhow::Result<()> {
+        if listings.is_empty() {
+            return Ok(());
+        }
+
+        let rows = listings.iter().map(ListingChRow::from).collect::<Vec<_>>();
+        let now = OffsetDateTime::now_utc().unix_timestamp_nanos();
+        let path = self.dir.join(format!("listings-{now}.parquet"));
+
+        tokio::task::spawn_blocking(move || write_listings(&path, &rows)).await?
+    }
+}
+
+enum Column {
+    Strings(Vec<ByteArray>),
+    Int32s(Vec<i32>),
+    Int64s(Vec<i64>),
+    Doubles(Vec<f64>),
+    Bools(Vec<bool>),
+    StringLists(Vec<Vec<String>>),
+    DoubleListLists(Vec<Vec<Vec<f64>>>),
+}
+
+fn columns(rows: &[ListingChRow]) -> Vec<Column> {
+    let strings = |f: fn(&ListingChRow) -> &str| {
+        Column::Strings(rows.iter().map(|r| ByteArray::from(f(r))).collect())
+    };
+    let doubles = |f: fn(&ListingChRow) -> f64| Column::Doubles(rows.iter().map(f).collect());
+
+    vec![
+        strings(|r| &r.item_id),
+        strings(|r| &r.stash_id),
+        strings(|r| &r.account_name),
+        strings(|r| &r.name),
+        strings(|r| &r.base_type),
+        strings(|r| &r.category),
+        strings(|r| &r.variant),
+        strings(|r| &r.icon),
+        Column::Int32s(rows.iter().map(|r| r.stack_size as i32).collect()),
+        strings(|r| &r.league),
+        doubles(|r| r.normalized_price),
+        doubles(|r| r.listed_price),
+        strings(|r| &r.listed_currency),
+        doubles(|r| r.listed_amount),
+        doubles(|r| r.listed_units),
+        Column::StringLists(rows.iter().map(|r| r.implicit_mods.clone()).collect()),
+        Column::StringLists(rows.iter().map(|r| r.explicit_mods.clone()).collect()),
+        Column::StringLists(rows.iter().map(|r| r.mod_kinds.clone()).collect()),
+        Column::StringLists(rows.iter().map(|r| r.mod_stats.clone()).collect()),
+        Column::DoubleListLists(rows.iter().map(|r| r.mod_values.clone()).collect()),
+        Column::Int64s(
+            rows.iter()
+                .map(|r| (r.created_at.unix_timestamp_nanos() / 1_000_000) as i64)
+                .collect(),
+        ),
+        Column::Int64s(rows.iter().map(|r| r.rate_snapshot_id as i64).collect()),
+        strings(|r| &r.price_source),
+        doubles(|r| r.outlier_score),
+        Column::Bools(rows.iter().map(|r| r.is_outlier).collect()),
+    ]
+}
+
+/// Writes the rows to a temporary file first, so readers never see a partially written file
+fn write_listings(path: &Path, rows: &[ListingChRow]) -> anyhow::Result<()> {
+    let tmp_path = path.with_extension("parquet.tmp");
+
+    let schema = Arc::new(parse_message_type(LISTING_SCHEMA)?);
+    let props = Arc::new(
+        WriterProperties::builder()
+            .set_compression(Compression::ZSTD(ZstdLevel::default()))
+            .build(),
+    );
+    let mut writer = SerializedFileWriter::new(File::create(&tmp_path)?, schema, props)?;
+    let mut row_group = writer.next_row_group()?;
+
+    let mut columns = columns(rows).into_iter();
+    while let Some(mut writer) = row_group.next_column()? {
+        let column = columns
+            .next()
+            .context("schema has more columns than are written")?;
+
+        match column {
+            Column::Strings(v) => writer
+                .typed::<ByteArrayType>()
+                .write_batch(&v, None, None)?,
+            Column::Int32s(v) => writer.typed::<Int32Type>().write_batch(&v, None, None)?,
+            Column::Int64s(v) => writer.typed::<Int64Type>().write_batch(&v, None, None)?,
+            Column::Doubles(v) => writer.typed::<DoubleType>().write_batch(&v, None, None)?,
+            Column::Bools(v) => writer.typed::<BoolType>().write_batch(&v, None, None)?,
+            Column::StringLists(lists) => {
+                let (values, def_levels, rep_levels) = list_levels(&lists);
+                writer.typed::<ByteArrayType>().write_batch(
+                    &values,
+                    Some(&def_levels),
+                    Some(&rep_levels),
+                )?
+            }
+            Column::DoubleListLists(lists) => {
+                let (values, def_levels, rep_levels) = nested_list_levels(&lists);
+                writer.typed::<DoubleType>().write_batch(
+                    &values,
+                    Some(&def_levels),
+                    Some(&rep_levels),
+                )?
+            }
+        };
+
+        writer.close()?;
+    }
+
+    row_group.close()?;
+    writer.close()?;
+    std::fs::rename(&tmp_path, path)?;
+
+    Ok(())
+}
+
+/// Flattens a repeated column into its values along with their definition and repetition
+/// levels, an empty list is a single undefined entry
+fn list_levels(lists: &[Vec<String>]) -> (Vec<ByteArray>, Vec<i16>, Vec<i16>) {
+    let mut values = Vec::new();
+    let mut def_levels = Vec::new();
+    let mut rep_levels = Vec::new();
+
+    for list in lists {
+        if list.is_empty() {
+            def_levels.push(0);
+            rep_levels.push(0);
+            continue;
+        }
+
+        for (i, value) in list.iter().enumerate() {
+            values.push(ByteArray::from(value.as_str()));
+            def_levels.push(1);
+            rep_levels.push(if i == 0 { 0 } else { 1 });
+        }
+    }
+
+    (values, def_levels, rep_levels)
+}
+
+/// Like [`list_levels`] for a list of lists, an empty inner list is defined up to the outer list
+fn nested_list_levels(lists: &[Vec<Vec<f64>>]) -> (Vec<f64>, Vec<i16>, Vec<i16>) {
+    let mut values = Vec::new();
+    let mut def_levels = Vec::new();
+    let mut rep_levels = Vec::new();
+
+    for list in lists {
+        if list.is_empty() {
+            def_levels.push(0);
+            rep_levels.push(0);
+            continue;
+        }
+
+        for (i, inner) in list.iter().enumerate() {
+            let outer_rep = if i == 0 { 0 } else { 1 };
+            if inner.is_empty() {
+                def_levels.push(1);
+                rep_levels.push(outer_rep);
+                continue;
+            }
+
+            for (j, value) in inner.iter().enumerate() {
+                values.push(*value);
+                def_levels.push(2);
+                rep_levels.push(if j == 0 { outer_rep } else { 2 });
+            }
+        }
+    }
+
+    (values, def_levels, rep_levels)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs::File};
+
+    use parquet::{
+        file::reader::{FileReader, SerializedFileReader},
+        record::RowAccessor,
+    };
+
+    use super::ParquetSink;
+    use crate::{
+        listing::Listing,
+        mods::{ModKind, NumericMod},
+        sink::ListingSink,
+    };
+
+    #[tokio::test]
+    async fn writes_readable_files() {
+        let dir = env::temp_dir().join(format!("parquet-sink-{}", std::process::id()));
+        let sink = ParquetSink::new(dir.clone()).unwrap();
+
+        let listings = [
+            Listing {
+                name: "Mageblood".to_owned(),
+                explicit_mods: vec!["+1 to Level of all Minion Skill Gems".to_owned()],
+                mods: vec![
+                    NumericMod::parse(ModKind::Explicit, "+1 to Level of all Minion Skill Gems"),
+                    NumericMod::parse(ModKind::Explicit, "Cannot be Frozen"),
+                    NumericMod::parse(ModKind::Explicit, "Adds 1-52 Lightning Damage"),
+                ],
+                ..Default::default()
+            },
+            Listing {
+                name: "Headhunter".to_owned(),
+                ..Default::default()
+            },
+        ];
+        sink.write_listings(&listings).await.unwrap();
+
+        let path = std::fs::read_dir(&dir)
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap()
+            .path();
+        let reader = SerializedFileReader::new(File::open(&path).unwrap()).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(reader.metadata().file_metadata().num_rows(), 2);
+        let names = reader
+            .get_row_iter(None)
+            .unwrap()
+            .map(|row| row.unwrap().get_string(3).unwrap().clone())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["Mageblood", "Headhunter"]);
+    }
+}
@@ -0,0 +1,171 @@
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+use async_trait::async_trait;
+use ledger_service::telemetry::{
+    nats_unready, write_counter, write_gauge, ConsumerStats, Telemetry, Throughput,
+};
+
+use crate::{db::ClickhouseDatabase, search::MeilisearchHandler};
+
+/// Metrics collected by the processor, rendered in the prometheus text format on `/metrics`
+#[derive(Default)]
+pub struct Metrics {
+    pub messages_total: AtomicU64,
+    pub bytes_total: AtomicU64,
+    pub stashes_total: AtomicU64,
+    pub listings_total: AtomicU64,
+    pub listing_events_total: AtomicU64,
+    pub duplicate_listings_total: AtomicU64,
+    pub outlier_listings_total: AtomicU64,
+    pub failed_stashes_total: AtomicU64,
+    pub failed_items_total: AtomicU64,
+    pub db_errors_total: AtomicU64,
+    pub retried_messages_total: AtomicU64,
+    pub consumer: ConsumerStats,
+    pub stashes: Throughput,
+    pub listings: Throughput,
+}
+
+impl AsRef<ConsumerStats> for Metrics {
+    fn as_ref(&self) -> &ConsumerStats {
+        &self.consumer
+    }
+}
+
+impl Metrics {
+    pub fn record_message(&self, bytes: usize, stashes: usize, listings: usize) {
+        self.messages_total.fetch_add(1, Ordering::Relaxed);
+        self.bytes_total.fetch_add(bytes as u64, Ordering::Relaxed);
+        self.stashes_total
+            .fetch_add(stashes as u64, Ordering::Relaxed);
+        self.listings_total
+            .fetch_add(listings as u64, Ordering::Relaxed);
+        self.stashes.record(stashes as u64);
+        self.listings.record(listings as u64);
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        let counters = [
+            ("stash_processor_messages_total", &self.messages_total),
+            ("stash_processor_bytes_total", &self.bytes_total),
+            ("stash_processor_stashes_total", &self.stashes_total),
+            ("stash_processor_listings_total", &self.listings_total),
+            (
+                "stash_processor_listing_events_total",
+                &self.listing_events_total,
+            ),
+            (
+                "stash_processor_duplicate_listings_total",
+                &self.duplicate_listings_total,
+            ),
+            (
+                "stash_processor_outlier_listings_total",
+                &self.outlier_listings_total,
+            ),
+            (
+                "stash_processor_failed_stashes_total",
+                &self.failed_stashes_total,
+            ),
+            (
+                "stash_processor_failed_items_total",
+                &self.failed_items_total,
+            ),
+            ("stash_processor_db_errors_total", &self.db_errors_total),
+            (
+                "stash_processor_retried_messages_total",
+                &self.retried_messages_total,
+            ),
+        ];
+        for (name, counter) in counters {
+            write_counter(&mut out, name, counter.load(Ordering::Relaxed));
+        }
+
+        let gauges = [
+            (
+                "stash_processor_stashes_per_second",
+                self.stashes.per_second(),
+            ),
+            (
+                "stash_processor_listings_per_second",
+                self.listings.per_second(),
+            ),
+            (
+                "stash_processor_consumer_pending",
+                self.consumer.pending.load(Ordering::Relaxed) as f64,
+            ),
+            (
+                "stash_processor_consumer_ack_pending",
+                self.consumer.ack_pending.load(Ordering::Relaxed) as f64,
+            ),
+        ];
+        for (name, value) in gauges {
+            write_gauge(&mut out, name, value);
+        }
+
+        out
+    }
+}
+
+/// TelemetryState is served by the processor's telemetry server
+#[derive(Clone)]
+pub struct TelemetryState {
+    pub nats: async_nats::Client,
+    pub db: ClickhouseDatabase,
+    /// only when listings are indexed for search
+    pub search: Option<MeilisearchHandler>,
+    pub metrics: Arc<Metrics>,
+}
+
+#[async_trait]
+impl Telemetry for TelemetryState {
+    fn render_metrics(&self) -> String {
+        self.metrics.render()
+    }
+
+    async fn unready(&self) -> Vec<String> {
+        let mut failures = nats_unready(&self.nats).into_iter().collect::<Vec<_>>();
+
+        if let Err(e) = self.db.ping().await {
+            failures.push(format!("clickhouse: {e}"));
+        }
+
+        if let Some(search) = &self.search {
+            if let Err(e) = search.ping().await {
+                failures.push(format!("meilisearch: {e}"));
+            }
+        }
+
+        failures
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+
+    use super::Metrics;
+
+    #[test]
+    fn messages_are_counted() {
+        let metrics = Metrics::default();
+        metrics.record_message(1024, 3, 40);
+        metrics.record_message(512, 1, 0);
+        metrics.consumer.pending.store(7, Ordering::Relaxed);
+
+        let rendered = metrics.render();
+        for line in [
+            "stash_processor_messages_total 2",
+            "stash_processor_bytes_total 1536",
+            "stash_processor_stashes_total 4",
+            "stash_processor_listings_total 40",
+            "stash_processor_consumer_pending 7",
+        ] {
+            assert!(rendered.contains(&format!("{line}\n")), "missing {line}");
+        }
+    }
+}
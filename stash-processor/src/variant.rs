@@ -0,0 +1,221 @@
+use poe_types::item::{FrameType, Item, ItemProperty};
+use poeledger_economy_data::ItemLinks;
+
+use crate::category::ItemCategory;
+
+/// Uniques whose variants are named after part of a mod, the text between a prefix and suffix
+const MOD_VARIANTS: &[(&str, &str, &str)] = &[
+    (
+        "Forbidden Flame",
+        "Allocates ",
+        " if you have the matching modifier on Forbidden Flesh",
+    ),
+    (
+        "Forbidden Flesh",
+        "Allocates ",
+        " if you have the matching modifier on Forbidden Flame",
+    ),
+    (
+        "Impossible Escape",
+        "Passives in radius of ",
+        " can be Allocated without being connected to your tree",
+    ),
+    ("Thread of Hope", "Only affects Passives in ", " Ring"),
+];
+
+/// Uniques which drop on more than one base type, each priced differently
+const BASE_TYPE_VARIANTS: &[&str] = &[
+    "Precursor's Emblem",
+    "Grand Spectrum",
+    "Combat Focus",
+    "Doryani's Delusion",
+];
+
+/// Computes the variant of an item, what sets it apart from other items with the same name, in
+/// the same terms as poe.ninja's `itemVariant` and `itemLinks`.
+///
+/// Variants are made of the unique variant, 5 or 6 links, gem `level/quality` with a `c` for
+/// corrupted gems and `relic`, joined by `, `, e.g. `Juggernaut, 6 links` or `21/23c`. Items
+/// without any of these have an empty variant.
+pub fn item_variant(item: &Item, category: ItemCategory) -> String {
+    let mut parts = Vec::new();
+
+    match category {
+        ItemCategory::Unique => {
+            parts.extend(unique_variant(item));
+            parts.extend(item_links(item).and_then(|links| match links {
+                ItemLinks::OneToFour => None,
+                links => Some(links.to_string()),
+            }));
+
+            let is_relic = item.is_relic.unwrap_or(false)
+                || matches!(
+                    item.frame_type,
+                    Some(FrameType::Foil | FrameType::SupporterFoil)
+                );
+            if is_relic {
+                parts.push("relic".to_owned());
+            }
+        }
+        ItemCategory::Gem => parts.extend(gem_variant(item)),
+        _ => {}
+    }
+
+    parts.join(", ")
+}
+
+/// Links of the largest socket group, None for items without sockets
+pub fn item_links(item: &Item) -> Option<ItemLinks> {
+    let sockets = item.sockets.as_ref().filter(|s| !s.is_empty())?;
+
+    let largest = sockets
+        .iter()
+        .map(|s| sockets.iter().filter(|o| o.group == s.group).count())
+        .max()
+        .unwrap_or_default();
+
+    Some(match largest {
+        6.. => ItemLinks::Six,
+        5 => ItemLinks::Five,
+        _ => ItemLinks::OneToFour,
+    })
+}
+
+fn unique_variant(item: &Item) -> Option<String> {
+    if BASE_TYPE_VARIANTS.contains(&item.name.as_str()) {
+        return Some(item.base_type.clone());
+    }
+
+    let (_, prefix, suffix) = MOD_VARIANTS.iter().find(|(name, ..)| *name == item.name)?;
+    item.explicit_mods
+        .iter()
+        .flatten()
+        .find_map(|m| m.strip_prefix(prefix)?.strip_suffix(suffix))
+        .map(|v| v.to_owned())
+}
+
+fn gem_variant(item: &Item) -> Option<String> {
+    let properties = item.properties.as_deref().unwrap_or_default();
+    let level = property_number(properties, "Level")?;
+    let quality = property_number(properties, "Quality").unwrap_or(0);
+
+    let mut variant = match quality {
+        0 => level.to_string(),
+        q => format!("{level}/{q}"),
+    };
+    if item.corrupted.unwrap_or(false) {
+        variant.push('c');
+    }
+
+    Some(variant)
+}
+
+/// The leading number of a property's first value, e.g. 20 for a level of `20 (Max)` or 23 for a
+/// quality of `+23%`
+fn property_number(properties: &[ItemProperty], name: &str) -> Option<u32> {
+    let (value, _) = properties.iter().find(|p| p.name == name)?.values.first()?;
+
+    let digits = value
+        .trim_start_matches('+')
+        .chars()
+        .take_while(|c| c.is_ascii_digit())
+        .collect::<String>();
+
+    digits.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use poe_types::item::{FrameType, Item, ItemProperty, ItemSocket};
+
+    use super::item_variant;
+    use crate::category::ItemCategory;
+
+    fn sockets(groups: &[usize]) -> Option<Vec<ItemSocket>> {
+        Some(
+            groups
+                .iter()
+                .map(|&group| ItemSocket {
+                    group,
+                    ..Default::default()
+                })
+                .collect(),
+        )
+    }
+
+    fn property(name: &str, value: &str) -> ItemProperty {
+        ItemProperty {
+            name: name.to_owned(),
+            values: vec![(value.to_owned(), 0)],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn unique_variants() {
+        let wrappings = Item {
+            name: "Shavronne's Wrappings".to_owned(),
+            base_type: "Occultist's Vestment".to_owned(),
+            sockets: sockets(&[0, 0, 0, 0, 0, 0]),
+            ..Default::default()
+        };
+        assert_eq!(item_variant(&wrappings, ItemCategory::Unique), "6 links");
+
+        let four_link = Item {
+            sockets: sockets(&[0, 0, 0, 0, 1, 1]),
+            ..wrappings.clone()
+        };
+        assert_eq!(item_variant(&four_link, ItemCategory::Unique), "");
+
+        let flame = Item {
+            name: "Forbidden Flame".to_owned(),
+            base_type: "Crimson Jewel".to_owned(),
+            explicit_mods: Some(vec![
+                "Allocates Unstoppable Hero if you have the matching modifier on Forbidden Flesh"
+                    .to_owned(),
+            ]),
+            frame_type: Some(FrameType::Foil),
+            ..Default::default()
+        };
+        assert_eq!(
+            item_variant(&flame, ItemCategory::Unique),
+            "Unstoppable Hero, relic"
+        );
+
+        let emblem = Item {
+            name: "Precursor's Emblem".to_owned(),
+            base_type: "Topaz Ring".to_owned(),
+            ..Default::default()
+        };
+        assert_eq!(item_variant(&emblem, ItemCategory::Unique), "Topaz Ring");
+    }
+
+    #[test]
+    fn gem_variants() {
+        let gem = |level: &str, quality: Option<&str>, corrupted| Item {
+            base_type: "Enlighten Support".to_owned(),
+            properties: Some(
+                std::iter::once(property("Level", level))
+                    .chain(quality.map(|q| property("Quality", q)))
+                    .collect(),
+            ),
+            corrupted: Some(corrupted),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            item_variant(&gem("21", Some("+23%"), true), ItemCategory::Gem),
+            "21/23c"
+        );
+        assert_eq!(
+            item_variant(&gem("20 (Max)", Some("+20%"), false), ItemCategory::Gem),
+            "20/20"
+        );
+        assert_eq!(item_variant(&gem("1", None, false), ItemCategory::Gem), "1");
+        // only gems and uniques have variants
+        assert_eq!(
+            item_variant(&gem("1", None, true), ItemCategory::Currency),
+            ""
+        );
+    }
+}